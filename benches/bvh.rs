@@ -0,0 +1,199 @@
+//! Compares spatial queries through the scene `Bvh` against testing every
+//! entity. Each bench first checks that both give the same answer.
+//!
+//! Run with `cargo bench` on a nightly toolchain.
+#![feature(test)]
+
+extern crate cgmath;
+extern crate changeme;
+extern crate test;
+
+use cgmath::{Deg, Matrix4, Point3, Vector3};
+use changeme::scene::{transform, Aabb, Bounds, Bvh, Entity, Frustum, Ray, Scene, Transform, WorldTransform};
+use test::Bencher;
+
+const ENTITIES: usize = 10000;
+/// Half the size of the cube the entities are scattered in.
+const SPREAD: f32 = 100.0;
+
+/// Xorshift, so every run scatters the entities the same way.
+struct Rng(u32);
+
+impl Rng {
+    /// Uniform in `-1..1`.
+    fn next(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        (self.0 as f32 / ::std::u32::MAX as f32) * 2.0 - 1.0
+    }
+
+    fn point(&mut self, scale: f32) -> Vector3<f32> {
+        Vector3::new(self.next(), self.next(), self.next()) * scale
+    }
+}
+
+fn scattered_scene(rng: &mut Rng) -> Scene {
+    let mut scene = Scene::new();
+    for _ in 0..ENTITIES {
+        let entity = scene.spawn();
+        let mut transform = Transform::new();
+        transform.set_translation(rng.point(SPREAD));
+        scene.insert(entity, transform);
+        scene.insert(entity, Bounds(Aabb::new(Point3::new(-0.5, -0.5, -0.5), Point3::new(0.5, 0.5, 0.5))));
+    }
+    transform::propagate(&mut scene);
+    scene
+}
+
+/// Every entity with its world box, as the brute force queries see them.
+fn world_boxes(scene: &Scene) -> Vec<(Entity, Aabb)> {
+    scene.iter::<Bounds>()
+         .filter_map(|(entity, bounds)| scene.get::<WorldTransform>(entity).map(|world| (entity, bounds.0.transform(&world.0))))
+         .collect()
+}
+
+fn setup() -> (Scene, Bvh, Vec<(Entity, Aabb)>) {
+    let scene = scattered_scene(&mut Rng(0x2545f491));
+    let mut bvh = Bvh::new(0.1);
+    bvh.sync(&scene);
+    let boxes = world_boxes(&scene);
+    assert_eq!(bvh.len(), boxes.len());
+    (scene, bvh, boxes)
+}
+
+fn sorted(mut entities: Vec<Entity>) -> Vec<Entity> {
+    entities.sort();
+    entities
+}
+
+fn query_box() -> Aabb {
+    Aabb::new(Point3::new(-20.0, -20.0, -20.0), Point3::new(20.0, 20.0, 20.0))
+}
+
+fn query_frustum() -> Frustum {
+    let view = Matrix4::look_at(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 0.2, 0.5), Vector3::new(0.0, 1.0, 0.0));
+    Frustum::from_matrix(&(cgmath::perspective(Deg(60.0), 1.5, 0.1, 80.0) * view))
+}
+
+fn query_ray() -> Ray {
+    Ray::new(Point3::new(-SPREAD, 0.3, 0.2), Vector3::new(1.0, 0.01, -0.02))
+}
+
+fn brute_aabb(boxes: &[(Entity, Aabb)], aabb: &Aabb) -> Vec<Entity> {
+    boxes.iter().filter(|&&(_, ref b)| b.overlaps(aabb)).map(|&(entity, _)| entity).collect()
+}
+
+fn brute_frustum(boxes: &[(Entity, Aabb)], frustum: &Frustum) -> Vec<Entity> {
+    boxes.iter().filter(|&&(_, ref b)| frustum.intersects_aabb(b)).map(|&(entity, _)| entity).collect()
+}
+
+fn brute_ray(boxes: &[(Entity, Aabb)], ray: &Ray) -> Vec<(Entity, f32)> {
+    let mut hits = boxes.iter()
+                        .filter_map(|&(entity, ref b)| b.ray_distance(ray, 1000.0).map(|distance| (entity, distance)))
+                        .collect::<Vec<_>>();
+    hits.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+    hits
+}
+
+#[bench]
+fn bvh_aabb(b: &mut Bencher) {
+    let (_, bvh, boxes) = setup();
+    let aabb = query_box();
+    let mut found = Vec::new();
+    bvh.query_aabb(&aabb, &mut found);
+    assert_eq!(sorted(found), sorted(brute_aabb(&boxes, &aabb)));
+
+    b.iter(|| {
+        let mut found = Vec::new();
+        bvh.query_aabb(&aabb, &mut found);
+        found
+    });
+}
+
+#[bench]
+fn brute_force_aabb(b: &mut Bencher) {
+    let (_, _, boxes) = setup();
+    let aabb = query_box();
+    b.iter(|| brute_aabb(&boxes, &aabb));
+}
+
+#[bench]
+fn bvh_sphere(b: &mut Bencher) {
+    let (_, bvh, boxes) = setup();
+    let center = Point3::new(10.0, -5.0, 3.0);
+    let mut found = Vec::new();
+    bvh.query_sphere(center, 25.0, &mut found);
+    let brute = boxes.iter().filter(|&&(_, ref b)| b.overlaps_sphere(center, 25.0)).map(|&(entity, _)| entity).collect();
+    assert_eq!(sorted(found), sorted(brute));
+
+    b.iter(|| {
+        let mut found = Vec::new();
+        bvh.query_sphere(center, 25.0, &mut found);
+        found
+    });
+}
+
+#[bench]
+fn bvh_frustum(b: &mut Bencher) {
+    let (_, bvh, boxes) = setup();
+    let frustum = query_frustum();
+    let mut found = Vec::new();
+    bvh.query_frustum(&frustum, &mut found);
+    assert_eq!(sorted(found), sorted(brute_frustum(&boxes, &frustum)));
+
+    b.iter(|| {
+        let mut found = Vec::new();
+        bvh.query_frustum(&frustum, &mut found);
+        found
+    });
+}
+
+#[bench]
+fn brute_force_frustum(b: &mut Bencher) {
+    let (_, _, boxes) = setup();
+    let frustum = query_frustum();
+    b.iter(|| brute_frustum(&boxes, &frustum));
+}
+
+#[bench]
+fn bvh_ray(b: &mut Bencher) {
+    let (_, bvh, boxes) = setup();
+    let ray = query_ray();
+    assert_eq!(bvh.raycast(&ray, 1000.0), brute_ray(&boxes, &ray));
+
+    b.iter(|| bvh.raycast(&ray, 1000.0));
+}
+
+#[bench]
+fn brute_force_ray(b: &mut Bencher) {
+    let (_, _, boxes) = setup();
+    let ray = query_ray();
+    b.iter(|| brute_ray(&boxes, &ray));
+}
+
+/// Moves a tenth of the entities a little, then a few of them far enough to
+/// escape their fattened boxes, and syncs the tree.
+#[bench]
+fn bvh_sync(b: &mut Bencher) {
+    let (mut scene, mut bvh, _) = setup();
+    let mut rng = Rng(0x9e3779b9);
+    let entities = scene.entities().collect::<Vec<_>>();
+
+    b.iter(|| {
+        for (i, &entity) in entities.iter().enumerate().filter(|&(i, _)| i % 10 == 0) {
+            let offset = rng.point(if i % 100 == 0 { 5.0 } else { 0.05 });
+            if let Some(transform) = scene.get_mut::<Transform>(entity) {
+                let translation = transform.translation() + offset;
+                transform.set_translation(translation);
+            }
+        }
+        transform::propagate(&mut scene);
+        bvh.sync(&scene);
+    });
+
+    let aabb = query_box();
+    let mut found = Vec::new();
+    bvh.query_aabb(&aabb, &mut found);
+    assert_eq!(sorted(found), sorted(brute_aabb(&world_boxes(&scene), &aabb)));
+}
@@ -0,0 +1,120 @@
+//! Compares query iteration over the archetype tables of `Scene` against a
+//! naive layout where every entity owns a map of boxed components.
+//!
+//! Run with `cargo bench` on a nightly toolchain.
+#![feature(test)]
+
+extern crate changeme;
+extern crate test;
+
+use changeme::scene::Scene;
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use test::Bencher;
+
+const ENTITIES: usize = 10000;
+
+#[derive(Copy, Clone)]
+struct Position([f32; 3]);
+#[derive(Copy, Clone)]
+struct Velocity([f32; 3]);
+#[derive(Copy, Clone)]
+struct Health(u32);
+/// Bulky component that only some entities carry, to spread them over
+/// several archetypes.
+#[derive(Copy, Clone)]
+struct Blob([u64; 16]);
+
+/// The layout we are trying to beat: a list of entities, each owning a map
+/// of boxed components scattered over the heap.
+struct NaiveEntity {
+    components: HashMap<TypeId, Box<Any>>,
+}
+
+impl NaiveEntity {
+    fn get<T: Any>(&self) -> Option<&T> {
+        self.components.get(&TypeId::of::<T>()).and_then(|c| c.downcast_ref::<T>())
+    }
+
+    fn get_mut<T: Any>(&mut self) -> Option<&mut T> {
+        self.components.get_mut(&TypeId::of::<T>()).and_then(|c| c.downcast_mut::<T>())
+    }
+}
+
+fn archetype_scene() -> Scene {
+    let mut scene = Scene::new();
+    for i in 0..ENTITIES {
+        let entity = scene.spawn();
+        scene.insert(entity, Position([i as f32, 0.0, 0.0]));
+        scene.insert(entity, Velocity([1.0, 2.0, 3.0]));
+        if i % 2 == 0 {
+            scene.insert(entity, Health(100));
+        }
+        if i % 3 == 0 {
+            scene.insert(entity, Blob([0; 16]));
+        }
+    }
+    scene
+}
+
+fn naive_scene() -> Vec<NaiveEntity> {
+    (0..ENTITIES).map(|i| {
+        let mut components: HashMap<TypeId, Box<Any>> = HashMap::new();
+        components.insert(TypeId::of::<Position>(), Box::new(Position([i as f32, 0.0, 0.0])));
+        components.insert(TypeId::of::<Velocity>(), Box::new(Velocity([1.0, 2.0, 3.0])));
+        if i % 2 == 0 {
+            components.insert(TypeId::of::<Health>(), Box::new(Health(100)));
+        }
+        if i % 3 == 0 {
+            components.insert(TypeId::of::<Blob>(), Box::new(Blob([0; 16])));
+        }
+        NaiveEntity { components: components }
+    }).collect()
+}
+
+#[bench]
+fn archetype_integrate(b: &mut Bencher) {
+    let mut scene = archetype_scene();
+    b.iter(|| {
+        for (_, (position, velocity)) in scene.query::<(&mut Position, &Velocity)>() {
+            for i in 0..3 {
+                position.0[i] += velocity.0[i];
+            }
+        }
+    });
+}
+
+#[bench]
+fn naive_integrate(b: &mut Bencher) {
+    let mut entities = naive_scene();
+    b.iter(|| {
+        for entity in entities.iter_mut() {
+            let velocity = match entity.get::<Velocity>() {
+                Some(velocity) => *velocity,
+                None => continue,
+            };
+            if let Some(position) = entity.get_mut::<Position>() {
+                for i in 0..3 {
+                    position.0[i] += velocity.0[i];
+                }
+            }
+        }
+    });
+}
+
+#[bench]
+fn archetype_sparse_sum(b: &mut Bencher) {
+    let mut scene = archetype_scene();
+    b.iter(|| {
+        scene.query::<&Health>().fold(0u64, |sum, (_, health)| sum + health.0 as u64)
+    });
+}
+
+#[bench]
+fn naive_sparse_sum(b: &mut Bencher) {
+    let entities = naive_scene();
+    b.iter(|| {
+        entities.iter().filter_map(|entity| entity.get::<Health>())
+                       .fold(0u64, |sum, health| sum + health.0 as u64)
+    });
+}
@@ -0,0 +1,254 @@
+//! Derives for the `changeme` crate.
+
+extern crate proc_macro;
+extern crate proc_macro2;
+#[macro_use]
+extern crate quote;
+extern crate syn;
+
+use proc_macro::TokenStream;
+use syn::{Data, DeriveInput, Fields, GenericArgument, LitStr, PathArguments, Type};
+
+/// Implements `vulkano::pipeline::vertex::Vertex`, describing where each
+/// shader input lives in the struct.
+///
+/// Every field is one input, named after the field or after
+/// `#[vertex(name = "...")]`, which must match the `in` variable of the
+/// vertex shader. Newtypes name their only field on the struct itself:
+///
+/// ```ignore
+/// #[derive(Copy, Clone, VertexLayout)]
+/// #[vertex(name = "position")]
+/// pub struct Vertex(pub Vector3<f32>);
+///
+/// #[derive(Copy, Clone, VertexLayout)]
+/// pub struct Colored {
+///     position: [f32; 3],
+///     #[vertex(name = "color")]
+///     rgba: Vector4<f32>,
+/// }
+/// ```
+///
+/// cgmath vectors and points are passed as a whole, so a `Vector3<f32>`
+/// field feeds a `vec3` input. Any other field type must implement
+/// `vulkano::pipeline::vertex::VertexMember`.
+#[proc_macro_derive(VertexLayout, attributes(vertex))]
+pub fn derive_vertex_layout(input: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(input as DeriveInput);
+    match vertex_layout(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn vertex_layout(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let fields = match input.data {
+        Data::Struct(ref data) => &data.fields,
+        _ => return Err(syn::Error::new_spanned(input, "VertexLayout can only be derived for structs")),
+    };
+    let struct_name = attribute_name(&input.attrs)?;
+
+    let mut members = Vec::new();
+    match *fields {
+        Fields::Named(ref named) => {
+            if struct_name.is_some() {
+                return Err(syn::Error::new_spanned(input, "#[vertex(name)] belongs on the fields of structs with named fields"));
+            }
+            for field in named.named.iter() {
+                let ident = field.ident.clone().expect("named field without a name");
+                let name = attribute_name(&field.attrs)?.unwrap_or_else(|| ident.to_string());
+                members.push((name, quote!(#ident), &field.ty));
+            }
+        },
+        Fields::Unnamed(ref unnamed) => {
+            for (i, field) in unnamed.unnamed.iter().enumerate() {
+                let name = match (attribute_name(&field.attrs)?, unnamed.unnamed.len()) {
+                    (Some(name), _) => name,
+                    (None, 1) => match struct_name.clone() {
+                        Some(name) => name,
+                        None => return Err(syn::Error::new_spanned(input, "newtypes need #[vertex(name = \"...\")]")),
+                    },
+                    (None, _) => return Err(syn::Error::new_spanned(field, "tuple struct fields need #[vertex(name = \"...\")]")),
+                };
+                let index = syn::Index::from(i);
+                members.push((name, quote!(#index), &field.ty));
+            }
+        },
+        Fields::Unit => return Err(syn::Error::new_spanned(input, "VertexLayout needs at least one field")),
+    }
+
+    for (i, &(ref name, _, ty)) in members.iter().enumerate() {
+        if members[..i].iter().any(|&(ref other, _, _)| other == name) {
+            return Err(syn::Error::new_spanned(ty, format!("vertex input '{}' is declared twice", name)));
+        }
+    }
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let arms = members.iter().map(|&(ref name, ref member, ty)| {
+        let format = member_type(ty);
+        quote! {
+            #name => {
+                debug_assert_eq!(::std::mem::size_of::<#ty>(), ::std::mem::size_of::<#format>());
+                let dummy = ::std::mem::MaybeUninit::<#ident #ty_generics>::uninit();
+                let base = dummy.as_ptr();
+                let offset = unsafe { ::std::ptr::addr_of!((*base).#member) as usize - base as usize };
+                let (ty, array_size) = <#format as VertexMember>::format();
+                Some(VertexMemberInfo {
+                    offset: offset,
+                    ty: ty,
+                    array_size: array_size,
+                })
+            },
+        }
+    });
+
+    Ok(quote! {
+        unsafe impl #impl_generics ::vulkano::pipeline::vertex::Vertex for #ident #ty_generics #where_clause {
+            #[inline(always)]
+            fn member(name: &str) -> Option<::vulkano::pipeline::vertex::VertexMemberInfo> {
+                use ::vulkano::pipeline::vertex::{VertexMember, VertexMemberInfo};
+
+                match name {
+                    #(#arms)*
+                    _ => None,
+                }
+            }
+        }
+    })
+}
+
+/// The value of `#[vertex(name = "...")]` among the attributes, if any.
+fn attribute_name(attrs: &[syn::Attribute]) -> syn::Result<Option<String>> {
+    let mut name = None;
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("vertex")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("name") {
+                name = Some(meta.value()?.parse::<LitStr>()?.value());
+                Ok(())
+            } else {
+                Err(meta.error("unknown vertex attribute, expected `name`"))
+            }
+        })?;
+    }
+    Ok(name)
+}
+
+/// The type vulkano sees for a field: cgmath vectors and points become
+/// arrays of their components, which have the same layout.
+fn member_type(ty: &Type) -> proc_macro2::TokenStream {
+    let segment = match *ty {
+        Type::Path(ref path) if path.qself.is_none() => path.path.segments.last(),
+        _ => None,
+    };
+    let segment = match segment {
+        Some(segment) => segment,
+        None => return quote!(#ty),
+    };
+
+    let count = match &*segment.ident.to_string() {
+        "Vector1" => 1,
+        "Vector2" | "Point2" => 2,
+        "Vector3" | "Point3" => 3,
+        "Vector4" => 4,
+        _ => return quote!(#ty),
+    };
+    let scalar = match segment.arguments {
+        PathArguments::AngleBracketed(ref args) if args.args.len() == 1 => match args.args[0] {
+            GenericArgument::Type(ref scalar) => scalar.clone(),
+            _ => return quote!(#ty),
+        },
+        _ => return quote!(#ty),
+    };
+    let count = proc_macro2::Literal::usize_unsuffixed(count);
+    quote!([#scalar; #count])
+}
+
+#[cfg(test)]
+mod tests {
+    use proc_macro2::{Span, TokenStream};
+    use std::mem::{self, MaybeUninit};
+    use syn::{Data, DeriveInput, Ident};
+
+    /// Defines a `#[repr(C)]` copy of `core::Vertex` holding the types
+    /// vulkano sees, and returns its fields as source along with each one's
+    /// offset, measured both like the generated `member` and with
+    /// `offset_of!`.
+    macro_rules! mirror {
+        ($($field:ident: $ty:ty,)*) => {
+            #[repr(C)]
+            #[allow(dead_code)]
+            struct Mirror { $($field: $ty,)* }
+
+            fn mirror() -> (&'static str, Vec<(&'static str, usize, usize)>) {
+                let dummy = MaybeUninit::<Mirror>::uninit();
+                let base = dummy.as_ptr();
+                let offsets = vec![$((
+                    stringify!($field),
+                    unsafe { ::std::ptr::addr_of!((*base).$field) as usize - base as usize },
+                    mem::offset_of!(Mirror, $field),
+                ),)*];
+                (stringify!($($field: $ty,)*), offsets)
+            }
+        }
+    }
+
+    mirror! {
+        position: [f32; 3],
+        normal: [f32; 3],
+        uv: [f32; 2],
+        tangent: [f32; 4],
+        color: [f32; 4],
+    }
+
+    /// `core::Vertex` as written in the main crate.
+    fn core_vertex() -> DeriveInput {
+        let source = include_str!("../../src/core/mod.rs");
+        let start = source.find("pub struct Vertex {").expect("The core module has no Vertex");
+        let end = start + source[start..].find("\n}").expect("Vertex is not closed") + 2;
+        syn::parse_str(&source[start..end]).expect("Vertex doesn't parse")
+    }
+
+    fn fields(vertex: &DeriveInput) -> &syn::Fields {
+        match vertex.data {
+            Data::Struct(ref data) => &data.fields,
+            _ => unreachable!(),
+        }
+    }
+
+    fn normalize(source: &str) -> String {
+        source.parse::<TokenStream>().expect("Not valid tokens").to_string()
+    }
+
+    #[test]
+    fn mirror_matches_core_vertex() {
+        let vertex = core_vertex();
+        let fields = fields(&vertex).iter().map(|field| {
+            let ident = field.ident.as_ref().expect("Vertex has unnamed fields");
+            let ty = super::member_type(&field.ty);
+            quote!(#ident: #ty,).to_string()
+        }).collect::<Vec<_>>().join(" ");
+        let (source, _) = mirror();
+        assert_eq!(normalize(&fields), normalize(source));
+    }
+
+    #[test]
+    fn member_offsets_match_field_offsets() {
+        let vertex = core_vertex();
+        let generated = super::vertex_layout(&vertex).expect("VertexLayout rejects core::Vertex").to_string();
+
+        let (_, offsets) = mirror();
+        assert_eq!(offsets.len(), fields(&vertex).len());
+        for &(field, measured, expected) in offsets.iter() {
+            // Each input must be measured on the field of the same name.
+            let ident = Ident::new(field, Span::call_site());
+            let arm = quote!(#field =>).to_string();
+            let lookup = quote!(::std::ptr::addr_of!((*base).#ident)).to_string();
+            let body = generated.split(&arm).nth(1).unwrap_or_else(|| panic!("No member for '{}'", field));
+            let body = body.split("VertexMemberInfo {").next().unwrap();
+            assert!(body.contains(&lookup), "'{}' isn't measured on its own field", field);
+
+            assert_eq!(measured, expected, "Offset of '{}'", field);
+        }
+    }
+}
@@ -0,0 +1,226 @@
+use ::std::path::PathBuf;
+
+error_chain! {
+    errors {
+        UnknownArgument(arg: String) {
+            description("unknown argument")
+            display("unknown argument '{}'", arg)
+        }
+        MissingValue(flag: String) {
+            description("missing value")
+            display("{} needs a value", flag)
+        }
+        UnexpectedValue(flag: String) {
+            description("unexpected value")
+            display("{} does not take a value", flag)
+        }
+        InvalidValue(flag: String, value: String, expected: &'static str) {
+            description("invalid value")
+            display("invalid value '{}' for {}, expected {}", value, flag, expected)
+        }
+        Conflict(first: String, second: String) {
+            description("conflicting arguments")
+            display("{} cannot be used with {}", first, second)
+        }
+    }
+}
+
+pub static USAGE: &'static str = "\
+Usage: changeme [options]
+
+Options:
+    --config <path>         Config file to use instead of the one in the config directory
+    --set <key>=<value>     Override a config setting, e.g. --set window.title=changeme
+    --model <path>          COLLADA model to load
+    --scene <path>          Scene file to load instead of building one around the model
+    --width <pixels>        Window width
+    --height <pixels>       Window height
+    --fullscreen            Open the window fullscreen
+    --headless              Run the simulation without a window
+    --frames <n>            Exit after <n> frames
+    --log-level <level>     One of off, error, warn, info, debug or trace
+    --seed <n>              Seed of the random number generator
+    --record <path>         Record the input of every tick to a file
+    --replay <path>         Play back a recording instead of taking input
+    --verify <path>         Play back a recording, checking the scene matches it every tick
+    -h, --help              Print this help
+    -V, --version           Print the version
+";
+
+/// What the command line asks for.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    Run(Args),
+    Help,
+    Version,
+}
+
+/// Options of a run.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Args {
+    /// Config file given with `--config`.
+    pub config: Option<PathBuf>,
+    /// Config overrides in the order given, from `--set` and the flags that
+    /// are shorthands for a setting.
+    pub settings: Vec<(String, String)>,
+    pub headless: bool,
+    pub frames: Option<u64>,
+    pub seed: Option<u64>,
+    pub record: Option<PathBuf>,
+    /// Recording to play back, and whether to verify it.
+    pub replay: Option<(PathBuf, bool)>,
+}
+
+/// Parses the arguments, without the program name. Parsing stops at
+/// `--help` or `--version`.
+pub fn parse<I>(args: I) -> Result<Command> where I: IntoIterator<Item = String> {
+    let mut parsed = Args::default();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        // Accept both `--flag value` and `--flag=value`.
+        let (flag, inline) = match arg.find('=') {
+            Some(i) if arg.starts_with("--") => (arg[..i].to_owned(), Some(arg[i + 1..].to_owned())),
+            _ => (arg.clone(), None),
+        };
+
+        match &*flag {
+            "-h" | "--help" | "-V" | "--version" => {
+                if inline.is_some() {
+                    bail!(ErrorKind::UnexpectedValue(flag));
+                }
+                return Ok(if flag == "-h" || flag == "--help" { Command::Help } else { Command::Version });
+            },
+            "--fullscreen" | "--headless" => {
+                if inline.is_some() {
+                    bail!(ErrorKind::UnexpectedValue(flag));
+                }
+                if flag == "--fullscreen" {
+                    parsed.settings.push(("window.fullscreen".to_owned(), "true".to_owned()));
+                } else {
+                    parsed.headless = true;
+                }
+            },
+            "--config" | "--set" | "--model" | "--scene" | "--width" | "--height" | "--frames" | "--log-level" |
+            "--seed" | "--record" | "--replay" | "--verify" => {
+                let value = match inline.or_else(|| args.next()) {
+                    Some(value) => value,
+                    None => bail!(ErrorKind::MissingValue(flag)),
+                };
+                match &*flag {
+                    "--config" => parsed.config = Some(PathBuf::from(value)),
+                    "--set" => {
+                        let setting = match value.find('=') {
+                            Some(i) if i > 0 => (value[..i].to_owned(), value[i + 1..].to_owned()),
+                            _ => bail!(ErrorKind::InvalidValue(flag, value, "<key>=<value>")),
+                        };
+                        parsed.settings.push(setting);
+                    },
+                    "--model" => parsed.settings.push(("assets.model".to_owned(), value)),
+                    "--scene" => parsed.settings.push(("assets.scene".to_owned(), value)),
+                    "--width" | "--height" => {
+                        match value.parse::<u32>() {
+                            Ok(size) if size > 0 => (),
+                            _ => bail!(ErrorKind::InvalidValue(flag, value, "a positive number of pixels")),
+                        }
+                        let key = if flag == "--width" { "window.width" } else { "window.height" };
+                        parsed.settings.push((key.to_owned(), value));
+                    },
+                    "--frames" => match value.parse() {
+                        Ok(frames) => parsed.frames = Some(frames),
+                        Err(_) => bail!(ErrorKind::InvalidValue(flag, value, "a number of frames")),
+                    },
+                    "--log-level" => parsed.settings.push(("log.level".to_owned(), value)),
+                    "--seed" => match value.parse() {
+                        Ok(seed) => parsed.seed = Some(seed),
+                        Err(_) => bail!(ErrorKind::InvalidValue(flag, value, "a number")),
+                    },
+                    "--record" => parsed.record = Some(PathBuf::from(value)),
+                    "--replay" => parsed.replay = Some((PathBuf::from(value), false)),
+                    "--verify" => parsed.replay = Some((PathBuf::from(value), true)),
+                    _ => unreachable!(),
+                }
+            },
+            _ => bail!(ErrorKind::UnknownArgument(arg)),
+        }
+    }
+
+    // A replay brings its own seed and input.
+    if parsed.replay.is_some() {
+        if parsed.record.is_some() {
+            bail!(ErrorKind::Conflict("--record".to_owned(), "--replay or --verify".to_owned()));
+        }
+        if parsed.seed.is_some() {
+            bail!(ErrorKind::Conflict("--seed".to_owned(), "--replay or --verify".to_owned()));
+        }
+    }
+    Ok(Command::Run(parsed))
+}
+
+#[cfg(test)]
+mod tests {
+    use ::std::path::PathBuf;
+    use super::{parse, Args, Command, ErrorKind, Result};
+
+    fn run(args: &[&str]) -> Result<Command> {
+        parse(args.iter().map(|&arg| arg.to_owned()))
+    }
+
+    fn args(args: &[&str]) -> Args {
+        match run(args).unwrap() {
+            Command::Run(args) => args,
+            command => panic!("unexpected command {:?}", command),
+        }
+    }
+
+    fn setting(key: &str, value: &str) -> (String, String) {
+        (key.to_owned(), value.to_owned())
+    }
+
+    #[test]
+    fn flags_become_settings_in_order() {
+        let parsed = args(&["--width", "800", "--set=window.title=a=b", "--fullscreen", "--model=monkey.dae",
+                            "--log-level", "info", "--set", "window.width=640"]);
+        assert_eq!(parsed.settings, vec![setting("window.width", "800"), setting("window.title", "a=b"),
+                                         setting("window.fullscreen", "true"), setting("assets.model", "monkey.dae"),
+                                         setting("log.level", "info"), setting("window.width", "640")]);
+        assert!(!parsed.headless);
+
+        let parsed = args(&["--headless", "--frames", "10", "--seed=7", "--record", "out.json", "--config", "my.toml"]);
+        assert_eq!(parsed, Args {
+            config: Some(PathBuf::from("my.toml")),
+            settings: Vec::new(),
+            headless: true,
+            frames: Some(10),
+            seed: Some(7),
+            record: Some(PathBuf::from("out.json")),
+            replay: None,
+        });
+        assert_eq!(args(&["--verify", "in.json"]).replay, Some((PathBuf::from("in.json"), true)));
+        assert_eq!(args(&[]), Args::default());
+    }
+
+    #[test]
+    fn help_and_version_stop_parsing() {
+        assert_eq!(run(&["--help", "--bogus"]).unwrap(), Command::Help);
+        assert_eq!(run(&["--width", "800", "-V"]).unwrap(), Command::Version);
+        assert!(run(&["--bogus", "--help"]).is_err());
+    }
+
+    #[test]
+    fn bad_arguments_are_reported() {
+        let message = |args: &[&str]| run(args).unwrap_err().to_string();
+        assert_eq!(message(&["--bogus"]), "unknown argument '--bogus'");
+        assert_eq!(message(&["model.dae"]), "unknown argument 'model.dae'");
+        assert_eq!(message(&["--width"]), "--width needs a value");
+        assert_eq!(message(&["--headless=yes"]), "--headless does not take a value");
+        assert_eq!(message(&["--help=me"]), "--help does not take a value");
+        assert_eq!(message(&["--width", "0"]), "invalid value '0' for --width, expected a positive number of pixels");
+        assert_eq!(message(&["--frames", "-1"]), "invalid value '-1' for --frames, expected a number of frames");
+        assert_eq!(message(&["--set", "=1"]), "invalid value '=1' for --set, expected <key>=<value>");
+        match *run(&["--replay", "in.json", "--seed", "1"]).unwrap_err().kind() {
+            ErrorKind::Conflict(ref first, _) => assert_eq!(first, "--seed"),
+            ref kind => panic!("unexpected error {:?}", kind),
+        }
+        assert!(run(&["--record", "out.json", "--verify", "in.json"]).is_err());
+    }
+}
@@ -0,0 +1,318 @@
+use ::log::LogLevelFilter;
+use ::serde_json::{self, Value};
+use ::std::env;
+use ::std::fs::File;
+use ::std::io::Read;
+use ::std::path::{Path, PathBuf};
+
+/// Prefix of the environment variables that override settings, e.g.
+/// `CHANGEME_WINDOW_WIDTH=800` sets `window.width`.
+pub const ENV_PREFIX: &'static str = "CHANGEME_";
+
+/// Environment variable pointing at a config file to use instead of the
+/// one in the config directory.
+pub const ENV_CONFIG: &'static str = "CHANGEME_CONFIG";
+
+error_chain! {
+    foreign_links {
+        Io(::std::io::Error);
+    }
+
+    errors {
+        Parse(path: PathBuf, message: String) {
+            description("could not parse config file")
+            display("could not parse config file '{}': {}", path.display(), message)
+        }
+        UnknownSetting(key: String) {
+            description("unknown setting")
+            display("unknown setting '{}'", key)
+        }
+        InvalidSetting(key: String, message: String) {
+            description("invalid setting")
+            display("invalid value for '{}': {}", key, message)
+        }
+    }
+}
+
+/// All settings of the game. Every field has a default, so a config file
+/// only needs the settings it changes.
+///
+/// Settings are layered, each layer overriding the one before:
+///
+/// 1. The defaults.
+/// 2. `config.toml` in the config directory, see `path`.
+/// 3. `CHANGEME_<SECTION>_<KEY>` environment variables.
+/// 4. Command-line flags, applied with `set`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub assets: Assets,
+    pub window: Window,
+    pub graphics: Graphics,
+    pub input: Input,
+    pub log: Log,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Assets {
+    /// Collada model to load.
+    pub model: PathBuf,
+    /// Scene file to load instead of building one around the model.
+    pub scene: Option<PathBuf>,
+}
+
+impl Default for Assets {
+    fn default() -> Assets {
+        Assets {
+            model: PathBuf::from("assets/monkey.dae"),
+            scene: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Window {
+    pub title: String,
+    /// Size used when the platform leaves it to us.
+    pub width: u32,
+    pub height: u32,
+    pub fullscreen: bool,
+}
+
+impl Default for Window {
+    fn default() -> Window {
+        Window {
+            title: "changeme".to_owned(),
+            width: 1280,
+            height: 1024,
+            fullscreen: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Graphics {
+    /// Wait for vertical blank instead of presenting as fast as possible.
+    pub vsync: bool,
+}
+
+impl Default for Graphics {
+    fn default() -> Graphics {
+        Graphics { vsync: true }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Input {
+    /// File mapping keys and buttons to actions and axes. The built-in
+    /// bindings are used without one.
+    pub bindings: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Log {
+    /// One of `off`, `error`, `warn`, `info`, `debug` or `trace`.
+    pub level: String,
+}
+
+impl Default for Log {
+    fn default() -> Log {
+        Log { level: "trace".to_owned() }
+    }
+}
+
+impl Log {
+    pub fn level(&self) -> LogLevelFilter {
+        self.level.parse().expect("log level is checked when the config is loaded")
+    }
+}
+
+impl Config {
+    /// Loads the config file at `path`, or the one named by `CHANGEME_CONFIG`,
+    /// or the one in the config directory if there is one, then applies the
+    /// environment.
+    pub fn load(path: Option<&Path>) -> Result<Config> {
+        let named = path.map(Path::to_owned).or_else(|| env::var_os(ENV_CONFIG).map(PathBuf::from));
+        let mut config = match named {
+            Some(path) => try!(Config::from_file(&path)),
+            None => match ::config::path() {
+                Some(ref path) if path.is_file() => try!(Config::from_file(path)),
+                _ => Config::default(),
+            },
+        };
+        try!(config.apply_env());
+        Ok(config)
+    }
+
+    /// Reads a config file, leaving out settings at their defaults.
+    pub fn from_file(path: &Path) -> Result<Config> {
+        let mut source = String::new();
+        try!(File::open(path).and_then(|mut file| file.read_to_string(&mut source))
+                             .chain_err(|| format!("could not read config file '{}'", path.display())));
+        let config: Config = try!(::toml::from_str(&source)
+                                      .map_err(|e| ErrorKind::Parse(path.to_owned(), e.to_string())));
+        try!(config.check());
+        debug!("Loaded config from {}", path.display());
+        Ok(config)
+    }
+
+    /// Applies every `CHANGEME_<SECTION>_<KEY>` environment variable.
+    /// Variables that name no setting, or are not Unicode, are skipped with a
+    /// warning, as other programs may share the prefix.
+    pub fn apply_env(&mut self) -> Result<()> {
+        for (name, value) in env::vars_os() {
+            let (name, value) = match (name.into_string(), value.into_string()) {
+                (Ok(name), Ok(value)) => (name, value),
+                (Ok(ref name), Err(_)) if name.starts_with(ENV_PREFIX) => {
+                    warn!("Ignoring environment variable {}, its value is not valid Unicode", name);
+                    continue;
+                },
+                _ => continue,
+            };
+            if name == ENV_CONFIG || !name.starts_with(ENV_PREFIX) {
+                continue;
+            }
+            let key = name[ENV_PREFIX.len()..].to_lowercase().replacen('_', ".", 1);
+            match self.set(&key, &value) {
+                Err(Error(ErrorKind::UnknownSetting(_), _)) => warn!("Ignoring environment variable {}, there is no setting '{}'", name, key),
+                result => try!(result.chain_err(|| format!("in environment variable {}", name))),
+            }
+        }
+        Ok(())
+    }
+
+    /// Overrides a single setting by its dotted key, e.g. `window.width`.
+    /// The value is read as JSON if that fits the setting, so numbers and
+    /// booleans work, and as a plain string otherwise.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        let mut parts = key.splitn(2, '.');
+        let (section, field) = match (parts.next(), parts.next()) {
+            (Some(section), Some(field)) if !field.is_empty() => (section, field),
+            _ => bail!(ErrorKind::UnknownSetting(key.to_owned())),
+        };
+
+        let text = Value::String(value.to_owned());
+        let config = match serde_json::from_str(value) {
+            Ok(parsed) => match self.with(section, field, parsed) {
+                Err(e) => self.with(section, field, text).or(Err(e)),
+                config => config,
+            },
+            Err(_) => self.with(section, field, text),
+        };
+        let config = match config {
+            Ok(Some(config)) => config,
+            Ok(None) => bail!(ErrorKind::UnknownSetting(key.to_owned())),
+            Err(e) => bail!(ErrorKind::InvalidSetting(key.to_owned(), e.to_string())),
+        };
+        try!(config.check());
+        *self = config;
+        Ok(())
+    }
+
+    /// A copy with one setting replaced, or `None` if there is no such
+    /// setting.
+    fn with(&self, section: &str, field: &str, value: Value) -> ::std::result::Result<Option<Config>, serde_json::Error> {
+        let mut tree = serde_json::to_value(self).expect("config is always representable as JSON");
+        match tree.get_mut(section) {
+            Some(&mut Value::Object(ref mut settings)) if settings.contains_key(field) => {
+                settings.insert(field.to_owned(), value);
+            },
+            _ => return Ok(None),
+        }
+        serde_json::from_value(tree).map(Some)
+    }
+
+    /// Catches values the types alone do not rule out.
+    fn check(&self) -> Result<()> {
+        if self.log.level.parse::<LogLevelFilter>().is_err() {
+            bail!(ErrorKind::InvalidSetting("log.level".to_owned(), format!("unknown log level '{}'", self.log.level)));
+        }
+        if self.window.width == 0 || self.window.height == 0 {
+            bail!(ErrorKind::InvalidSetting("window".to_owned(), "size must not be zero".to_owned()));
+        }
+        Ok(())
+    }
+}
+
+/// Where the config file lives: `$XDG_CONFIG_HOME/changeme/config.toml`,
+/// falling back to `~/.config`.
+pub fn path() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_CONFIG_HOME") {
+        Some(ref dir) if Path::new(dir).is_absolute() => PathBuf::from(dir),
+        _ => match env::var_os("HOME") {
+            Some(home) => Path::new(&home).join(".config"),
+            None => return None,
+        },
+    };
+    Some(base.join("changeme").join("config.toml"))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use ::std::env;
+    use ::std::path::PathBuf;
+    use super::{Config, ErrorKind};
+
+    #[test]
+    fn set_parses_values_to_fit_the_setting() {
+        let mut config = Config::default();
+        config.set("window.width", "800").unwrap();
+        config.set("window.fullscreen", "true").unwrap();
+        // Valid JSON of the wrong type is still a fine string.
+        config.set("window.title", "1234").unwrap();
+        config.set("assets.scene", "levels/one.json").unwrap();
+        config.set("log.level", "info").unwrap();
+        assert_eq!(config.window.width, 800);
+        assert!(config.window.fullscreen);
+        assert_eq!(config.window.title, "1234");
+        assert_eq!(config.assets.scene, Some(PathBuf::from("levels/one.json")));
+        assert_eq!(config.log.level, "info");
+
+        config.set("assets.scene", "null").unwrap();
+        assert_eq!(config.assets.scene, None);
+    }
+
+    #[test]
+    fn set_rejects_unknown_and_invalid_settings() {
+        let mut config = Config::default();
+        for key in &["window", "window.", "windo.width", "window.widht", ""] {
+            match *config.set(key, "1").unwrap_err().kind() {
+                ErrorKind::UnknownSetting(ref unknown) => assert_eq!(unknown, key),
+                ref kind => panic!("unexpected error {:?} for '{}'", kind, key),
+            }
+        }
+        for &(key, value) in &[("window.width", "wide"), ("window.width", "-5"), ("window.height", "0"), ("log.level", "loud")] {
+            match *config.set(key, value).unwrap_err().kind() {
+                ErrorKind::InvalidSetting(..) => (),
+                ref kind => panic!("unexpected error {:?} for {}={}", kind, key, value),
+            }
+        }
+        assert_eq!(config, Config::default());
+    }
+
+    #[test]
+    fn apply_env_sets_prefixed_variables() {
+        env::set_var("CHANGEME_WINDOW_WIDTH", "640");
+        env::set_var("CHANGEME_GRAPHICS_VSYNC", "false");
+        // Not a setting, and the variable naming the config file.
+        env::set_var("CHANGEME_HOME", "/opt/changeme");
+        env::set_var("CHANGEME_CONFIG", "/nowhere.toml");
+        let mut config = Config::default();
+        config.apply_env().unwrap();
+        assert_eq!(config.window.width, 640);
+        assert!(!config.graphics.vsync);
+
+        env::set_var("CHANGEME_WINDOW_HEIGHT", "tall");
+        let message = Config::default().apply_env().unwrap_err().to_string();
+        for name in &["CHANGEME_WINDOW_WIDTH", "CHANGEME_GRAPHICS_VSYNC", "CHANGEME_HOME", "CHANGEME_CONFIG", "CHANGEME_WINDOW_HEIGHT"] {
+            env::remove_var(name);
+        }
+        assert_eq!(message, "in environment variable CHANGEME_WINDOW_HEIGHT");
+    }
+}
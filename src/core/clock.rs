@@ -0,0 +1,396 @@
+use ::std::collections::BTreeMap;
+use ::std::time::Instant;
+use super::seconds;
+
+/// Slack for timers, so a one second timer fires on the 60th tick of a
+/// 60 Hz loop despite rounding in the sum of the steps.
+const EPSILON: f64 = 1e-9;
+
+/// What a timeline advances with.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
+pub enum Source {
+    /// The game timeline, so pausing or slowing the game affects it too.
+    Game,
+    /// The simulation step regardless of the game's pause and scale, e.g.
+    /// for menus that keep animating while the game is paused.
+    Unscaled,
+}
+
+/// A running time that can be paused and scaled independently.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Timeline {
+    source: Source,
+    time: f64,
+    delta: f64,
+    scale: f64,
+    paused: bool,
+}
+
+impl Timeline {
+    pub fn new(source: Source) -> Timeline {
+        Timeline {
+            source: source,
+            time: 0.0,
+            delta: 0.0,
+            scale: 1.0,
+            paused: false,
+        }
+    }
+
+    /// Seconds elapsed on this timeline.
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    /// Seconds the last tick advanced this timeline by. Zero while paused.
+    pub fn delta(&self) -> f64 {
+        self.delta
+    }
+
+    pub fn scale(&self) -> f64 {
+        self.scale
+    }
+
+    /// How fast the timeline runs, e.g. 0.25 for slow motion.
+    pub fn set_scale(&mut self, scale: f64) {
+        assert!(scale >= 0.0, "Time scale cannot be negative");
+        self.scale = scale;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    fn advance(&mut self, dt: f64) {
+        self.delta = if self.paused { 0.0 } else { dt * self.scale };
+        self.time += self.delta;
+    }
+}
+
+/// A countdown on a timeline.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Timer {
+    timeline: Option<String>,
+    duration: f64,
+    remaining: f64,
+    repeat: bool,
+}
+
+impl Timer {
+    /// Seconds left before the timer fires.
+    pub fn remaining(&self) -> f64 {
+        self.remaining
+    }
+
+    pub fn duration(&self) -> f64 {
+        self.duration
+    }
+
+    /// How far along the countdown is, from 0 to 1.
+    pub fn progress(&self) -> f64 {
+        if self.duration > 0.0 { 1.0 - self.remaining / self.duration } else { 1.0 }
+    }
+
+    /// Whether a one-shot timer has fired. Repeating timers never finish.
+    pub fn is_finished(&self) -> bool {
+        !self.repeat && self.remaining <= EPSILON
+    }
+}
+
+/// The time sources of the game.
+///
+/// - Real time passes no matter what and is measured per rendered frame.
+/// - Game time advances by the simulation step every tick, and can be
+///   paused or scaled for slow motion.
+/// - Named timelines run alongside, each with its own pause and scale.
+/// - Named timers count down on a timeline and report when they fire.
+///
+/// The game loop calls `tick` once per simulation tick and `frame` once per
+/// rendered frame. The clock is kept as a global of the scene, so systems
+/// declaring `Access::new().read::<Clock>()` can read it.
+///
+/// Serializing it keeps only what the simulation advanced, leaving out the
+/// real time, which differs from run to run.
+#[derive(Serialize)]
+pub struct Clock {
+    #[serde(skip)]
+    start: Instant,
+    #[serde(skip)]
+    last_frame: Option<Instant>,
+    #[serde(skip)]
+    real_time: f64,
+    #[serde(skip)]
+    frame_delta: f64,
+    #[serde(skip)]
+    frames: u64,
+    game: Timeline,
+    ticks: u64,
+    timelines: BTreeMap<String, Timeline>,
+    /// Sorted, so timers firing on the same tick are reported in the same
+    /// order on every run.
+    timers: BTreeMap<String, Timer>,
+    fired: Vec<String>,
+}
+
+impl Clock {
+    pub fn new() -> Clock {
+        Clock {
+            start: Instant::now(),
+            last_frame: None,
+            real_time: 0.0,
+            frame_delta: 0.0,
+            frames: 0,
+            game: Timeline::new(Source::Unscaled),
+            ticks: 0,
+            timelines: BTreeMap::new(),
+            timers: BTreeMap::new(),
+            fired: Vec::new(),
+        }
+    }
+
+    /// Advances game time, timelines and timers by a simulation step of
+    /// `dt` seconds.
+    pub fn tick(&mut self, dt: f64) {
+        self.ticks += 1;
+        self.game.advance(dt);
+        let game_delta = self.game.delta;
+        for timeline in self.timelines.values_mut() {
+            let dt = match timeline.source {
+                Source::Game => game_delta,
+                Source::Unscaled => dt,
+            };
+            timeline.advance(dt);
+        }
+
+        self.fired.clear();
+        for (name, timer) in self.timers.iter_mut() {
+            if timer.is_finished() {
+                continue;
+            }
+            let delta = match timer.timeline {
+                None => game_delta,
+                Some(ref timeline) => self.timelines.get(timeline).map_or(0.0, |timeline| timeline.delta),
+            };
+
+            timer.remaining -= delta;
+            while timer.remaining <= EPSILON {
+                self.fired.push(name.clone());
+                if !timer.repeat || timer.duration <= 0.0 {
+                    timer.remaining = 0.0;
+                    break;
+                }
+                timer.remaining += timer.duration;
+            }
+        }
+    }
+
+    /// Measures the real time since the previous frame.
+    pub fn frame(&mut self, now: Instant) {
+        self.frame_delta = match self.last_frame {
+            Some(last) if now > last => seconds(now - last),
+            _ => 0.0,
+        };
+        self.last_frame = Some(now);
+        self.real_time = if now > self.start { seconds(now - self.start) } else { 0.0 };
+        self.frames += 1;
+    }
+
+    /// Seconds since the clock was created, as of the last frame.
+    pub fn real_time(&self) -> f64 {
+        self.real_time
+    }
+
+    /// Real seconds between the last two frames.
+    pub fn frame_delta(&self) -> f64 {
+        self.frame_delta
+    }
+
+    /// Frames rendered so far.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Ticks simulated so far, including paused ones.
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    /// Seconds of game time elapsed.
+    pub fn time(&self) -> f64 {
+        self.game.time
+    }
+
+    /// Game seconds the last tick advanced by, which is what gameplay
+    /// should integrate with.
+    pub fn delta(&self) -> f64 {
+        self.game.delta
+    }
+
+    /// The game timeline, to pause or scale the game.
+    pub fn game(&mut self) -> &mut Timeline {
+        &mut self.game
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.game.paused
+    }
+
+    /// Adds a timeline, replacing any of the same name.
+    pub fn add_timeline(&mut self, name: &str, source: Source) -> &mut Timeline {
+        self.timelines.insert(name.to_owned(), Timeline::new(source));
+        self.timelines.get_mut(name).expect("timeline was just added")
+    }
+
+    pub fn timeline(&self, name: &str) -> Option<&Timeline> {
+        self.timelines.get(name)
+    }
+
+    pub fn timeline_mut(&mut self, name: &str) -> Option<&mut Timeline> {
+        self.timelines.get_mut(name)
+    }
+
+    pub fn remove_timeline(&mut self, name: &str) -> bool {
+        self.timelines.remove(name).is_some()
+    }
+
+    /// Starts, or restarts, a countdown of `duration` seconds of game time.
+    /// A repeating timer fires every `duration` seconds until cancelled.
+    pub fn start_timer(&mut self, name: &str, duration: f64, repeat: bool) {
+        self.add_timer(name, None, duration, repeat);
+    }
+
+    /// Like `start_timer`, counting down on the named timeline instead. The
+    /// timer does not run while the timeline does not exist.
+    pub fn start_timer_on(&mut self, name: &str, timeline: &str, duration: f64, repeat: bool) {
+        self.add_timer(name, Some(timeline.to_owned()), duration, repeat);
+    }
+
+    pub fn timer(&self, name: &str) -> Option<&Timer> {
+        self.timers.get(name)
+    }
+
+    pub fn cancel_timer(&mut self, name: &str) -> bool {
+        self.timers.remove(name).is_some()
+    }
+
+    /// Names of the timers that fired during the last tick, once per time
+    /// they fired, sorted by name.
+    pub fn fired(&self) -> &[String] {
+        &self.fired
+    }
+
+    /// Whether the timer fired during the last tick.
+    pub fn just_fired(&self, name: &str) -> bool {
+        self.fired.iter().any(|fired| fired == name)
+    }
+
+    fn add_timer(&mut self, name: &str, timeline: Option<String>, duration: f64, repeat: bool) {
+        assert!(duration >= 0.0, "Timer duration cannot be negative");
+        self.timers.insert(name.to_owned(), Timer {
+            timeline: timeline,
+            duration: duration,
+            remaining: duration,
+            repeat: repeat,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Clock, Source};
+
+    const TICK: f64 = 1.0 / 60.0;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn pausing_and_scaling_the_game_affects_game_timelines_only() {
+        let mut clock = Clock::new();
+        clock.add_timeline("world", Source::Game).set_scale(2.0);
+        clock.add_timeline("menu", Source::Unscaled);
+
+        clock.tick(TICK);
+        assert!(close(clock.delta(), TICK));
+        assert!(close(clock.timeline("world").unwrap().delta(), 2.0 * TICK));
+
+        clock.game().set_scale(0.5);
+        clock.tick(TICK);
+        assert!(close(clock.delta(), 0.5 * TICK));
+        assert!(close(clock.timeline("world").unwrap().delta(), TICK));
+        assert!(close(clock.timeline("menu").unwrap().delta(), TICK));
+
+        clock.game().pause();
+        clock.tick(TICK);
+        assert!(clock.is_paused());
+        assert_eq!(clock.delta(), 0.0);
+        assert_eq!(clock.timeline("world").unwrap().delta(), 0.0);
+        assert!(close(clock.timeline("menu").unwrap().time(), 3.0 * TICK));
+        assert!(close(clock.time(), 1.5 * TICK));
+        assert_eq!(clock.ticks(), 3);
+
+        clock.game().resume();
+        clock.timeline_mut("world").unwrap().pause();
+        clock.tick(TICK);
+        assert!(close(clock.delta(), 0.5 * TICK));
+        assert_eq!(clock.timeline("world").unwrap().delta(), 0.0);
+    }
+
+    #[test]
+    fn timers_fire_on_the_tick_they_run_out() {
+        let mut clock = Clock::new();
+        clock.start_timer("second", 1.0, false);
+        clock.start_timer("quarter", 0.25, true);
+        let mut fired = Vec::new();
+        for tick in 1..121 {
+            clock.tick(TICK);
+            fired.extend(clock.fired().iter().map(|name| (tick, name.clone())));
+        }
+        let expected = vec![(15, "quarter"), (30, "quarter"), (45, "quarter"), (60, "quarter"), (60, "second")];
+        assert_eq!(fired[..5].to_vec(), expected.into_iter().map(|(tick, name)| (tick, name.to_owned())).collect::<Vec<_>>());
+        assert_eq!(fired.len(), 9);
+        assert!(clock.timer("second").unwrap().is_finished());
+        assert!(!clock.timer("quarter").unwrap().is_finished());
+        assert!(clock.cancel_timer("quarter"));
+        clock.tick(TICK);
+        assert!(clock.fired().is_empty());
+    }
+
+    #[test]
+    fn timers_follow_their_timeline() {
+        let mut clock = Clock::new();
+        clock.add_timeline("menu", Source::Unscaled);
+        clock.start_timer("game", 0.1, false);
+        clock.start_timer_on("menu", "menu", 0.1, false);
+        clock.start_timer_on("nowhere", "missing", 0.1, false);
+        clock.game().pause();
+        for _ in 0..6 {
+            clock.tick(TICK);
+        }
+        assert!(clock.just_fired("menu"));
+        assert!(close(clock.timer("game").unwrap().remaining(), 0.1));
+        assert_eq!(clock.timer("game").unwrap().progress(), 0.0);
+
+        clock.game().resume();
+        clock.game().set_scale(2.0);
+        for _ in 0..3 {
+            clock.tick(TICK);
+        }
+        assert!(clock.just_fired("game"));
+        assert!(!clock.timer("nowhere").unwrap().is_finished());
+
+        // A fast repeating timer fires once per period it covered.
+        clock.start_timer("fast", TICK / 4.0, true);
+        clock.tick(TICK);
+        assert_eq!(clock.fired().iter().filter(|&name| name == "fast").count(), 8);
+    }
+}
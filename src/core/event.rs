@@ -0,0 +1,274 @@
+use ::std::any::{Any, TypeId};
+use ::std::collections::HashMap;
+use ::std::marker::PhantomData;
+
+/// Anything that can be sent through an `EventBus`. Implemented for every
+/// type that qualifies.
+pub trait Event: Any + Send + Sync {}
+
+impl<T> Event for T where T: Any + Send + Sync {}
+
+/// Identifies a handler registered with `Events::subscribe`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Subscription(usize);
+
+/// Where a reader is in the stream of events of one type. Every reader sees
+/// each event once, independently of the others.
+#[derive(Debug)]
+pub struct Reader<T> {
+    /// Id of the next event to read.
+    cursor: usize,
+    marker: PhantomData<fn(T)>,
+}
+
+impl<T> Clone for Reader<T> {
+    fn clone(&self) -> Reader<T> {
+        Reader { cursor: self.cursor, marker: PhantomData }
+    }
+}
+
+/// The events of one type.
+///
+/// Deferred events, sent with `send`, are queued in two buffers: the events
+/// of the current frame and those of the previous one. `update` drops the
+/// older buffer, so an event stays readable for two frames and a reader that
+/// runs once per frame, before or after the sender, never misses it.
+///
+/// Immediate events, sent with `dispatch`, are handed to the subscribed
+/// handlers right away and are not queued.
+pub struct Events<T> {
+    previous: Vec<T>,
+    current: Vec<T>,
+    /// Id of the first event in `previous`. Ids are never reused.
+    previous_start: usize,
+    handlers: Vec<(Subscription, Box<FnMut(&T) + Send + Sync>)>,
+    next_subscription: usize,
+}
+
+impl<T> Events<T> where T: Event {
+    pub fn new() -> Events<T> {
+        Events {
+            previous: Vec::new(),
+            current: Vec::new(),
+            previous_start: 0,
+            handlers: Vec::new(),
+            next_subscription: 0,
+        }
+    }
+
+    /// Queues an event for the readers.
+    pub fn send(&mut self, event: T) {
+        self.current.push(event);
+    }
+
+    /// Hands an event to every subscribed handler, in subscription order,
+    /// before returning.
+    pub fn dispatch(&mut self, event: T) {
+        for &mut (_, ref mut handler) in self.handlers.iter_mut() {
+            handler(&event);
+        }
+    }
+
+    /// Calls `handler` for every event sent with `dispatch` from now on.
+    pub fn subscribe<F>(&mut self, handler: F) -> Subscription where F: FnMut(&T) + Send + Sync + 'static {
+        let subscription = Subscription(self.next_subscription);
+        self.next_subscription += 1;
+        self.handlers.push((subscription, Box::new(handler)));
+        subscription
+    }
+
+    /// Returns false if the handler was already gone.
+    pub fn unsubscribe(&mut self, subscription: Subscription) -> bool {
+        let before = self.handlers.len();
+        self.handlers.retain(|&(s, _)| s != subscription);
+        self.handlers.len() != before
+    }
+
+    /// A reader that only sees events sent from now on.
+    pub fn reader(&self) -> Reader<T> {
+        Reader { cursor: self.end(), marker: PhantomData }
+    }
+
+    /// A reader that also sees the events still buffered.
+    pub fn reader_from_start(&self) -> Reader<T> {
+        Reader { cursor: self.previous_start, marker: PhantomData }
+    }
+
+    /// The events the reader has not seen yet, oldest first, and moves the
+    /// reader past them.
+    pub fn read<'a>(&'a self, reader: &mut Reader<T>) -> ::std::iter::Chain<::std::slice::Iter<'a, T>, ::std::slice::Iter<'a, T>> {
+        if reader.cursor < self.previous_start {
+            warn!("Event reader fell behind and missed {} events", self.previous_start - reader.cursor);
+            reader.cursor = self.previous_start;
+        }
+
+        let current_start = self.previous_start + self.previous.len();
+        let previous = &self.previous[::std::cmp::min(reader.cursor - self.previous_start, self.previous.len())..];
+        let current = &self.current[reader.cursor.saturating_sub(current_start)..];
+        reader.cursor = self.end();
+        previous.iter().chain(current.iter())
+    }
+
+    /// Number of events buffered.
+    pub fn len(&self) -> usize {
+        self.previous.len() + self.current.len()
+    }
+
+    /// Ends the frame, dropping the events sent before the previous call.
+    pub fn update(&mut self) {
+        self.previous_start += self.previous.len();
+        self.previous = ::std::mem::replace(&mut self.current, Vec::new());
+    }
+
+    fn end(&self) -> usize {
+        self.previous_start + self.previous.len() + self.current.len()
+    }
+}
+
+/// Lets `EventBus` update channels without knowing their event type.
+trait Channel: Any + Send + Sync {
+    fn update(&mut self);
+    fn as_any(&self) -> &Any;
+    fn as_any_mut(&mut self) -> &mut Any;
+}
+
+impl<T> Channel for Events<T> where T: Event {
+    fn update(&mut self) {
+        Events::update(self);
+    }
+
+    fn as_any(&self) -> &Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut Any {
+        self
+    }
+}
+
+/// Carries events between subsystems that don't know about each other, one
+/// `Events` channel per event type.
+///
+/// Call `update` once per frame, after everything had a chance to read.
+pub struct EventBus {
+    channels: HashMap<TypeId, Box<Channel>>,
+}
+
+impl EventBus {
+    pub fn new() -> EventBus {
+        EventBus { channels: HashMap::new() }
+    }
+
+    /// The channel of events of type `T`, created on first use.
+    pub fn events<T>(&mut self) -> &mut Events<T> where T: Event {
+        self.channels.entry(TypeId::of::<T>())
+                     .or_insert_with(|| Box::new(Events::<T>::new()))
+                     .as_any_mut()
+                     .downcast_mut::<Events<T>>()
+                     .expect("event channel of the wrong type")
+    }
+
+    /// The channel of events of type `T`, if anything used it yet.
+    pub fn get<T>(&self) -> Option<&Events<T>> where T: Event {
+        self.channels.get(&TypeId::of::<T>()).and_then(|channel| channel.as_any().downcast_ref::<Events<T>>())
+    }
+
+    /// Queues an event for the readers of its type.
+    pub fn send<T>(&mut self, event: T) where T: Event {
+        self.events::<T>().send(event);
+    }
+
+    /// Hands an event to the handlers of its type right away.
+    pub fn dispatch<T>(&mut self, event: T) where T: Event {
+        self.events::<T>().dispatch(event);
+    }
+
+    pub fn subscribe<T, F>(&mut self, handler: F) -> Subscription where T: Event, F: FnMut(&T) + Send + Sync + 'static {
+        self.events::<T>().subscribe(handler)
+    }
+
+    pub fn unsubscribe<T>(&mut self, subscription: Subscription) -> bool where T: Event {
+        self.events::<T>().unsubscribe(subscription)
+    }
+
+    /// A reader of the events of type `T` sent from now on.
+    pub fn reader<T>(&mut self) -> Reader<T> where T: Event {
+        self.events::<T>().reader()
+    }
+
+    /// The events of type `T` the reader has not seen yet.
+    pub fn read<'a, T>(&'a mut self, reader: &mut Reader<T>) -> ::std::iter::Chain<::std::slice::Iter<'a, T>, ::std::slice::Iter<'a, T>> where T: Event {
+        self.events::<T>().read(reader)
+    }
+
+    /// Ends the frame for every channel.
+    pub fn update(&mut self) {
+        for channel in self.channels.values_mut() {
+            channel.update();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::std::sync::{Arc, Mutex};
+    use super::{EventBus, Events, Reader};
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Hit(u32);
+
+    fn read(events: &Events<Hit>, reader: &mut Reader<Hit>) -> Vec<Hit> {
+        events.read(reader).cloned().collect()
+    }
+
+    #[test]
+    fn events_stay_readable_until_the_second_update() {
+        let mut events = Events::new();
+        let mut early = events.reader();
+        let mut late = events.reader();
+        events.send(Hit(1));
+        assert_eq!(read(&events, &mut early), vec![Hit(1)]);
+        assert_eq!(read(&events, &mut early), vec![]);
+
+        // A reader running before the sender next frame still sees both.
+        events.update();
+        events.send(Hit(2));
+        assert_eq!(read(&events, &mut early), vec![Hit(2)]);
+        assert_eq!(read(&events, &mut late), vec![Hit(1), Hit(2)]);
+
+        events.update();
+        assert_eq!(events.len(), 1);
+        assert_eq!(read(&events, &mut events.reader_from_start()), vec![Hit(2)]);
+        events.update();
+        assert_eq!(events.len(), 0);
+        assert_eq!(read(&events, &mut late), vec![]);
+    }
+
+    #[test]
+    fn readers_that_fell_behind_skip_dropped_events() {
+        let mut events = Events::new();
+        let mut reader = events.reader();
+        events.send(Hit(1));
+        events.update();
+        events.send(Hit(2));
+        events.update();
+        events.send(Hit(3));
+        assert_eq!(read(&events, &mut reader), vec![Hit(2), Hit(3)]);
+    }
+
+    #[test]
+    fn dispatch_calls_handlers_until_unsubscribed() {
+        let mut bus = EventBus::new();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let handler_seen = seen.clone();
+        let subscription = bus.subscribe(move |hit: &Hit| handler_seen.lock().unwrap().push(hit.0));
+
+        bus.dispatch(Hit(1));
+        assert!(bus.unsubscribe::<Hit>(subscription));
+        assert!(!bus.unsubscribe::<Hit>(subscription));
+        bus.dispatch(Hit(2));
+        assert_eq!(*seen.lock().unwrap(), vec![1]);
+        // Dispatched events are not queued.
+        assert_eq!(bus.get::<Hit>().map(Events::len), Some(0));
+    }
+}
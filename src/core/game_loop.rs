@@ -0,0 +1,210 @@
+use ::std::time::Instant;
+use super::seconds;
+
+/// Whether the loop should keep going.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Control {
+    Continue,
+    Exit,
+}
+
+/// The parts of a game driven by a `GameLoop`.
+pub trait Game {
+    type Error;
+
+    /// Called once per frame before anything else, e.g. to handle window
+    /// events. Returning `Exit` ends the loop before the frame is simulated.
+    fn events(&mut self) -> Result<Control, Self::Error>;
+
+    /// Advances the simulation by exactly `dt` seconds.
+    fn update(&mut self, dt: f64) -> Result<Control, Self::Error>;
+
+    /// Draws a frame. `alpha` in [0, 1) is how far the frame lies between
+    /// the last two ticks, to interpolate between their states.
+    fn render(&mut self, alpha: f64) -> Result<(), Self::Error>;
+}
+
+/// What a frame should do, as decided by `GameLoop::advance`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Frame {
+    /// Simulation ticks to run before rendering.
+    pub ticks: u32,
+    /// Interpolation factor to render with.
+    pub alpha: f64,
+}
+
+/// Runs the simulation at a fixed rate and renders as often as possible.
+///
+/// Real time is accumulated each frame and spent in ticks of a fixed
+/// length, so the simulation behaves the same whatever the frame rate. What
+/// is left over becomes the interpolation alpha.
+///
+/// A frame never runs more than `max_ticks` ticks. If the simulation cannot
+/// keep up, or the process was stalled, the backlog is dropped and the game
+/// slows down instead of spiralling into ever longer frames.
+pub struct GameLoop {
+    tick: f64,
+    max_ticks: u32,
+    accumulator: f64,
+    previous: Option<Instant>,
+    ticks: u64,
+}
+
+impl GameLoop {
+    /// A loop running `rate` ticks per second, catching up at most a
+    /// quarter of a second per frame.
+    pub fn new(rate: f64) -> GameLoop {
+        assert!(rate > 0.0, "Tick rate must be positive");
+        GameLoop {
+            tick: 1.0 / rate,
+            max_ticks: ::std::cmp::max(1, (0.25 * rate).ceil() as u32),
+            accumulator: 0.0,
+            previous: None,
+            ticks: 0,
+        }
+    }
+
+    /// Most ticks a single frame may run.
+    pub fn with_max_ticks(mut self, max_ticks: u32) -> GameLoop {
+        self.max_ticks = ::std::cmp::max(1, max_ticks);
+        self
+    }
+
+    /// Length of a tick in seconds.
+    pub fn tick(&self) -> f64 {
+        self.tick
+    }
+
+    /// Ticks simulated since the loop started.
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    /// Accounts for the time passed since the previous frame and works out
+    /// what this one should do. The first frame runs no ticks.
+    pub fn advance(&mut self, now: Instant) -> Frame {
+        let elapsed = match self.previous {
+            Some(previous) if now > previous => seconds(now - previous),
+            _ => 0.0,
+        };
+        self.previous = Some(now);
+        self.accumulator += elapsed;
+
+        let due = (self.accumulator / self.tick).floor();
+        let ticks = if due > self.max_ticks as f64 {
+            debug!("Game loop fell behind, dropping {} ticks", due as u64 - self.max_ticks as u64);
+            self.accumulator %= self.tick;
+            self.max_ticks
+        } else {
+            self.accumulator -= due * self.tick;
+            due as u32
+        };
+        self.ticks += ticks as u64;
+
+        Frame {
+            ticks: ticks,
+            alpha: (self.accumulator / self.tick).max(0.0).min(1.0),
+        }
+    }
+
+    /// Runs frames until the game asks to exit or fails.
+    pub fn run<G>(&mut self, game: &mut G) -> Result<(), G::Error> where G: Game {
+        loop {
+            if try!(game.events()) == Control::Exit {
+                return Ok(());
+            }
+
+            let frame = self.advance(Instant::now());
+            for _ in 0..frame.ticks {
+                if try!(game.update(self.tick)) == Control::Exit {
+                    return Ok(());
+                }
+            }
+            try!(game.render(frame.alpha));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::std::time::{Duration, Instant};
+    use super::{Control, Game, GameLoop};
+
+    fn millis(start: Instant, millis: u64) -> Instant {
+        start + Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn time_is_spent_in_whole_ticks() {
+        let start = Instant::now();
+        let mut game_loop = GameLoop::new(10.0);
+        let frame = game_loop.advance(start);
+        assert_eq!((frame.ticks, frame.alpha), (0, 0.0));
+
+        let frame = game_loop.advance(millis(start, 250));
+        assert_eq!(frame.ticks, 2);
+        assert!((frame.alpha - 0.5).abs() < 1e-6);
+
+        let frame = game_loop.advance(millis(start, 320));
+        assert_eq!(frame.ticks, 1);
+        assert!((frame.alpha - 0.2).abs() < 1e-6);
+        assert_eq!(game_loop.ticks(), 3);
+
+        // Time going backwards counts as none passing.
+        let frame = game_loop.advance(millis(start, 300));
+        assert_eq!(frame.ticks, 0);
+        assert!((frame.alpha - 0.2).abs() < 1e-6);
+    }
+
+    #[test]
+    fn stalls_drop_the_backlog() {
+        let start = Instant::now();
+        let mut game_loop = GameLoop::new(10.0).with_max_ticks(3);
+        game_loop.advance(start);
+
+        let frame = game_loop.advance(millis(start, 10_050));
+        assert_eq!(frame.ticks, 3);
+        assert!((frame.alpha - 0.5).abs() < 1e-6);
+        let frame = game_loop.advance(millis(start, 10_100));
+        assert_eq!(frame.ticks, 1);
+        assert!(frame.alpha.abs() < 1e-6);
+        assert_eq!(game_loop.ticks(), 4);
+    }
+
+    /// Exits after `updates` ticks, counting what it was asked to do.
+    struct Counter {
+        updates: u32,
+        events: u32,
+        renders: u32,
+    }
+
+    impl Game for Counter {
+        type Error = ();
+
+        fn events(&mut self) -> Result<Control, ()> {
+            self.events += 1;
+            Ok(Control::Continue)
+        }
+
+        fn update(&mut self, dt: f64) -> Result<Control, ()> {
+            assert_eq!(dt, 0.001);
+            self.updates -= 1;
+            Ok(if self.updates == 0 { Control::Exit } else { Control::Continue })
+        }
+
+        fn render(&mut self, alpha: f64) -> Result<(), ()> {
+            assert!((0.0..1.0).contains(&alpha));
+            self.renders += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn run_stops_when_the_game_exits() {
+        let mut game = Counter { updates: 5, events: 0, renders: 0 };
+        GameLoop::new(1000.0).run(&mut game).unwrap();
+        assert_eq!(game.updates, 0);
+        assert!(game.events > 0);
+        assert_eq!(game.renders, game.events - 1);
+    }
+}
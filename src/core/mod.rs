@@ -1,149 +1,42 @@
-#[derive(Copy, Clone)]
-pub struct Vertex(pub ::cgmath::Vector3<f32>);
-#[derive(Copy, Clone)]
-pub struct Normal(pub ::cgmath::Vector3<f32>);
-pub type Index = u16;
-
-/// This is the expansion of impl_vertex! macro which implements the needed traits to
-/// use in the vulkano pipeline. It was modified to support newtypes.
-/// Using this allows us to freely communicate between vulkan and the cgmath crates
-unsafe impl ::vulkano::pipeline::vertex::Vertex for Vertex {
-    #[inline(always)]
-    fn member(name: &str) -> Option<::vulkano::pipeline::vertex::VertexMemberInfo> {
-        #[allow(unused_imports)]
-        use ::vulkano::format::Format;
-        use ::vulkano::pipeline::vertex::VertexMemberInfo;
-        use ::vulkano::pipeline::vertex::VertexMemberTy;
-        use ::vulkano::pipeline::vertex::VertexMember;
-
-        // am unsure why the member names must match variables in glsl
-        match name {
-            "pos_x" => {
-                let (ty, array_size) = unsafe {
-                    #[inline] fn f<S: VertexMember>(_: &S) -> (VertexMemberTy, usize) { S::format() }
-                    let dummy = 0usize as *const Vertex;
-                    f(&(&*dummy).0.x)
-                };
-
-                Some(VertexMemberInfo {
-                    offset: unsafe {
-                        let dummy = 0usize as *const Vertex;
-                        let member = (&(&*dummy).0.x) as *const _;
-                        member as usize
-                    },
-
-                    ty: ty,
-                    array_size: array_size,
-                })
-            },
-            "pos_y" => {
-                let (ty, array_size) = unsafe {
-                    #[inline] fn f<S: VertexMember>(_: &S) -> (VertexMemberTy, usize) { S::format() }
-                    let dummy = 0usize as *const Vertex;
-                    f(&(&*dummy).0.y)
-                };
-
-                Some(VertexMemberInfo {
-                    offset: unsafe {
-                        let dummy = 0usize as *const Vertex;
-                        let member = (&(&*dummy).0.y) as *const _;
-                        member as usize
-                    },
-
-                    ty: ty,
-                    array_size: array_size,
-                })
-            },
-            "pos_z" => {
-                let (ty, array_size) = unsafe {
-                    #[inline] fn f<S: VertexMember>(_: &S) -> (VertexMemberTy, usize) { S::format() }
-                    let dummy = 0usize as *const Vertex;
-                    f(&(&*dummy).0.z)
-                };
-
-                Some(VertexMemberInfo {
-                    offset: unsafe {
-                        let dummy = 0usize as *const Vertex;
-                        let member = (&(&*dummy).0.z) as *const _;
-                        member as usize
-                    },
+use ::cgmath::{Vector2, Vector3, Vector4};
+use ::std::time::Duration;
+
+pub mod clock;
+pub mod event;
+pub mod game_loop;
+pub mod rng;
+
+/// One vertex of a mesh, every attribute interleaved in a single buffer.
+/// Field names match the inputs of the vertex shader.
+#[derive(Copy, Clone, Debug, PartialEq, VertexLayout)]
+#[repr(C)]
+pub struct Vertex {
+    pub position: Vector3<f32>,
+    pub normal: Vector3<f32>,
+    pub uv: Vector2<f32>,
+    /// xyz is the tangent along which `uv.x` grows, w is 1 or -1 depending on
+    /// whether the bitangent is `normal × tangent` or its opposite.
+    pub tangent: Vector4<f32>,
+    /// Linear RGBA, multiplied with the material color.
+    pub color: Vector4<f32>,
+}
 
-                    ty: ty,
-                    array_size: array_size,
-                })
-            },
-            _ => None,
+impl Vertex {
+    /// A white vertex at `position` with the other attributes zeroed.
+    pub fn new(position: Vector3<f32>) -> Vertex {
+        Vertex {
+            position: position,
+            normal: Vector3::new(0.0, 0.0, 0.0),
+            uv: Vector2::new(0.0, 0.0),
+            tangent: Vector4::new(0.0, 0.0, 0.0, 1.0),
+            color: Vector4::new(1.0, 1.0, 1.0, 1.0),
         }
     }
 }
 
-/// impl_vertex!(Normal, x, y, z)
-unsafe impl ::vulkano::pipeline::vertex::Vertex for Normal {
-    #[inline(always)]
-    fn member(name: &str) -> Option<::vulkano::pipeline::vertex::VertexMemberInfo> {
-        #[allow(unused_imports)]
-        use ::vulkano::format::Format;
-        use ::vulkano::pipeline::vertex::VertexMemberInfo;
-        use ::vulkano::pipeline::vertex::VertexMemberTy;
-        use ::vulkano::pipeline::vertex::VertexMember;
-
-        // am unsure why the member names must match variables in glsl
-        match name {
-            "norm_x" => {
-                let (ty, array_size) = unsafe {
-                    #[inline] fn f<S: VertexMember>(_: &S) -> (VertexMemberTy, usize) { S::format() }
-                    let dummy = 0usize as *const Normal;
-                    f(&(&*dummy).0.x)
-                };
-
-                Some(VertexMemberInfo {
-                    offset: unsafe {
-                        let dummy = 0usize as *const Normal;
-                        let member = (&(&*dummy).0.x) as *const _;
-                        member as usize
-                    },
-
-                    ty: ty,
-                    array_size: array_size,
-                })
-            },
-            "norm_y" => {
-                let (ty, array_size) = unsafe {
-                    #[inline] fn f<S: VertexMember>(_: &S) -> (VertexMemberTy, usize) { S::format() }
-                    let dummy = 0usize as *const Normal;
-                    f(&(&*dummy).0.y)
-                };
-
-                Some(VertexMemberInfo {
-                    offset: unsafe {
-                        let dummy = 0usize as *const Normal;
-                        let member = (&(&*dummy).0.y) as *const _;
-                        member as usize
-                    },
-
-                    ty: ty,
-                    array_size: array_size,
-                })
-            },
-            "norm_z" => {
-                let (ty, array_size) = unsafe {
-                    #[inline] fn f<S: VertexMember>(_: &S) -> (VertexMemberTy, usize) { S::format() }
-                    let dummy = 0usize as *const Normal;
-                    f(&(&*dummy).0.z)
-                };
-
-                Some(VertexMemberInfo {
-                    offset: unsafe {
-                        let dummy = 0usize as *const Normal;
-                        let member = (&(&*dummy).0.z) as *const _;
-                        member as usize
-                    },
+pub type Index = u16;
 
-                    ty: ty,
-                    array_size: array_size,
-                })
-            },
-            _ => None,
-        }
-    }
+/// A duration in seconds.
+pub(crate) fn seconds(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + duration.subsec_nanos() as f64 * 1e-9
 }
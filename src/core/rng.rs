@@ -0,0 +1,65 @@
+use ::std::time::{SystemTime, UNIX_EPOCH};
+
+/// A small, fast random number generator (xorshift64*).
+///
+/// The sequence only depends on the seed, on every platform, so a run can
+/// be reproduced from its seed. Not suitable for anything security related.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rng {
+    seed: u64,
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        // The state must never be zero, and similar seeds should not start
+        // out with similar sequences.
+        let mut state = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        state = (state ^ (state >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        state = (state ^ (state >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        state ^= state >> 31;
+        Rng {
+            seed: seed,
+            state: if state == 0 { 0x9E37_79B9_7F4A_7C15 } else { state },
+        }
+    }
+
+    /// Seeded from the current time, for runs that need not be reproduced.
+    pub fn from_time() -> Rng {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        Rng::new(now.as_secs() ^ ((now.subsec_nanos() as u64) << 32))
+    }
+
+    /// The seed this generator started from.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Where in its sequence the generator is, e.g. to tell whether two
+    /// runs drew the same numbers.
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Uniform in [0, 1).
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Uniform in [low, high).
+    pub fn range(&mut self, low: f64, high: f64) -> f64 {
+        low + (high - low) * self.next_f64()
+    }
+
+    /// True with the given probability.
+    pub fn chance(&mut self, probability: f64) -> bool {
+        self.next_f64() < probability
+    }
+}
@@ -0,0 +1,478 @@
+use ::std::collections::{BTreeMap, BTreeSet};
+use ::std::fs::File;
+use ::std::io::Read;
+use ::std::path::{Path, PathBuf};
+use ::winit::{ElementState, Event, MouseButton, MouseScrollDelta, VirtualKeyCode};
+
+/// Scroll distance of one line, for touchpads that scroll by pixels.
+const PIXELS_PER_LINE: f64 = 20.0;
+
+/// Names of winit's `VirtualKeyCode`s, as `Button::key` spells them.
+const KEYS: &'static [&'static str] = &[
+    "Key1", "Key2", "Key3", "Key4", "Key5", "Key6", "Key7", "Key8", "Key9", "Key0", "A", "B", "C",
+    "D", "E", "F", "G", "H", "I", "J", "K", "L", "M", "N", "O", "P", "Q", "R", "S", "T", "U", "V",
+    "W", "X", "Y", "Z", "Escape", "F1", "F2", "F3", "F4", "F5", "F6", "F7", "F8", "F9", "F10",
+    "F11", "F12", "F13", "F14", "F15", "Snapshot", "Scroll", "Pause", "Insert", "Home", "Delete",
+    "End", "PageDown", "PageUp", "Left", "Up", "Right", "Down", "Back", "Return", "Space",
+    "Numlock", "Numpad0", "Numpad1", "Numpad2", "Numpad3", "Numpad4", "Numpad5", "Numpad6",
+    "Numpad7", "Numpad8", "Numpad9", "AbntC1", "AbntC2", "Add", "Apostrophe", "Apps", "At", "Ax",
+    "Backslash", "Calculator", "Capital", "Colon", "Comma", "Convert", "Decimal", "Divide",
+    "Equals", "Grave", "Kana", "Kanji", "LAlt", "LBracket", "LControl", "LMenu", "LShift", "LWin",
+    "Mail", "MediaSelect", "MediaStop", "Minus", "Multiply", "Mute", "MyComputer",
+    "NavigateForward", "NavigateBackward", "NextTrack", "NoConvert", "NumpadComma", "NumpadEnter",
+    "NumpadEquals", "OEM102", "Period", "PlayPause", "Power", "PrevTrack", "RAlt", "RBracket",
+    "RControl", "RMenu", "RShift", "RWin", "Semicolon", "Slash", "Sleep", "Stop", "Subtract",
+    "Sysrq", "Tab", "Underline", "Unlabeled", "VolumeDown", "VolumeUp", "Wake", "WebBack",
+    "WebFavorites", "WebForward", "WebHome", "WebRefresh", "WebSearch", "WebStop", "Yen",
+];
+
+error_chain! {
+    foreign_links {
+        Io(::std::io::Error);
+    }
+
+    errors {
+        Parse(path: PathBuf, message: String) {
+            description("could not parse bindings")
+            display("could not parse bindings '{}': {}", path.display(), message)
+        }
+        UnknownButton(path: PathBuf, binding: String, button: String) {
+            description("unknown button")
+            display("unknown button '{}' in {} of bindings '{}'", button, binding, path.display())
+        }
+    }
+}
+
+/// A key or mouse button, by name.
+///
+/// Keys are named like winit's `VirtualKeyCode`, e.g. `W`, `Space`,
+/// `LShift` or `Escape`. Mouse buttons are `MouseLeft`, `MouseRight`,
+/// `MouseMiddle` or `Mouse<n>` for any other. Bindings files naming
+/// anything else are rejected.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Button(String);
+
+impl Button {
+    pub fn named(name: &str) -> Button {
+        Button(name.to_owned())
+    }
+
+    pub fn key(key: VirtualKeyCode) -> Button {
+        Button(format!("{:?}", key))
+    }
+
+    pub fn mouse(button: MouseButton) -> Button {
+        match button {
+            MouseButton::Other(n) => Button(format!("Mouse{}", n)),
+            button => Button(format!("Mouse{:?}", button)),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.0
+    }
+
+    /// Whether the name is that of a key or mouse button, so it can ever be
+    /// pressed.
+    pub fn is_known(&self) -> bool {
+        match &self.0[..] {
+            "MouseLeft" | "MouseRight" | "MouseMiddle" => true,
+            name if name.starts_with("Mouse") => name["Mouse".len()..].parse::<u8>().is_ok(),
+            name => KEYS.contains(&name),
+        }
+    }
+}
+
+/// A change to the input state, taken from a window event. Recorded to
+/// replay a run.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Change {
+    Press(Button),
+    Release(Button),
+    /// The cursor moved to a window position.
+    Cursor { x: f64, y: f64 },
+    /// Scrolled by a number of lines.
+    Scroll { x: f64, y: f64 },
+    /// The window lost focus, so releases would go elsewhere.
+    ReleaseAll,
+}
+
+impl Change {
+    /// The change a window event makes, if any.
+    pub fn from_event(event: &Event) -> Option<Change> {
+        let button = |state, button| if state == ElementState::Pressed { Change::Press(button) } else { Change::Release(button) };
+        Some(match *event {
+            Event::KeyboardInput(state, _, Some(key)) => button(state, Button::key(key)),
+            Event::MouseInput(state, mouse) => button(state, Button::mouse(mouse)),
+            Event::MouseMoved(x, y) => Change::Cursor { x: x as f64, y: y as f64 },
+            Event::MouseWheel(MouseScrollDelta::LineDelta(x, y), _) => Change::Scroll { x: x as f64, y: y as f64 },
+            Event::MouseWheel(MouseScrollDelta::PixelDelta(x, y), _) => {
+                Change::Scroll { x: x as f64 / PIXELS_PER_LINE, y: y as f64 / PIXELS_PER_LINE }
+            },
+            Event::Focused(false) => Change::ReleaseAll,
+            _ => return None,
+        })
+    }
+}
+
+/// Mouse movement that can drive an axis.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Motion {
+    /// Cursor movement in pixels, right is positive.
+    MouseX,
+    /// Cursor movement in pixels, down is positive.
+    MouseY,
+    /// Scrolling in lines.
+    ScrollX,
+    ScrollY,
+}
+
+/// What drives an axis. Held buttons give -1, 0 or 1, to which the scaled
+/// mouse motion is added.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Axis {
+    pub positive: Vec<Button>,
+    pub negative: Vec<Button>,
+    pub motion: Option<Motion>,
+    /// Multiplies the motion, e.g. to turn pixels into radians.
+    pub scale: f64,
+}
+
+impl Default for Axis {
+    fn default() -> Axis {
+        Axis {
+            positive: Vec::new(),
+            negative: Vec::new(),
+            motion: None,
+            scale: 1.0,
+        }
+    }
+}
+
+/// Named actions and axes mapped to physical inputs, so gameplay asks for
+/// `jump` rather than `Space`. Read from TOML:
+///
+/// ```toml
+/// [actions]
+/// jump = ["Space", "MouseRight"]
+///
+/// [axes.move_x]
+/// positive = ["D", "Right"]
+/// negative = ["A", "Left"]
+///
+/// [axes.look_x]
+/// motion = "mouse_x"
+/// scale = 0.005
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Bindings {
+    /// Buttons triggering each action. Any of them will do.
+    pub actions: BTreeMap<String, Vec<Button>>,
+    pub axes: BTreeMap<String, Axis>,
+}
+
+impl Default for Bindings {
+    /// Mouse look, WASD and arrow keys to move and Escape to quit.
+    fn default() -> Bindings {
+        let buttons = |names: &[&str]| names.iter().map(|name| Button::named(name)).collect::<Vec<_>>();
+        let mut actions = BTreeMap::new();
+        actions.insert("quit".to_owned(), buttons(&["Escape"]));
+        actions.insert("jump".to_owned(), buttons(&["Space"]));
+
+        let mut axes = BTreeMap::new();
+        axes.insert("move_x".to_owned(), Axis { positive: buttons(&["D", "Right"]), negative: buttons(&["A", "Left"]), .. Axis::default() });
+        axes.insert("move_y".to_owned(), Axis { positive: buttons(&["W", "Up"]), negative: buttons(&["S", "Down"]), .. Axis::default() });
+        axes.insert("look_x".to_owned(), Axis { motion: Some(Motion::MouseX), scale: 0.005, .. Axis::default() });
+        axes.insert("look_y".to_owned(), Axis { motion: Some(Motion::MouseY), scale: 0.005, .. Axis::default() });
+        axes.insert("zoom".to_owned(), Axis { motion: Some(Motion::ScrollY), .. Axis::default() });
+
+        Bindings { actions: actions, axes: axes }
+    }
+}
+
+impl Bindings {
+    /// Reads bindings from a TOML file. Only the actions and axes in the
+    /// file exist, the defaults are not merged in.
+    pub fn from_file(path: &Path) -> Result<Bindings> {
+        let mut source = String::new();
+        try!(File::open(path).and_then(|mut file| file.read_to_string(&mut source))
+                             .chain_err(|| format!("could not read bindings '{}'", path.display())));
+        let bindings: Bindings = try!(::toml::from_str(&source)
+                                          .map_err(|e| ErrorKind::Parse(path.to_owned(), e.to_string())));
+        if let Some((binding, button)) = bindings.unknown_button() {
+            bail!(ErrorKind::UnknownButton(path.to_owned(), binding, button.0.clone()));
+        }
+        debug!("Loaded input bindings from {}", path.display());
+        Ok(bindings)
+    }
+
+    /// The first button that is not a key or mouse button, along with where
+    /// it is bound, e.g. `axes.move_x.positive`.
+    fn unknown_button(&self) -> Option<(String, &Button)> {
+        for (action, buttons) in self.actions.iter() {
+            if let Some(button) = buttons.iter().find(|button| !button.is_known()) {
+                return Some((format!("actions.{}", action), button));
+            }
+        }
+        for (name, axis) in self.axes.iter() {
+            for &(side, buttons) in [("positive", &axis.positive), ("negative", &axis.negative)].iter() {
+                if let Some(button) = buttons.iter().find(|button| !button.is_known()) {
+                    return Some((format!("axes.{}.{}", name, side), button));
+                }
+            }
+        }
+        None
+    }
+}
+
+/// The state of keyboard and mouse, fed with window events.
+///
+/// Besides what is held down it keeps the edges and motion since the last
+/// call to `begin_frame`, so a press is seen even if the button is released
+/// again before anyone looked.
+///
+/// Serializing it keeps the state but not the bindings.
+#[derive(Serialize)]
+pub struct Input {
+    #[serde(skip)]
+    bindings: Bindings,
+    down: BTreeSet<Button>,
+    pressed: BTreeSet<Button>,
+    released: BTreeSet<Button>,
+    cursor: Option<(f64, f64)>,
+    mouse_delta: (f64, f64),
+    scroll: (f64, f64),
+}
+
+impl Input {
+    pub fn new(bindings: Bindings) -> Input {
+        Input {
+            bindings: bindings,
+            down: BTreeSet::new(),
+            pressed: BTreeSet::new(),
+            released: BTreeSet::new(),
+            cursor: None,
+            mouse_delta: (0.0, 0.0),
+            scroll: (0.0, 0.0),
+        }
+    }
+
+    pub fn bindings(&self) -> &Bindings {
+        &self.bindings
+    }
+
+    pub fn set_bindings(&mut self, bindings: Bindings) {
+        self.bindings = bindings;
+    }
+
+    /// Forgets the edges and motion of the previous frame. Call before
+    /// handling the frame's events, or before applying the changes of a
+    /// simulation tick.
+    pub fn begin_frame(&mut self) {
+        self.pressed.clear();
+        self.released.clear();
+        self.mouse_delta = (0.0, 0.0);
+        self.scroll = (0.0, 0.0);
+    }
+
+    /// Updates the state from a window event. Other events are ignored.
+    pub fn handle(&mut self, event: &Event) {
+        if let Some(change) = Change::from_event(event) {
+            self.apply(&change);
+        }
+    }
+
+    pub fn apply(&mut self, change: &Change) {
+        match *change {
+            Change::Press(ref button) => self.set(button.clone(), true),
+            Change::Release(ref button) => self.set(button.clone(), false),
+            Change::Cursor { x, y } => self.move_cursor(x, y),
+            Change::Scroll { x, y } => self.scroll_by(x, y),
+            Change::ReleaseAll => self.release_all(),
+        }
+    }
+
+    /// Presses or releases a button. Repeated presses from key repeat are
+    /// not new edges.
+    pub fn set(&mut self, button: Button, down: bool) {
+        if down {
+            if self.down.insert(button.clone()) {
+                self.pressed.insert(button);
+            }
+        } else if self.down.remove(&button) {
+            self.released.insert(button);
+        }
+    }
+
+    pub fn release_all(&mut self) {
+        for button in ::std::mem::replace(&mut self.down, BTreeSet::new()) {
+            self.released.insert(button);
+        }
+    }
+
+    /// Moves the cursor to a window position. The first position is not
+    /// counted as motion.
+    pub fn move_cursor(&mut self, x: f64, y: f64) {
+        if let Some((last_x, last_y)) = self.cursor {
+            self.mouse_delta.0 += x - last_x;
+            self.mouse_delta.1 += y - last_y;
+        }
+        self.cursor = Some((x, y));
+    }
+
+    pub fn scroll_by(&mut self, x: f64, y: f64) {
+        self.scroll.0 += x;
+        self.scroll.1 += y;
+    }
+
+    pub fn is_down(&self, button: &Button) -> bool {
+        self.down.contains(button)
+    }
+
+    /// Whether the button went down this frame.
+    pub fn was_pressed(&self, button: &Button) -> bool {
+        self.pressed.contains(button)
+    }
+
+    /// Whether the button went up this frame.
+    pub fn was_released(&self, button: &Button) -> bool {
+        self.released.contains(button)
+    }
+
+    /// Last known cursor position in window pixels.
+    pub fn cursor(&self) -> Option<(f64, f64)> {
+        self.cursor
+    }
+
+    /// Cursor movement this frame in pixels.
+    pub fn mouse_delta(&self) -> (f64, f64) {
+        self.mouse_delta
+    }
+
+    /// Scrolling this frame in lines.
+    pub fn scroll(&self) -> (f64, f64) {
+        self.scroll
+    }
+
+    /// Whether any button of the action is held. Unknown actions are never
+    /// down.
+    pub fn action_down(&self, action: &str) -> bool {
+        self.action_any(action, |button| self.is_down(button))
+    }
+
+    pub fn action_pressed(&self, action: &str) -> bool {
+        self.action_any(action, |button| self.was_pressed(button))
+    }
+
+    pub fn action_released(&self, action: &str) -> bool {
+        self.action_any(action, |button| self.was_released(button))
+    }
+
+    /// Value of an axis, or 0 for unknown axes.
+    pub fn axis(&self, axis: &str) -> f64 {
+        let axis = match self.bindings.axes.get(axis) {
+            Some(axis) => axis,
+            None => return 0.0,
+        };
+
+        let held = |buttons: &[Button]| if buttons.iter().any(|button| self.is_down(button)) { 1.0 } else { 0.0 };
+        let buttons = held(&axis.positive) - held(&axis.negative);
+        let motion = match axis.motion {
+            Some(Motion::MouseX) => self.mouse_delta.0,
+            Some(Motion::MouseY) => self.mouse_delta.1,
+            Some(Motion::ScrollX) => self.scroll.0,
+            Some(Motion::ScrollY) => self.scroll.1,
+            None => 0.0,
+        };
+        buttons + motion * axis.scale
+    }
+
+    fn action_any<F>(&self, action: &str, test: F) -> bool where F: Fn(&Button) -> bool {
+        self.bindings.actions.get(action).map_or(false, |buttons| buttons.iter().any(test))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Axis, Bindings, Button, Change, Input, Motion};
+
+    fn button(name: &str) -> Button {
+        Button::named(name)
+    }
+
+    #[test]
+    fn presses_and_releases_are_edges_until_the_next_frame() {
+        let mut input = Input::new(Bindings::default());
+        input.apply(&Change::Press(button("Space")));
+        input.apply(&Change::Press(button("Space")));
+        assert!(input.is_down(&button("Space")));
+        assert!(input.was_pressed(&button("Space")));
+        assert!(input.action_down("jump") && input.action_pressed("jump"));
+
+        // Key repeat is not a new press.
+        input.begin_frame();
+        input.apply(&Change::Press(button("Space")));
+        assert!(input.action_down("jump") && !input.action_pressed("jump"));
+
+        // A tap within a frame is seen both ways.
+        input.apply(&Change::Press(button("Escape")));
+        input.apply(&Change::Release(button("Escape")));
+        assert!(input.action_pressed("quit") && input.action_released("quit"));
+        assert!(!input.action_down("quit"));
+
+        input.begin_frame();
+        input.apply(&Change::ReleaseAll);
+        assert!(!input.is_down(&button("Space")));
+        assert!(input.was_released(&button("Space")));
+        assert!(!input.was_released(&button("Escape")));
+        assert!(!input.action_down("missing") && !input.action_pressed("missing"));
+    }
+
+    #[test]
+    fn axes_combine_buttons_and_scaled_motion() {
+        let mut input = Input::new(Bindings::default());
+        input.apply(&Change::Press(button("D")));
+        assert_eq!(input.axis("move_x"), 1.0);
+        input.apply(&Change::Press(button("Left")));
+        assert_eq!(input.axis("move_x"), 0.0);
+        input.apply(&Change::Release(button("D")));
+        assert_eq!(input.axis("move_x"), -1.0);
+
+        // The first cursor position is not motion.
+        input.apply(&Change::Cursor { x: 100.0, y: 50.0 });
+        assert_eq!(input.axis("look_x"), 0.0);
+        input.apply(&Change::Cursor { x: 150.0, y: 30.0 });
+        input.apply(&Change::Cursor { x: 300.0, y: 30.0 });
+        input.apply(&Change::Scroll { x: 0.0, y: -2.0 });
+        assert_eq!(input.mouse_delta(), (200.0, -20.0));
+        assert!((input.axis("look_x") - 1.0).abs() < 1e-9);
+        assert!((input.axis("look_y") + 0.1).abs() < 1e-9);
+        assert_eq!(input.axis("zoom"), -2.0);
+        assert_eq!(input.axis("missing"), 0.0);
+
+        input.begin_frame();
+        assert_eq!(input.axis("look_x"), 0.0);
+        assert_eq!(input.axis("zoom"), 0.0);
+        assert_eq!(input.axis("move_x"), -1.0);
+        assert_eq!(input.cursor(), Some((300.0, 30.0)));
+    }
+
+    #[test]
+    fn unknown_buttons_are_found_with_where_they_are_bound() {
+        let mut bindings = Bindings::default();
+        assert!(bindings.unknown_button().is_none());
+        bindings.actions.insert("fire".to_owned(), vec![button("MouseLeft"), button("Mouse4"), button("LShift")]);
+        assert!(bindings.unknown_button().is_none());
+
+        let axis = Axis { positive: vec![button("D")], negative: vec![button("Lfet")], motion: Some(Motion::MouseX), scale: 1.0 };
+        bindings.axes.insert("strafe".to_owned(), axis);
+        assert_eq!(bindings.unknown_button(), Some(("axes.strafe.negative".to_owned(), &button("Lfet"))));
+        bindings.actions.insert("jump".to_owned(), vec![button("Spcae")]);
+        assert_eq!(bindings.unknown_button(), Some(("actions.jump".to_owned(), &button("Spcae"))));
+        assert!(!button("Mouse").is_known() && !button("Mousex").is_known());
+    }
+}
@@ -0,0 +1,41 @@
+#[macro_use]
+extern crate error_chain;
+#[macro_use]
+extern crate log;
+extern crate time;
+#[macro_use]
+extern crate vulkano;
+extern crate vulkano_win;
+extern crate winit;
+extern crate cgmath;
+extern crate collada;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate toml;
+extern crate rhai;
+extern crate rayon;
+#[macro_use]
+extern crate changeme_derive;
+extern crate xml;
+
+/// The crate version as a literal, so it can be `concat!`ed.
+macro_rules! version {
+    () => (concat!(env!("CARGO_PKG_VERSION_MAJOR"), ".",
+                   env!("CARGO_PKG_VERSION_MINOR"), ".",
+                   env!("CARGO_PKG_VERSION_PATCH")))
+}
+
+/// Version of the game, e.g. for `--version`.
+pub static VERSION: &'static str = version!();
+
+pub mod config;
+pub mod core;
+pub mod input;
+pub mod logger;
+pub mod render;
+pub mod replay;
+pub mod resource;
+pub mod scene;
+pub mod script;
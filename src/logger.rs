@@ -1,15 +1,11 @@
-use log::{self, LogRecord, LogLevel, LogMetadata, LogLevelFilter};
+use log::{self, LogRecord, LogMetadata, LogLevelFilter};
 use time;
 
 use std::io::{Stderr, stderr};
 use std::io::Write;
 use std::sync::{Arc, Mutex};
 
-static HEADER: &'static str = concat!(
-    "A really awesome game ",
-    env!("CARGO_PKG_VERSION_MAJOR"), ".",
-    env!("CARGO_PKG_VERSION_MINOR"), ".",
-    env!("CARGO_PKG_VERSION_PATCH"), "\n");
+static HEADER: &'static str = concat!("A really awesome game ", version!(), "\n");
 
 // There is only one type of error in this module, a foreign error from the
 // log crate.
@@ -31,14 +27,14 @@ enum LoggerType<T> where T: Write {
 struct Logger<T> where T: Write {
     output: Arc<Mutex<T>>,
     init_time: u64,
-    level: LogLevel
+    level: LogLevelFilter
 }
 
 impl<T> Logger<T> where T: Write {
     /// Attempts to create a logger that writes to an object of type `T`. In
     /// case it cannot write, we return a `LoggerType<T>` to provide a fallback
     /// to a `LoggerType<Stderr>`.
-    fn new(mut output: T, level: LogLevel) -> LoggerType<T> {
+    fn new(mut output: T, level: LogLevelFilter) -> LoggerType<T> {
         // Try to write the header to the log
         match write!(&mut output, "{}", HEADER) {
             // Success. Let's create a normal log.
@@ -99,11 +95,12 @@ impl<T> log::Log for Logger<T> where T: Write {
     }
 }
 
-/// Initializes a global logging system that can allow
-pub fn init() -> Result<()> {
-    Ok(try!(log::set_logger(| level | {
-        level.set(LogLevelFilter::Trace);
-        match Logger::new(stderr(), LogLevel::Trace) {
+/// Initializes a global logging system that lets through messages up to
+/// `level`.
+pub fn init(level: LogLevelFilter) -> Result<()> {
+    Ok(try!(log::set_logger(| max_level | {
+        max_level.set(level);
+        match Logger::new(stderr(), level) {
             LoggerType::Normal(l) => Box::new(l),
             LoggerType::Stderr(l) => Box::new(l)
         }
@@ -2,63 +2,347 @@
 extern crate error_chain;
 #[macro_use]
 extern crate log;
-#[macro_use]
-extern crate time;
-#[macro_use]
-extern crate vulkano;
-extern crate vulkano_win;
-extern crate winit;
 extern crate cgmath;
 extern crate collada;
+extern crate changeme;
+extern crate winit;
 
+mod cli;
+
+use cgmath::{InnerSpace, Quaternion, Rad, Rotation, Rotation3, Vector3};
+use changeme::{logger, render};
+use changeme::config::Config;
+use changeme::core::clock::Clock;
+use changeme::core::game_loop::{Control, Game, GameLoop};
+use changeme::core::rng::Rng;
+use changeme::input::{Bindings, Change, Input};
+use changeme::replay::{self, Player, Recorder, Recording};
+use changeme::render::Renderer;
+use changeme::resource::Resources;
+use changeme::scene::{self, ActiveCamera, Bounds, Camera, Light, Model, PreviousWorldTransform, Scene, Transform, WorldTransform};
+use changeme::scene::schedule::{Access, Schedule, System, SystemScene};
+use changeme::scene::serialize::Registry;
 use collada::document::ColladaDocument;
-use render::Renderer;
-use resource::ModelData;
-use std::path::Path;
-use vulkano::buffer::{BufferUsage, CpuAccessibleBuffer};
+use std::time::{Duration, Instant};
+
+error_chain! {
+    links {
+        Config(::changeme::config::Error, ::changeme::config::ErrorKind);
+        Replay(::changeme::replay::Error, ::changeme::replay::ErrorKind);
+    }
+}
 
-mod core;
-mod logger;
-mod render;
-mod resource;
+/// Simulation ticks per second.
+const TICK_RATE: f64 = 60.0;
 
-static COLLADA_FILE: &'static str = "/home/yutoo/monkey.dae";
+/// How fast the camera flies, in units per second.
+const FLY_SPEED: f32 = 2.0;
 
-error_chain!{}
+/// Where the input of each tick comes from.
+enum Mode {
+    Live,
+    Record(Recorder),
+    Replay(Player),
+}
+
+/// Everything the game loop drives.
+struct App {
+    /// `None` when running headless.
+    renderer: Option<render::Vulkan>,
+    /// Holds the clock, the input and the random number generator as
+    /// globals, so systems can use them.
+    scene: Scene,
+    schedule: Schedule,
+    /// Input changes waiting for the next tick.
+    pending: Vec<Change>,
+    mode: Mode,
+    /// Saves scenes for hashing while recording or verifying.
+    registry: Registry,
+    /// Frames to run before exiting, if limited.
+    max_frames: Option<u64>,
+}
 
-fn run() -> Result<()> {
-    let mut renderer = render::Vulkan::new();
+impl Game for App {
+    type Error = Error;
 
-    /// Load vertex data
-    let doc = match ColladaDocument::from_path(Path::new(COLLADA_FILE)) {
-        Ok(file) => file,
-        Err(e) => { println!("{}", e); return Ok(()) }
-    };
-    let vertex_buffer = CpuAccessibleBuffer::from_iter(&renderer.device, &BufferUsage::all(), Some(renderer.queue.family()),
-                                                       doc.vertices().iter().cloned())
-                                            .expect("failed to create vertex buffer");
-    let normal_buffer = CpuAccessibleBuffer::from_iter(&renderer.device, &BufferUsage::all(), Some(renderer.queue.family()),
-                                                       doc.normals().iter().cloned())
-                                            .expect("failed to create normals buffer");
-    let index_buffer = CpuAccessibleBuffer::from_iter(&renderer.device, &BufferUsage::all(), Some(renderer.queue.family()),
-                                                      doc.indices().iter().cloned())
-                                           .expect("failed to create index buffer");
-
-    renderer.set_draw_buffers(vertex_buffer, normal_buffer, index_buffer);
-
-    loop {
+    fn events(&mut self) -> Result<Control> {
+        let frames = self.scene.global::<Clock>().map_or(0, Clock::frames);
+        if self.max_frames.map_or(false, |max_frames| frames >= max_frames) {
+            info!("Ran {} frames", frames);
+            return Ok(Control::Exit);
+        }
+        if let Some(ref mut renderer) = self.renderer {
+            for event in renderer.poll_events() {
+                match event {
+                    ::winit::Event::Closed => {
+                        info!("Window closed");
+                        return Ok(Control::Exit);
+                    },
+                    // The camera picks up the new aspect ratio when drawing.
+                    ::winit::Event::Resized(width, height) => renderer.resize(width, height),
+                    // Held keys are released through the input changes.
+                    ::winit::Event::Focused(focused) => debug!("Window {}", if focused { "focused" } else { "lost focus" }),
+                    _ => (),
+                }
+                // A replay ignores live input.
+                if let Mode::Replay(_) = self.mode {
+                    continue;
+                }
+                self.pending.extend(Change::from_event(&event));
+            }
+        }
+        Ok(Control::Continue)
+    }
+
+    fn update(&mut self, dt: f64) -> Result<Control> {
+        // Input is handed to the simulation per tick rather than per frame,
+        // so every press is seen by exactly one tick and a replay can feed
+        // the same input to the same tick.
+        let changes = match self.mode {
+            Mode::Replay(ref mut player) => match player.next_input() {
+                Some(changes) => changes,
+                None => {
+                    info!("Replay finished after {} ticks", player.ticks());
+                    try!(player.verify_end());
+                    return Ok(Control::Exit);
+                },
+            },
+            _ => ::std::mem::replace(&mut self.pending, Vec::new()),
+        };
+        {
+            let input = self.scene.global_mut::<Input>().expect("Input is a global of the scene");
+            input.begin_frame();
+            for change in changes.iter() {
+                input.apply(change);
+            }
+            if input.action_pressed("quit") {
+                info!("Quit");
+                match self.mode {
+                    Mode::Record(ref mut recorder) => recorder.record_quit(changes),
+                    Mode::Replay(ref player) => try!(player.verify_quit()),
+                    Mode::Live => (),
+                }
+                return Ok(Control::Exit);
+            }
+        }
+
+        self.scene.global_mut::<Clock>().expect("The clock is a global of the scene").tick(dt);
+        try!(self.schedule.run(&mut self.scene).chain_err(|| "could not run systems"));
+
+        match self.mode {
+            Mode::Record(ref mut recorder) => {
+                let hash = if recorder.wants_hash() { Some(try!(replay::hash_state(&self.registry, &self.scene))) } else { None };
+                recorder.record(changes, hash);
+            },
+            Mode::Replay(ref player) if player.wants_hash() => try!(player.verify(try!(replay::hash_state(&self.registry, &self.scene)))),
+            _ => (),
+        }
+        Ok(Control::Continue)
+    }
+
+    fn render(&mut self, alpha: f64) -> Result<()> {
+        if let Some(clock) = self.scene.global_mut::<Clock>() {
+            clock.frame(Instant::now());
+        }
+        let renderer = match self.renderer {
+            Some(ref mut renderer) if !renderer.is_minimized() => renderer,
+            _ => {
+                // Headless or minimized, nothing to draw. Don't spin waiting
+                // for the next tick.
+                ::std::thread::sleep(Duration::from_millis(1));
+                return Ok(());
+            },
+        };
+
+        if let Some((view, proj)) = scene::camera::matrices(&self.scene, renderer.aspect_ratio()) {
+            renderer.set_camera(view, proj);
+        }
+        renderer.set_lights(scene::light::gather(&self.scene));
+        for (_, (model, world, previous, bounds)) in self.scene.query::<(&Model, &WorldTransform, Option<&PreviousWorldTransform>, Option<&Bounds>)>() {
+            let world = scene::transform::interpolate(previous, world, alpha as f32);
+            renderer.draw(&model.path, &model.mesh, world, bounds.map(|bounds| &bounds.0));
+        }
         renderer.render();
+        Ok(())
     }
-    Ok(())
+}
+
+/// Flies the active camera with the move axes and turns it with the look
+/// axes.
+fn fly(scene: &mut SystemScene) {
+    let (move_x, move_y, look_x, look_y) = match scene.global::<Input>() {
+        Some(input) => (input.axis("move_x"), input.axis("move_y"), input.axis("look_x"), input.axis("look_y")),
+        None => return,
+    };
+    if move_x == 0.0 && move_y == 0.0 && look_x == 0.0 && look_y == 0.0 {
+        return;
+    }
+    let dt = scene.global::<Clock>().map_or(0.0, Clock::delta) as f32;
+
+    for (_, (transform, _)) in scene.query::<(&mut Transform, &ActiveCamera)>() {
+        // Yaw around the world's up axis, pitch around the camera's side.
+        let yaw = Quaternion::from_angle_y(Rad(-look_x as f32));
+        let pitch = Quaternion::from_angle_x(Rad(-look_y as f32));
+        let rotation = (yaw * transform.rotation() * pitch).normalize();
+        let step = rotation.rotate_vector(Vector3::new(move_x as f32, 0.0, -move_y as f32)) * FLY_SPEED * dt;
+        let translation = transform.translation() + step;
+        transform.set_rotation(rotation);
+        transform.set_translation(translation);
+    }
+}
+
+/// A scene with the configured model, a camera looking at it and some light.
+fn model_scene(config: &Config, resources: &mut Resources) -> Result<Scene> {
+    let mut scene = Scene::new();
+
+    // Load the model and its node hierarchy
+    let path = &config.assets.model;
+    let doc = try!(ColladaDocument::from_path(path)
+                       .map_err(|e| Error::from(format!("could not open model '{}': {}", path.display(), e))));
+    let model = try!(scene::import::import_collada(&mut scene, resources, &path.to_string_lossy(), &doc)
+                         .chain_err(|| "could not import model"));
+    if let Some(transform) = scene.get_mut::<Transform>(model) {
+        transform.set_scale(::cgmath::Vector3::new(0.5, 0.5, 0.5));
+    }
+
+    let camera = scene.spawn();
+    scene.insert(camera, Transform::looking_at(::cgmath::Point3::new(0.3, 0.3, 1.0),
+                                               ::cgmath::Point3::new(0.0, 0.0, 0.0),
+                                               ::cgmath::Vector3::new(0.0, 1.0, 0.0)));
+    scene.insert(camera, Camera::perspective(::cgmath::Rad(::std::f32::consts::FRAC_PI_2), 0.01, 100.0));
+    scene.set_active_camera(camera);
+
+    let sun = scene.spawn();
+    scene.insert(sun, Transform::looking_at(::cgmath::Point3::new(0.0, 0.0, 1.0),
+                                            ::cgmath::Point3::new(0.0, 0.0, 0.0),
+                                            ::cgmath::Vector3::new(0.0, 1.0, 0.0)));
+    scene.insert(sun, Light::directional(::cgmath::Vector3::new(1.0, 1.0, 1.0), 1.0));
+    let sky = scene.spawn();
+    scene.insert(sky, Light::ambient(::cgmath::Vector3::new(1.0, 1.0, 1.0), 0.2));
+
+    Ok(scene)
+}
+
+fn run(config: &Config, args: &cli::Args) -> Result<()> {
+    let renderer = if args.headless {
+        info!("Running headless");
+        None
+    } else {
+        Some(render::Vulkan::new(&config.window, &config.graphics))
+    };
+    let mut resources = Resources::new();
+    let mut scene = match config.assets.scene {
+        Some(ref path) => try!(scene::serialize::Registry::new().load_file(path, &mut resources)
+                                   .chain_err(|| format!("could not load scene '{}'", path.display()))),
+        None => try!(model_scene(config, &mut resources)),
+    };
+
+    let mut schedule = Schedule::new();
+    try!(schedule.add_stage("pre_update").chain_err(|| "could not set up systems"));
+    try!(schedule.add_stage("update").chain_err(|| "could not set up systems"));
+    try!(schedule.add_stage("post_update").chain_err(|| "could not set up systems"));
+    try!(schedule.add_system("pre_update", System::exclusive("snapshot", scene::transform::snapshot))
+                 .chain_err(|| "could not set up systems"));
+    let fly_access = Access::new().read::<Input>().read::<Clock>().query::<(&mut Transform, &ActiveCamera)>();
+    try!(schedule.add_system("update", System::new("fly", fly_access, fly))
+                 .chain_err(|| "could not set up systems"));
+    try!(schedule.add_system("post_update", System::exclusive("propagate", scene::transform::propagate))
+                 .chain_err(|| "could not set up systems"));
+
+    // Place everything before the first frame is drawn.
+    scene::transform::propagate(&mut scene);
+
+    let recording = match args.replay {
+        Some((ref path, _)) => Some(try!(Recording::load(path).chain_err(|| format!("could not load recording '{}'", path.display())))),
+        None => None,
+    };
+    // A replay reads its input with the bindings it was recorded with.
+    let bindings = match (&recording, &config.input.bindings) {
+        (&Some(ref recording), _) => recording.bindings.clone(),
+        (&None, &Some(ref path)) => try!(Bindings::from_file(path).chain_err(|| "could not load input bindings")),
+        (&None, &None) => Bindings::default(),
+    };
+    // Randomness for the simulation, seeded so a replay draws the same
+    // numbers.
+    let rng = match recording {
+        Some(ref recording) => Rng::new(recording.seed),
+        None => args.seed.map_or_else(Rng::from_time, Rng::new),
+    };
+    let seed = rng.seed();
+    info!("Random seed {}", seed);
+    scene.insert_global(Clock::new());
+    scene.insert_global(Input::new(bindings.clone()));
+    scene.insert_global(rng);
+
+    let mut game_loop = GameLoop::new(TICK_RATE);
+    let registry = Registry::new();
+    let mode = match (recording, &args.replay, &args.record) {
+        (Some(recording), &Some((ref path, verify)), _) => {
+            let initial = try!(replay::hash_state(&registry, &scene));
+            let player = try!(Player::new(recording, game_loop.tick(), initial, verify));
+            info!("{} {}", if verify { "Verifying" } else { "Replaying" }, path.display());
+            Mode::Replay(player)
+        },
+        (_, _, &Some(ref path)) => {
+            let initial = try!(replay::hash_state(&registry, &scene));
+            Mode::Record(Recorder::new(path, Recording::new(seed, game_loop.tick(), bindings, initial)))
+        },
+        _ => Mode::Live,
+    };
+
+    let mut app = App {
+        renderer: renderer,
+        scene: scene,
+        schedule: schedule,
+        pending: Vec::new(),
+        mode: mode,
+        registry: registry,
+        max_frames: args.frames,
+    };
+    let result = game_loop.run(&mut app);
+
+    // Keep what was recorded even if the game failed, to reproduce it.
+    if let Mode::Record(ref recorder) = app.mode {
+        try!(recorder.save());
+    }
+    result
+}
+
+/// Loads the config, applying the overrides given on the command line.
+fn load_config(args: &cli::Args) -> Result<Config> {
+    let mut config = try!(Config::load(args.config.as_ref().map(|path| path.as_path())));
+    for &(ref key, ref value) in args.settings.iter() {
+        try!(config.set(key, value));
+    }
+    Ok(config)
 }
 
 fn main() {
-    // Before we do anything. Initialize the logger.
-    // This will only fail if we can't write to stderr.
-    logger::init().expect("Could not initialize logger");
+    let args = match cli::parse(::std::env::args().skip(1)) {
+        Ok(cli::Command::Run(args)) => args,
+        Ok(cli::Command::Help) => {
+            print!("{}", cli::USAGE);
+            return;
+        },
+        Ok(cli::Command::Version) => {
+            println!("changeme {}", changeme::VERSION);
+            return;
+        },
+        Err(e) => {
+            eprint!("error: {}\n\n{}", e, cli::USAGE);
+            ::std::process::exit(2);
+        },
+    };
+
+    // Before we do anything. Load the config and initialize the logger with
+    // it, falling back to logging everything if the config is broken.
+    // The logger will only fail if we can't write to stderr.
+    let config = load_config(&args);
+    let level = config.as_ref().map(|config| config.log.level()).unwrap_or(::log::LogLevelFilter::Trace);
+    logger::init(level).expect("Could not initialize logger");
 
     // Run the program, and enter the block if we get an error.
-    if let Err(ref e) = run() {
+    if let Err(ref e) = config.and_then(|config| run(&config, &args)) {
         error!("Program failed: {}", e);
 
         // Backtrace if we can. We may need RUST_BACKTRACE=1
@@ -70,4 +354,3 @@ fn main() {
         ::std::process::exit(1);
     }
 }
-
@@ -13,14 +13,78 @@
 #extension GL_ARB_shading_language_450pack : enable
 
 layout(location = 0) in vec3 v_normal;
+layout(location = 1) in vec3 v_position;
+layout(location = 2) in vec4 v_color;
 layout(location = 0) out vec4 f_color;
 
-const vec3 LIGHT = vec3(0.0, 0.0, 1.0);
+// Must match MAX_LIGHTS in render/mod.rs.
+#define MAX_LIGHTS 16
+
+#define DIRECTIONAL 0
+#define POINT 1
+#define SPOT 2
+
+struct Light {
+    // xyz: world position, w: kind
+    vec4 position;
+    // xyz: normalized direction the light shines in, w: range
+    vec4 direction;
+    // rgb: color times intensity
+    vec4 radiance;
+    // x: cosine of the inner cone angle, y: cosine of the outer one
+    vec4 cone;
+};
+
+layout(set = 0, binding = 1) uniform Lights {
+    vec4 ambient;
+    // xyz: world position of the camera
+    vec4 eye;
+    Light lights[MAX_LIGHTS];
+    uint count;
+} lights;
+
+const vec3 ALBEDO = vec3(1.0, 0.0, 0.0);
+const vec3 SPECULAR = vec3(0.5, 0.5, 0.5);
+const float SHININESS = 32.0;
+
+// Inverse square falloff, windowed to reach zero at the range.
+float attenuation(float distance, float range) {
+    float window = clamp(1.0 - pow(distance / range, 4.0), 0.0, 1.0);
+    return window * window / (distance * distance + 1.0);
+}
 
 void main() {
-    float brightness = dot(normalize(v_normal), normalize(LIGHT));
-    vec3 dark_color = vec3(0.6, 0.0, 0.0);
-    vec3 regular_color = vec3(1.0, 0.0, 0.0);
+    vec3 normal = normalize(v_normal);
+    vec3 to_eye = normalize(lights.eye.xyz - v_position);
+    vec3 albedo = ALBEDO * v_color.rgb;
+    vec3 color = lights.ambient.rgb * albedo;
+
+    for (uint i = 0u; i < min(lights.count, uint(MAX_LIGHTS)); i++) {
+        Light light = lights.lights[i];
+        int kind = int(light.position.w);
+
+        vec3 to_light;
+        float strength = 1.0;
+        if (kind == DIRECTIONAL) {
+            to_light = -light.direction.xyz;
+        } else {
+            vec3 offset = light.position.xyz - v_position;
+            float distance = length(offset);
+            to_light = offset / distance;
+            strength = attenuation(distance, light.direction.w);
+            if (kind == SPOT) {
+                strength *= smoothstep(light.cone.y, light.cone.x, dot(-to_light, light.direction.xyz));
+            }
+        }
+
+        // Blinn-Phong
+        float diffuse = max(dot(normal, to_light), 0.0);
+        float specular = 0.0;
+        if (diffuse > 0.0) {
+            specular = pow(max(dot(normal, normalize(to_light + to_eye)), 0.0), SHININESS);
+        }
+        color += light.radiance.rgb * strength * (diffuse * albedo + specular * SPECULAR);
+    }
 
-    f_color = vec4(mix(dark_color, regular_color, brightness), 1.0);
+    f_color = vec4(color, v_color.a);
 }
@@ -1,3 +1,7 @@
+use ::resource::{Mesh, ModelData};
+use ::scene::{Aabb, Frustum, LightKind};
+use ::scene::light::Lights;
+use ::std::collections::HashMap;
 use ::std::sync::Arc;
 use ::std::time::Duration;
 use ::vulkano;
@@ -31,32 +35,80 @@ mod renderpass {
 mod pipeline_layout {
     pipeline_layout!{
         set0: {
-            uniforms: UniformBuffer<::render::vs::ty::Data>
+            uniforms: UniformBuffer<::render::vs::ty::Data>,
+            lights: UniformBuffer<::render::fs::ty::Lights>
         }
     }
 }
 
 
+/// Most lights the fragment shader evaluates. Must match `fs.glsl`.
+const MAX_LIGHTS: usize = 16;
+
 pub trait Renderer {
     fn render(&mut self);
 }
 
+/// Vertex and index buffers of a mesh uploaded to the GPU.
+pub struct GpuMesh {
+    vertices: Arc<CpuAccessibleBuffer<[::core::Vertex]>>,
+    indices: Arc<CpuAccessibleBuffer<[u16]>>,
+}
+
+/// A mesh uploaded by `draw`, along with the mesh it was uploaded from and
+/// the last frame that asked for it.
+struct Upload {
+    mesh: Arc<Mesh>,
+    gpu: Arc<GpuMesh>,
+    frame: u64,
+}
+
+/// Uniforms of a single draw. Slots are allocated as the number of draws
+/// grows and reused every frame.
+struct DrawSlot {
+    uniform_buffer: Arc<CpuAccessibleBuffer<vs::ty::Data>>,
+    set: Arc<pipeline_layout::set0::Set>,
+}
+
+/// How many draws made it into a frame and how many were culled.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct FrameStats {
+    pub visible: usize,
+    pub culled: usize,
+}
+
+type Pipeline = ::vulkano::pipeline::GraphicsPipeline<::vulkano::pipeline::vertex::SingleBufferDefinition<::core::Vertex>, pipeline_layout::CustomPipeline, renderpass::CustomRenderPass>;
+
 pub struct Vulkan {
-    command_buffers: Vec<Arc<::vulkano::command_buffer::PrimaryCommandBuffer<Arc<StandardCommandPool>>>>,
+    descriptor_pool: Arc<::vulkano::descriptor::descriptor_set::DescriptorPool>,
     pub device: Arc<::vulkano::device::Device>,
+    dimensions: [u32; 2],
+    /// Size the window was resized to, applied before the next frame.
+    resized: Option<[u32; 2]>,
+    draws: Vec<(Arc<GpuMesh>, ::cgmath::Matrix4<f32>)>,
+    /// Frames rendered so far.
+    frame: u64,
     frame_buffers: Vec<Arc<::vulkano::framebuffer::Framebuffer<renderpass::CustomRenderPass>>>,
-    pipeline: Arc<::vulkano::pipeline::GraphicsPipeline<::vulkano::pipeline::vertex::TwoBuffersDefinition<::core::Vertex, ::core::Normal>, pipeline_layout::CustomPipeline, renderpass::CustomRenderPass>>,
+    frustum: Frustum,
+    last_frame: FrameStats,
+    lights: Lights,
+    lights_buffer: Arc<CpuAccessibleBuffer<fs::ty::Lights>>,
+    meshes: HashMap<String, Upload>,
+    pipeline: Arc<Pipeline>,
+    pipeline_layout: Arc<pipeline_layout::CustomPipeline>,
+    proj: ::cgmath::Matrix4<f32>,
     pub queue: Arc<::vulkano::device::Queue>,
     renderpass: Arc<renderpass::CustomRenderPass>,
-    set: Arc<pipeline_layout::set0::Set>,
+    slots: Vec<DrawSlot>,
+    stats: FrameStats,
     submissions: Vec<Arc<::vulkano::command_buffer::Submission>>,
     swapchain: Arc<::vulkano::swapchain::Swapchain>,
-    uniform_buffer: Arc<CpuAccessibleBuffer<vs::ty::Data>>,
+    view: ::cgmath::Matrix4<f32>,
     window: ::vulkano_win::Window,
 }
 
 impl Vulkan {
-    pub fn new() -> Vulkan {
+    pub fn new(settings: &::config::Window, graphics: &::config::Graphics) -> Vulkan {
         // Init vulkan instance
         let instance = {
             let extensions = ::vulkano_win::required_extensions();
@@ -69,7 +121,13 @@ impl Vulkan {
         debug!("Using device: {} (type: {:?})", physical.name(), physical.ty());
 
         // Create window:
-        let window = ::winit::WindowBuilder::new().build_vk_surface(&instance).unwrap();
+        let mut builder = ::winit::WindowBuilder::new()
+                              .with_title(settings.title.clone())
+                              .with_dimensions(settings.width, settings.height);
+        if settings.fullscreen {
+            builder = builder.with_fullscreen(::winit::get_primary_monitor());
+        }
+        let window = builder.build_vk_surface(&instance).unwrap();
 
         // Choose GPU queue for draw command execution
         let queue = physical.queue_families().find(|q| q.supports_graphics() &&
@@ -92,8 +150,17 @@ impl Vulkan {
         // Create a swapchain which allocates color buffers for the screen
         let (swapchain, images) = {
             let caps = window.surface().get_capabilities(&physical).expect("failed to get surface capabilities");
-            let dimensions = caps.current_extent.unwrap_or([1280, 1024]);
-            let present = caps.present_modes.iter().next().unwrap();
+            let dimensions = caps.current_extent.unwrap_or([settings.width, settings.height]);
+            // FIFO is always supported and waits for vertical blank.
+            let present = if graphics.vsync {
+                vulkano::swapchain::PresentMode::Fifo
+            } else if caps.present_modes.mailbox {
+                vulkano::swapchain::PresentMode::Mailbox
+            } else if caps.present_modes.immediate {
+                vulkano::swapchain::PresentMode::Immediate
+            } else {
+                vulkano::swapchain::PresentMode::Fifo
+            };
             let usage = caps.supported_usage_flags;
             //let alpha = caps.supported_composite_alpha.iter().next().unwrap(); // image alpha/window transparency
             let format = caps.supported_formats[0].0;
@@ -105,30 +172,6 @@ impl Vulkan {
         };
 
 
-        let depth_buffer = vulkano::image::attachment::AttachmentImage::transient(&device, images[0].dimensions(),
-                                                                                  vulkano::format::D16Unorm).unwrap();
-
-        // Reverse Y Axis
-        // perspective(FOV, aspect ratio, near clipping plane, far clipping plane
-        let proj = ::cgmath::perspective(::cgmath::Rad(::std::f32::consts::FRAC_PI_2),
-                                       { let d = images[0].dimensions(); d[0] as f32 / d[1] as f32 }, 0.01, 100.0);
-        let view = ::cgmath::Matrix4::look_at(::cgmath::Point3::new(0.3, 0.3, 1.0),
-                                              ::cgmath::Point3::new(0.0, 0.0, 0.0),
-                                              ::cgmath::Vector3::new(0.0, 1.0, 0.0));
-        let scale = ::cgmath::Matrix4::from_scale(0.5);
-
-        let uniform_buffer = CpuAccessibleBuffer::<vs::ty::Data>::from_data(
-                                 &device, &vulkano::buffer::BufferUsage::all(), Some(queue.family()),
-                                 vs::ty::Data {
-                                     world : <::cgmath::Matrix4<f32> as ::cgmath::SquareMatrix>::identity().into(),
-                                     view : (view * scale).into(),
-                                     proj : proj.into(),
-                                 }).expect("failed to create buffer");
-
-        let vs = vs::Shader::load(&device).expect("failed to create shader module");
-        let fs = fs::Shader::load(&device).expect("failed to create shader module");
-
-
         let renderpass = renderpass::CustomRenderPass::new(&device, &renderpass::Formats {
             color: (images[0].format(), 1),
             depth: (vulkano::format::D16Unorm, 1)
@@ -137,85 +180,178 @@ impl Vulkan {
         let descriptor_pool = vulkano::descriptor::descriptor_set::DescriptorPool::new(&device);
 
         let pipeline_layout = pipeline_layout::CustomPipeline::new(&device).unwrap();
-        let set = pipeline_layout::set0::Set::new(&descriptor_pool, &pipeline_layout, &pipeline_layout::set0::Descriptors {
-            uniforms: &uniform_buffer
-        });
-
-        let pipeline = vulkano::pipeline::GraphicsPipeline::new(&device, vulkano::pipeline::GraphicsPipelineParams {
-            vertex_input: vulkano::pipeline::vertex::TwoBuffersDefinition::new(),
-            vertex_shader: vs.main_entry_point(),
-            input_assembly: vulkano::pipeline::input_assembly::InputAssembly::triangle_list(),
-
-            tessellation: None,
-            geometry_shader: None,
-
-            viewport: vulkano::pipeline::viewport::ViewportsState::Fixed {
-                data: vec![(
-                    vulkano::pipeline::viewport::Viewport {
-                        origin: [0.0, 0.0],
-                        depth_range: 0.0 .. 1.0,
-                        dimensions: [images[0].dimensions()[0] as f32, images[0].dimensions()[1] as f32],
-                    },
-                    vulkano::pipeline::viewport::Scissor::irrelevant()
-                )],
-            },
 
-            raster: Default::default(),
-            multisample: vulkano::pipeline::multisample::Multisample::disabled(),
-            fragment_shader: fs.main_entry_point(),
-            depth_stencil: vulkano::pipeline::depth_stencil::DepthStencil::simple_depth_test(),
-            blend: vulkano::pipeline::blend::Blend::pass_through(),
-            layout: &pipeline_layout,
-            render_pass: vulkano::framebuffer::Subpass::from(&renderpass, 0).unwrap(),
-        }).unwrap();
+        let pipeline = create_pipeline(&device, &pipeline_layout, &renderpass, images[0].dimensions());
 
-        let frame_buffers = images.iter().map(|image| {
-            let dimensions = [image.dimensions()[0], image.dimensions()[1], 1];
+        let frame_buffers = create_frame_buffers(&device, &renderpass, &images);
 
-            // The `AList` struct was generated by the render pass macro above, and contains one
-            // member for each attachment.
-            let attachments = renderpass::AList {
-                color: &image,
-                depth: &depth_buffer,
-            };
+        let dimensions = images[0].dimensions();
+        // The view and projection are filled in every frame from the active camera.
+        let identity = <::cgmath::Matrix4<f32> as ::cgmath::SquareMatrix>::identity();
 
-            vulkano::framebuffer::Framebuffer::new(&renderpass, dimensions, attachments).unwrap()
-        }).collect::<Vec<_>>();
+        let empty_light = fs::ty::Light {
+            position: [0.0; 4],
+            direction: [0.0; 4],
+            radiance: [0.0; 4],
+            cone: [0.0; 4],
+        };
+        let lights_buffer = CpuAccessibleBuffer::<fs::ty::Lights>::from_data(
+                                &device, &vulkano::buffer::BufferUsage::all(), Some(queue.family()),
+                                fs::ty::Lights {
+                                    ambient: [0.0; 4],
+                                    eye: [0.0; 4],
+                                    lights: [empty_light; MAX_LIGHTS],
+                                    count: 0,
+                                }).expect("failed to create buffer");
 
         Vulkan {
-            command_buffers: Vec::with_capacity(0),
+            descriptor_pool: descriptor_pool,
             device: device,
+            dimensions: [dimensions[0], dimensions[1]],
+            draws: Vec::new(),
+            frame: 0,
             frame_buffers: frame_buffers,
+            frustum: Frustum::from_matrix(&identity),
+            last_frame: FrameStats::default(),
+            lights: Lights { ambient: ::cgmath::Vector3::new(0.0, 0.0, 0.0), lights: Vec::new() },
+            lights_buffer: lights_buffer,
+            meshes: HashMap::new(),
             pipeline: pipeline,
+            pipeline_layout: pipeline_layout,
+            proj: identity,
             queue: queue,
             renderpass: renderpass,
-            set: set,
+            resized: None,
+            slots: Vec::new(),
+            stats: FrameStats::default(),
             submissions: Vec::new(),
             swapchain: swapchain,
-            uniform_buffer: uniform_buffer,
+            view: identity,
             window: window,
         }
     }
 
-    pub fn set_draw_buffers(&mut self, vertex_buffer: Arc<CpuAccessibleBuffer<[::core::Vertex]>>,
-                                       normals_buffer: Arc<CpuAccessibleBuffer<[::core::Normal]>>,
-                                       index_buffer: Arc<CpuAccessibleBuffer<[u16]>>) {
-        let command_buffers = self.frame_buffers.iter().map(|frame_buffer| {
-            vulkano::command_buffer::PrimaryCommandBufferBuilder::new(&self.device, self.queue.family())
-                // Enter render pass
-                .draw_inline(&self.renderpass, &frame_buffer, renderpass::ClearValues {
-                     color: [0.0, 0.0, 1.0, 1.0],
-                     depth: 1.0,
-                 })
-                // Add draw command
-                .draw_indexed(&self.pipeline, (&vertex_buffer, &normals_buffer), &index_buffer,
-                              &vulkano::command_buffer::DynamicState::none(), &self.set, &())
-                // Leave render pass
-                .draw_end()
-                .build()
-        }).collect::<Vec<_>>();
-
-        self.command_buffers = command_buffers;
+    /// Copies a mesh into GPU buffers.
+    pub fn upload(&self, model: &ModelData) -> Arc<GpuMesh> {
+        let usage = vulkano::buffer::BufferUsage::all();
+        let vertices = CpuAccessibleBuffer::from_iter(&self.device, &usage, Some(self.queue.family()),
+                                                      model.vertices().iter().cloned())
+                                           .expect("failed to create vertex buffer");
+        let indices = CpuAccessibleBuffer::from_iter(&self.device, &usage, Some(self.queue.family()),
+                                                     model.indices().iter().cloned())
+                                          .expect("failed to create index buffer");
+
+        Arc::new(GpuMesh {
+            vertices: vertices,
+            indices: indices,
+        })
+    }
+
+    /// Queues a mesh to be drawn with the given world matrix on the next
+    /// `render`. Meshes are uploaded the first time their key is seen, and
+    /// again whenever the key holds a different mesh, e.g. after a reload.
+    /// Uploads no draw asked for during a frame are freed.
+    ///
+    /// If the local space `bounds` of the mesh are given and lie outside the
+    /// camera frustum, the draw is culled.
+    pub fn draw(&mut self, key: &str, mesh: &Arc<Mesh>, world: ::cgmath::Matrix4<f32>, bounds: Option<&Aabb>) {
+        if let Some(bounds) = bounds {
+            if !self.frustum.intersects_aabb(&bounds.transform(&world)) {
+                self.stats.culled += 1;
+                // Keep it around, it will likely be back in view soon.
+                if let Some(upload) = self.meshes.get_mut(key) {
+                    upload.frame = self.frame;
+                }
+                return;
+            }
+        }
+        self.stats.visible += 1;
+
+        if self.meshes.get(key).map_or(true, |upload| !Arc::ptr_eq(&upload.mesh, mesh)) {
+            debug!("Uploading mesh {}", key);
+            let gpu = self.upload(&**mesh);
+            self.meshes.insert(key.to_owned(), Upload { mesh: mesh.clone(), gpu: gpu, frame: self.frame });
+        }
+        let upload = self.meshes.get_mut(key).expect("Mesh was just uploaded");
+        upload.frame = self.frame;
+        self.draws.push((upload.gpu.clone(), world));
+    }
+
+    /// Sets the view and projection matrices, usually from the active camera.
+    /// Draws queued afterwards are culled against the new frustum.
+    pub fn set_camera(&mut self, view: ::cgmath::Matrix4<f32>, proj: ::cgmath::Matrix4<f32>) {
+        self.view = view;
+        self.proj = proj;
+        self.frustum = Frustum::from_matrix(&(proj * view));
+    }
+
+    /// Sets the lights the next frames are lit with, usually gathered from
+    /// the scene with `scene::light::gather`. Only the first `MAX_LIGHTS`
+    /// non-ambient lights are used.
+    pub fn set_lights(&mut self, lights: Lights) {
+        if lights.lights.len() > MAX_LIGHTS && lights.lights.len() != self.lights.lights.len() {
+            warn!("{} lights in the scene, only the first {} are used", lights.lights.len(), MAX_LIGHTS);
+        }
+        self.lights = lights;
+    }
+
+    /// Culling counts of the last rendered frame.
+    pub fn last_frame(&self) -> FrameStats {
+        self.last_frame
+    }
+
+    /// Window events that arrived since the last call.
+    pub fn poll_events(&mut self) -> Vec<::winit::Event> {
+        self.window.window().poll_events().collect()
+    }
+
+    /// Width / height of the swapchain images, for camera projections.
+    pub fn aspect_ratio(&self) -> f32 {
+        self.dimensions[0] as f32 / self.dimensions[1] as f32
+    }
+
+    /// Resizes the swapchain to the window's new size before the next frame.
+    /// A size of zero means the window was minimized, and nothing is drawn
+    /// until it is resized again.
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.resized = Some([width, height]);
+    }
+
+    pub fn is_minimized(&self) -> bool {
+        let dimensions = self.resized.unwrap_or(self.dimensions);
+        dimensions[0] == 0 || dimensions[1] == 0
+    }
+
+    /// Rebuilds everything that depends on the size of the window.
+    fn recreate(&mut self, dimensions: [u32; 2]) {
+        let (swapchain, images) = self.swapchain.recreate_with_dimensions(dimensions).expect("failed to recreate swapchain");
+        self.pipeline = create_pipeline(&self.device, &self.pipeline_layout, &self.renderpass, images[0].dimensions());
+        self.frame_buffers = create_frame_buffers(&self.device, &self.renderpass, &images);
+        self.swapchain = swapchain;
+        self.dimensions = [images[0].dimensions()[0], images[0].dimensions()[1]];
+        debug!("Swapchain resized to {}x{}", self.dimensions[0], self.dimensions[1]);
+    }
+
+    /// Makes sure there is a uniform buffer and descriptor set for `count` draws.
+    fn reserve_slots(&mut self, count: usize) {
+        while self.slots.len() < count {
+            let uniform_buffer = CpuAccessibleBuffer::<vs::ty::Data>::from_data(
+                                     &self.device, &vulkano::buffer::BufferUsage::all(), Some(self.queue.family()),
+                                     vs::ty::Data {
+                                         world : <::cgmath::Matrix4<f32> as ::cgmath::SquareMatrix>::identity().into(),
+                                         view : self.view.into(),
+                                         proj : self.proj.into(),
+                                     }).expect("failed to create buffer");
+            let set = pipeline_layout::set0::Set::new(&self.descriptor_pool, &self.pipeline_layout, &pipeline_layout::set0::Descriptors {
+                uniforms: &uniform_buffer,
+                lights: &self.lights_buffer,
+            });
+
+            self.slots.push(DrawSlot {
+                uniform_buffer: uniform_buffer,
+                set: set,
+            });
+        }
     }
 }
 
@@ -224,30 +360,141 @@ impl Renderer for Vulkan {
         // Clearing the old submissions by keeping alive only the ones whose destructor would block.
         self.submissions.retain(|s| s.destroying_would_block());
 
-        /*
-        {
-            // aquiring write lock for the uniform buffer
-            let mut buffer_content = self.uniform_buffer.write(Duration::new(1, 0)).unwrap();
+        // There is nothing to present to while minimized.
+        if self.is_minimized() {
+            self.draws.clear();
+            self.stats = FrameStats::default();
+            return;
+        }
+        if let Some(dimensions) = self.resized.take() {
+            self.recreate(dimensions);
+        }
 
-            let rotation = ::cgmath::Matrix3::from_angle_y(::cgmath::Rad(::time::precise_time_ns() as f32 * 0.000000001));
+        let draws = ::std::mem::replace(&mut self.draws, Vec::new());
+        let frame = self.frame;
+        self.meshes.retain(|_, upload| upload.frame == frame);
+        self.frame += 1;
+        self.last_frame = ::std::mem::replace(&mut self.stats, FrameStats::default());
+        trace!("Frame: {} visible, {} culled", self.last_frame.visible, self.last_frame.culled);
+        self.reserve_slots(draws.len());
+        for (&(_, world), slot) in draws.iter().zip(self.slots.iter()) {
+            let mut buffer_content = slot.uniform_buffer.write(Duration::new(1, 0)).expect("failed to lock uniform buffer");
+            buffer_content.world = world.into();
+            buffer_content.view = self.view.into();
+            buffer_content.proj = self.proj.into();
+        }
 
-            // since write lock implementd Deref and DerefMut traits,
-            // we can update content directly
-            buffer_content.world = ::cgmath::Matrix4::from(rotation).into();
+        {
+            let mut buffer_content = self.lights_buffer.write(Duration::new(1, 0)).expect("failed to lock lights buffer");
+            let ambient = self.lights.ambient;
+            let eye = <::cgmath::Matrix4<f32> as ::cgmath::SquareMatrix>::invert(&self.view).map(|inverse| inverse.w)
+                                                                                           .unwrap_or(::cgmath::Vector4::new(0.0, 0.0, 0.0, 1.0));
+            buffer_content.ambient = [ambient.x, ambient.y, ambient.z, 0.0];
+            buffer_content.eye = eye.into();
+            buffer_content.count = ::std::cmp::min(self.lights.lights.len(), MAX_LIGHTS) as u32;
+            for (light, uniform) in self.lights.lights.iter().zip(buffer_content.lights.iter_mut()) {
+                // The shader tells kinds apart by the w of the position.
+                let (kind, range, cone) = match light.kind {
+                    LightKind::Spot { range, inner, outer } => (2.0, range, [inner.0.cos(), outer.0.cos()]),
+                    LightKind::Point { range } => (1.0, range, [0.0, 0.0]),
+                    LightKind::Directional | LightKind::Ambient => (0.0, 0.0, [0.0, 0.0]),
+                };
+                let (p, d, r) = (light.position, light.direction, light.radiance);
+                uniform.position = [p.x, p.y, p.z, kind];
+                uniform.direction = [d.x, d.y, d.z, range];
+                uniform.radiance = [r.x, r.y, r.z, 0.0];
+                uniform.cone = [cone[0], cone[1], 0.0, 0.0];
+            }
         }
-        */
 
-        let image_num = self.swapchain.acquire_next_image(Duration::from_millis(1)).unwrap();
-        self.submissions.push(vulkano::command_buffer::submit(&self.command_buffers[image_num], &self.queue).unwrap());
-        self.swapchain.present(&self.queue, image_num).unwrap();
+        let image_num = match self.swapchain.acquire_next_image(Duration::from_millis(1)) {
+            Ok(image_num) => image_num,
+            // The window changed size without telling us. Skip the frame and
+            // catch up with it before the next one.
+            Err(vulkano::swapchain::AcquireError::OutOfDate) => {
+                if let Some((width, height)) = self.window.window().get_inner_size_pixels() {
+                    self.resized = Some([width, height]);
+                }
+                return;
+            },
+            Err(e) => panic!("failed to acquire swapchain image: {:?}", e),
+        };
+
+        // Draws change every frame, so the command buffer is recorded anew.
+        let command_buffer = {
+            // Enter render pass
+            let mut builder = vulkano::command_buffer::PrimaryCommandBufferBuilder::new(&self.device, self.queue.family())
+                .draw_inline(&self.renderpass, &self.frame_buffers[image_num], renderpass::ClearValues {
+                     color: [0.0, 0.0, 1.0, 1.0],
+                     depth: 1.0,
+                 });
 
-        /*
-        for ev in self.window.window().poll_events() {
-            match ev {
-                ::winit::Event::Closed => return,
-                _ => ()
+            // Add a draw command per mesh
+            for (&(ref mesh, _), slot) in draws.iter().zip(self.slots.iter()) {
+                builder = builder.draw_indexed(&self.pipeline, &mesh.vertices, &mesh.indices,
+                                               &vulkano::command_buffer::DynamicState::none(), &slot.set, &());
             }
-        }
-        */
+
+            // Leave render pass
+            builder.draw_end().build()
+        };
+
+        self.submissions.push(vulkano::command_buffer::submit(&command_buffer, &self.queue).unwrap());
+        self.swapchain.present(&self.queue, image_num).unwrap();
     }
 }
+
+fn create_pipeline(device: &Arc<::vulkano::device::Device>, pipeline_layout: &Arc<pipeline_layout::CustomPipeline>,
+                   renderpass: &Arc<renderpass::CustomRenderPass>, dimensions: [u32; 2]) -> Arc<Pipeline> {
+    let vs = vs::Shader::load(device).expect("failed to create shader module");
+    let fs = fs::Shader::load(device).expect("failed to create shader module");
+
+    vulkano::pipeline::GraphicsPipeline::new(device, vulkano::pipeline::GraphicsPipelineParams {
+        vertex_input: vulkano::pipeline::vertex::SingleBufferDefinition::new(),
+        vertex_shader: vs.main_entry_point(),
+        input_assembly: vulkano::pipeline::input_assembly::InputAssembly::triangle_list(),
+
+        tessellation: None,
+        geometry_shader: None,
+
+        viewport: vulkano::pipeline::viewport::ViewportsState::Fixed {
+            data: vec![(
+                vulkano::pipeline::viewport::Viewport {
+                    origin: [0.0, 0.0],
+                    depth_range: 0.0 .. 1.0,
+                    dimensions: [dimensions[0] as f32, dimensions[1] as f32],
+                },
+                vulkano::pipeline::viewport::Scissor::irrelevant()
+            )],
+        },
+
+        raster: Default::default(),
+        multisample: vulkano::pipeline::multisample::Multisample::disabled(),
+        fragment_shader: fs.main_entry_point(),
+        depth_stencil: vulkano::pipeline::depth_stencil::DepthStencil::simple_depth_test(),
+        blend: vulkano::pipeline::blend::Blend::pass_through(),
+        layout: pipeline_layout,
+        render_pass: vulkano::framebuffer::Subpass::from(renderpass, 0).unwrap(),
+    }).unwrap()
+}
+
+/// One framebuffer per swapchain image, sharing a depth buffer.
+fn create_frame_buffers(device: &Arc<::vulkano::device::Device>, renderpass: &Arc<renderpass::CustomRenderPass>,
+                        images: &[Arc<::vulkano::image::SwapchainImage>])
+                        -> Vec<Arc<::vulkano::framebuffer::Framebuffer<renderpass::CustomRenderPass>>> {
+    let depth_buffer = vulkano::image::attachment::AttachmentImage::transient(device, images[0].dimensions(),
+                                                                              vulkano::format::D16Unorm).unwrap();
+
+    images.iter().map(|image| {
+        let dimensions = [image.dimensions()[0], image.dimensions()[1], 1];
+
+        // The `AList` struct was generated by the render pass macro above, and contains one
+        // member for each attachment.
+        let attachments = renderpass::AList {
+            color: &image,
+            depth: &depth_buffer,
+        };
+
+        vulkano::framebuffer::Framebuffer::new(renderpass, dimensions, attachments).unwrap()
+    }).collect()
+}
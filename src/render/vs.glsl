@@ -12,14 +12,17 @@
 #extension GL_ARB_separate_shader_objects : enable
 #extension GL_ARB_shading_language_450pack : enable
 
-layout(location = 0) in float pos_x;
-layout(location = 1) in float pos_y;
-layout(location = 2) in float pos_z;
-layout(location = 3) in float norm_x;
-layout(location = 4) in float norm_y;
-layout(location = 5) in float norm_z;
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec3 normal;
+layout(location = 2) in vec2 uv;
+layout(location = 3) in vec4 tangent;
+layout(location = 4) in vec4 color;
 
 layout(location = 0) out vec3 v_normal;
+layout(location = 1) out vec3 v_position;
+layout(location = 2) out vec4 v_color;
+layout(location = 3) out vec2 v_uv;
+layout(location = 4) out vec4 v_tangent;
 
 layout(set = 0, binding = 0) uniform Data {
     mat4 world;
@@ -28,7 +31,12 @@ layout(set = 0, binding = 0) uniform Data {
 } uniforms;
 
 void main() {
-    mat4 worldview = uniforms.view * uniforms.world;
-    v_normal = transpose(inverse(mat3(worldview))) * vec3(norm_x, norm_y, norm_z);
-    gl_Position = uniforms.proj * worldview * vec4(pos_x, pos_y, pos_z, 1.0);
+    // Lighting is done in world space.
+    vec4 world_position = uniforms.world * vec4(position, 1.0);
+    v_position = world_position.xyz;
+    v_normal = transpose(inverse(mat3(uniforms.world))) * normal;
+    v_tangent = vec4(mat3(uniforms.world) * tangent.xyz, tangent.w);
+    v_color = color;
+    v_uv = uv;
+    gl_Position = uniforms.proj * uniforms.view * world_position;
 }
@@ -0,0 +1,262 @@
+use ::core::clock::Clock;
+use ::core::rng::Rng;
+use ::input::{Bindings, Change, Input};
+use ::scene::Scene;
+use ::scene::serialize::Registry;
+use ::serde_json;
+use ::std::fs::File;
+use ::std::io::{Read, Write};
+use ::std::path::{Path, PathBuf};
+
+/// Version of the recording format, bumped on incompatible changes.
+pub const VERSION: u32 = 3;
+
+error_chain! {
+    links {
+        Serialize(::scene::serialize::Error, ::scene::serialize::ErrorKind);
+    }
+
+    foreign_links {
+        Io(::std::io::Error);
+        Json(::serde_json::Error);
+    }
+
+    errors {
+        UnsupportedVersion(version: u32) {
+            description("unsupported recording version")
+            display("recording version {} is not the supported version {}", version, VERSION)
+        }
+        TickMismatch(recorded: f64, current: f64) {
+            description("recording was made with a different tick length")
+            display("recording was made with ticks of {}s, but ticks are {}s", recorded, current)
+        }
+        Diverged(tick: u64, expected: u64, actual: u64) {
+            description("replay diverged from the recording")
+            display("replay diverged by tick {}: expected state {:016x}, got {:016x}", tick, expected, actual)
+        }
+        QuitMismatch(expected: Option<u64>, actual: Option<u64>) {
+            description("replay quit on another tick than the recording")
+            display("replay quit {}, but the recording quit {}", on_tick(*actual), on_tick(*expected))
+        }
+    }
+}
+
+/// Everything needed to run a session again: the seed of the random number
+/// generator, the tick length, the input bindings, and the input of every
+/// tick along with a hash of the state after it.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Recording {
+    pub version: u32,
+    pub seed: u64,
+    /// Length of a tick in seconds.
+    pub tick: f64,
+    /// Ticks between state hashes, 1 to hash every tick.
+    pub interval: u64,
+    /// The bindings input was read with, so actions and axes mean the same
+    /// on replay whatever the config says.
+    pub bindings: Bindings,
+    /// Hash of the state before the first tick.
+    pub initial: u64,
+    pub ticks: Vec<Tick>,
+    /// The tick the session was quit on, which is the last one. `None` if it
+    /// ended some other way.
+    pub quit: Option<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Tick {
+    /// Input changes applied at the start of the tick.
+    pub input: Vec<Change>,
+    /// Hash of the state at the end of the tick, on every `interval`th tick.
+    /// Missing on the tick the session was quit on, which doesn't run.
+    pub hash: Option<u64>,
+}
+
+impl Recording {
+    pub fn new(seed: u64, tick: f64, bindings: Bindings, initial: u64) -> Recording {
+        Recording {
+            version: VERSION,
+            seed: seed,
+            tick: tick,
+            interval: 1,
+            bindings: bindings,
+            initial: initial,
+            ticks: Vec::new(),
+            quit: None,
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Recording> {
+        let mut contents = String::new();
+        try!(try!(File::open(path)).read_to_string(&mut contents));
+        let recording: Recording = try!(serde_json::from_str(&contents));
+        if recording.version != VERSION {
+            bail!(ErrorKind::UnsupportedVersion(recording.version));
+        }
+        Ok(recording)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let contents = try!(serde_json::to_string(self));
+        let mut file = try!(File::create(path));
+        try!(file.write_all(contents.as_bytes()));
+        Ok(())
+    }
+}
+
+/// Records a session tick by tick, to be saved when it ends.
+pub struct Recorder {
+    path: PathBuf,
+    recording: Recording,
+}
+
+impl Recorder {
+    pub fn new(path: &Path, recording: Recording) -> Recorder {
+        Recorder {
+            path: path.to_owned(),
+            recording: recording,
+        }
+    }
+
+    /// Hashes the state only every `interval` ticks instead of every tick,
+    /// which is faster but finds divergence up to `interval - 1` ticks late.
+    pub fn hash_every(mut self, interval: u64) -> Recorder {
+        self.recording.interval = ::std::cmp::max(interval, 1);
+        self
+    }
+
+    /// Whether the tick about to be recorded needs a state hash.
+    pub fn wants_hash(&self) -> bool {
+        (self.recording.ticks.len() as u64 + 1) % self.recording.interval.max(1) == 0
+    }
+
+    /// Records a tick, with a hash if `wants_hash` asked for one.
+    pub fn record(&mut self, input: Vec<Change>, hash: Option<u64>) {
+        self.recording.ticks.push(Tick { input: input, hash: hash });
+    }
+
+    /// Records the tick the session was quit on, which ends the recording.
+    pub fn record_quit(&mut self, input: Vec<Change>) {
+        self.recording.ticks.push(Tick { input: input, hash: None });
+        self.recording.quit = Some(self.recording.ticks.len() as u64);
+    }
+
+    /// Writes the recording to its file.
+    pub fn save(&self) -> Result<()> {
+        try!(self.recording.save(&self.path));
+        info!("Recorded {} ticks to {}", self.recording.ticks.len(), self.path.display());
+        Ok(())
+    }
+}
+
+/// Plays a recording back, optionally checking that every tick ends in the
+/// same state as when it was recorded.
+pub struct Player {
+    recording: Recording,
+    next: usize,
+    verify: bool,
+}
+
+impl Player {
+    /// Fails if the recording cannot be replayed with ticks of `tick`
+    /// seconds, or, when verifying, if the scene does not start out like
+    /// the recorded one.
+    pub fn new(recording: Recording, tick: f64, initial: u64, verify: bool) -> Result<Player> {
+        if (recording.tick - tick).abs() > 1e-12 {
+            bail!(ErrorKind::TickMismatch(recording.tick, tick));
+        }
+        if verify && recording.initial != initial {
+            bail!(ErrorKind::Diverged(0, recording.initial, initial));
+        }
+        Ok(Player {
+            recording: recording,
+            next: 0,
+            verify: verify,
+        })
+    }
+
+    /// The recording being played.
+    pub fn recording(&self) -> &Recording {
+        &self.recording
+    }
+
+    /// Whether the tick just played should be verified, being one the
+    /// recording has a hash for.
+    pub fn wants_hash(&self) -> bool {
+        self.verify && self.next > 0 && self.recording.ticks[self.next - 1].hash.is_some()
+    }
+
+    /// Ticks played so far.
+    pub fn ticks(&self) -> u64 {
+        self.next as u64
+    }
+
+    /// The input of the next tick, or `None` at the end of the recording.
+    pub fn next_input(&mut self) -> Option<Vec<Change>> {
+        let input = self.recording.ticks.get(self.next).map(|tick| tick.input.clone());
+        if input.is_some() {
+            self.next += 1;
+        }
+        input
+    }
+
+    /// Compares the state after the tick just played, whose input came from
+    /// `next_input`, with the recording, if it has a hash for the tick.
+    /// Ticks count from 1, tick 0 being the initial state.
+    pub fn verify(&self, hash: u64) -> Result<()> {
+        match self.recording.ticks[self.next - 1].hash {
+            Some(expected) if expected != hash => bail!(ErrorKind::Diverged(self.next as u64, expected, hash)),
+            _ => Ok(()),
+        }
+    }
+
+    /// When verifying, checks that the recording quit on the tick just
+    /// played.
+    pub fn verify_quit(&self) -> Result<()> {
+        let tick = self.next as u64;
+        if self.verify && self.recording.quit != Some(tick) {
+            bail!(ErrorKind::QuitMismatch(self.recording.quit, Some(tick)));
+        }
+        Ok(())
+    }
+
+    /// When verifying, checks that the recording did not quit, called once
+    /// it ran out of ticks.
+    pub fn verify_end(&self) -> Result<()> {
+        if self.verify && self.recording.quit.is_some() {
+            bail!(ErrorKind::QuitMismatch(self.recording.quit, None));
+        }
+        Ok(())
+    }
+}
+
+fn on_tick(tick: Option<u64>) -> String {
+    tick.map_or("never".to_owned(), |tick| format!("on tick {}", tick))
+}
+
+/// A hash of the simulation state: everything about the scene that would be
+/// saved, and the clock, input and random number generator among its
+/// globals. Stable across runs and platforms.
+pub fn hash_state(registry: &Registry, scene: &Scene) -> Result<u64> {
+    let contents = try!(registry.save(scene));
+    let mut hash = fnv1a(FNV_OFFSET, contents.as_bytes());
+    if let Some(clock) = scene.global::<Clock>() {
+        hash = fnv1a(hash, try!(serde_json::to_string(clock)).as_bytes());
+    }
+    if let Some(input) = scene.global::<Input>() {
+        hash = fnv1a(hash, try!(serde_json::to_string(input)).as_bytes());
+    }
+    if let Some(rng) = scene.global::<Rng>() {
+        hash = fnv1a(hash, &rng.state().to_le_bytes());
+    }
+    Ok(hash)
+}
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+
+/// 64-bit FNV-1a, continuing from `hash`. Unlike the standard library's
+/// hasher it is guaranteed to never change.
+fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3))
+}
+
@@ -1,71 +1,252 @@
-use ::core::{Vertex, Normal, Index};
+use ::cgmath::{InnerSpace, Vector2, Vector3};
+use ::core::{Vertex, Index};
 use ::collada::document::ColladaDocument;
+use ::std::collections::HashMap;
+use ::std::path::Path;
+use ::std::sync::Arc;
+
+error_chain! {
+    errors {
+        Collada(path: String, reason: &'static str) {
+            description("could not load COLLADA file")
+            display("could not load COLLADA file '{}': {}", path, reason)
+        }
+        MissingGeometry(path: String) {
+            description("geometry not found")
+            display("geometry not found: '{}'", path)
+        }
+        TooManyVertices(geometry: String) {
+            description("too many vertices")
+            display("geometry '{}' has more vertices than 16 bit indices can address", geometry)
+        }
+        UnsupportedShape(geometry: String) {
+            description("unsupported shape")
+            display("geometry '{}' has shapes other than triangles", geometry)
+        }
+    }
+}
 
 pub trait Resource {}
 
 pub trait ModelData: Resource {
     fn vertices(&self) -> Box<Vec<Vertex>>;
-    fn normals(&self) -> Box<Vec<Normal>>;
     fn indices(&self) -> Box<Vec<u16>>;
 }
 
 impl Resource for ColladaDocument {}
 
+/// Every geometry of the document as one model.
 impl ModelData for ColladaDocument {
     fn vertices(&self) -> Box<Vec<Vertex>> {
-        let obj_set = self.get_obj_set().expect("ObjectSet in Collada file not found.");
-
-        // Map collada lib vertices to vulkano/our vertices
-        // We have to collect these iterators because from_iter requires the ExactSizeIterator trait
-        let vertex_buffer = obj_set.objects.iter()
-                                           .flat_map(|obj| obj.vertices.iter())
-                                           .map(|vert| ::cgmath::Vector3::new(vert.x as f32,
-                                                                              vert.y as f32,
-                                                                              vert.z as f32))
-                                           .map(|vec3| Vertex(vec3))
+        let vertices = collada_meshes(self).into_iter()
+                                           .flat_map(|mesh| mesh.vertices.into_iter())
                                            .collect::<Vec<Vertex>>();
+        Box::new(vertices)
+    }
+    fn indices(&self) -> Box<Vec<u16>> {
+        // Indices of each geometry are shifted past the vertices of the previous ones.
+        let mut index_buffer = Vec::new();
+        let mut offset = 0;
+        for mesh in collada_meshes(self) {
+            index_buffer.extend(mesh.indices.iter().map(|&index| index + offset));
+            offset += mesh.vertices.len() as Index;
+        }
+        Box::new(index_buffer)
+    }
+}
+
+/// The geometries of the document that fit in one model. Those that fail to
+/// load, or whose vertices wouldn't be addressable anymore, are skipped.
+fn collada_meshes(doc: &ColladaDocument) -> Vec<Mesh> {
+    let obj_set = doc.get_obj_set().expect("ObjectSet in Collada file not found.");
+    let mut meshes = Vec::new();
+    let mut count = 0;
+    for obj in obj_set.objects.iter() {
+        match Mesh::from_object(obj) {
+            Ok(ref mesh) if count + mesh.vertices.len() > Index::max_value() as usize + 1 => {
+                warn!("Skipping geometry '{}', the model would have too many vertices", obj.id);
+            },
+            Ok(mesh) => {
+                count += mesh.vertices.len();
+                meshes.push(mesh);
+            },
+            Err(e) => warn!("Skipping a geometry: {}", e),
+        }
+    }
+    meshes
+}
+
+/// Geometry of a single COLLADA `<geometry>`, ready to be drawn from one
+/// interleaved vertex buffer.
+#[derive(Clone, Debug)]
+pub struct Mesh {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<Index>,
+}
+
+impl Mesh {
+    /// Fails if the geometry has shapes other than triangles, or more
+    /// distinct corners than an `Index` can address.
+    pub fn from_object(obj: &::collada::Object) -> Result<Mesh> {
+        // COLLADA indexes positions, texture coordinates and normals
+        // separately. Every distinct combination a corner uses becomes a vertex.
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        let mut corners = HashMap::new();
+        for geo in obj.geometry.iter() {
+            for &shape in geo.shapes.iter() {
+                match shape {
+                    ::collada::Shape::Triangle(u0, u1, u2) => {
+                        for &corner in [u0, u1, u2].iter() {
+                            let index = *corners.entry(corner).or_insert_with(|| {
+                                vertices.push(corner_vertex(obj, corner));
+                                vertices.len() - 1
+                            });
+                            if index > Index::max_value() as usize {
+                                bail!(ErrorKind::TooManyVertices(obj.id.clone()));
+                            }
+                            indices.push(index as Index);
+                        }
+                    },
+                    _ => bail!(ErrorKind::UnsupportedShape(obj.id.clone())),
+                }
+            }
+        }
+
+        fill_normals(&mut vertices, &indices);
+        fill_tangents(&mut vertices, &indices);
+        Ok(Mesh {
+            vertices: vertices,
+            indices: indices,
+        })
+    }
+}
 
-        return Box::new(vertex_buffer);
+fn corner_vertex(obj: &::collada::Object, corner: ::collada::VTNIndex) -> Vertex {
+    let (vertex_index, texture_index, normal_index) = corner;
+    let vert = &obj.vertices[vertex_index];
+    let mut vertex = Vertex::new(Vector3::new(vert.x as f32, vert.y as f32, vert.z as f32));
+    if let Some(uv) = texture_index.and_then(|i| obj.tex_vertices.get(i)) {
+        vertex.uv = Vector2::new(uv.x as f32, uv.y as f32);
     }
-    fn normals(&self) -> Box<Vec<Normal>> {
-        let obj_set = self.get_obj_set().expect("ObjectSet in Collada file not found.");
+    if let Some(norm) = normal_index.and_then(|i| obj.normals.get(i)) {
+        vertex.normal = Vector3::new(norm.x as f32, norm.y as f32, norm.z as f32);
+    }
+    vertex
+}
 
-        // Map collada lib vertices to vulkano/our vertices
-        // We have to collect these iterators because from_iter requires the ExactSizeIterator trait
-        let normal_buffer = obj_set.objects.iter()
-                                           .flat_map(|obj| obj.normals.iter())
-                                           .map(|norm| ::cgmath::Vector3::new(norm.x as f32,
-                                                                              norm.y as f32,
-                                                                              norm.z as f32))
-                                           .map(|vec3| Normal(vec3))
-                                           .collect::<Vec<Normal>>();
+/// Gives vertices the file left without a normal the average normal of the
+/// triangles around them.
+fn fill_normals(vertices: &mut [Vertex], indices: &[Index]) {
+    let missing = vertices.iter().map(|vertex| vertex.normal.magnitude2() == 0.0).collect::<Vec<_>>();
+    if !missing.iter().any(|&missing| missing) {
+        return;
+    }
 
-        return Box::new(normal_buffer);
+    for triangle in indices.chunks(3).filter(|triangle| triangle.len() == 3) {
+        let (a, b, c) = (triangle[0] as usize, triangle[1] as usize, triangle[2] as usize);
+        // Not normalized, so larger triangles weigh more.
+        let face = (vertices[b].position - vertices[a].position).cross(vertices[c].position - vertices[a].position);
+        for &corner in [a, b, c].iter() {
+            if missing[corner] {
+                vertices[corner].normal += face;
+            }
+        }
+    }
+    for (vertex, &missing) in vertices.iter_mut().zip(missing.iter()) {
+        if missing && vertex.normal.magnitude2() > 0.0 {
+            vertex.normal = vertex.normal.normalize();
+        }
+    }
+}
+
+/// Computes tangents from the texture coordinates, averaged over the
+/// triangles around each vertex and made orthogonal to its normal.
+fn fill_tangents(vertices: &mut [Vertex], indices: &[Index]) {
+    let zero = Vector3::new(0.0, 0.0, 0.0);
+    let mut tangents = vec![zero; vertices.len()];
+    let mut bitangents = vec![zero; vertices.len()];
+    for triangle in indices.chunks(3).filter(|triangle| triangle.len() == 3) {
+        let (a, b, c) = (triangle[0] as usize, triangle[1] as usize, triangle[2] as usize);
+        let (edge1, edge2) = (vertices[b].position - vertices[a].position, vertices[c].position - vertices[a].position);
+        let (duv1, duv2) = (vertices[b].uv - vertices[a].uv, vertices[c].uv - vertices[a].uv);
+        let determinant = duv1.x * duv2.y - duv2.x * duv1.y;
+        if determinant.abs() < 1e-12 {
+            continue;
+        }
+
+        let tangent = (edge1 * duv2.y - edge2 * duv1.y) / determinant;
+        let bitangent = (edge2 * duv1.x - edge1 * duv2.x) / determinant;
+        for &corner in [a, b, c].iter() {
+            tangents[corner] += tangent;
+            bitangents[corner] += bitangent;
+        }
+    }
+
+    for (i, vertex) in vertices.iter_mut().enumerate() {
+        let normal = vertex.normal;
+        let mut tangent = tangents[i] - normal * normal.dot(tangents[i]);
+        if tangent.magnitude2() < 1e-12 {
+            // No usable texture coordinates, any direction along the surface will do.
+            let axis = if normal.x.abs() < 0.9 { Vector3::unit_x() } else { Vector3::unit_y() };
+            tangent = if normal.magnitude2() > 0.0 { normal.cross(axis) } else { axis };
+        }
+        let tangent = tangent.normalize();
+        let handedness = if normal.cross(tangent).dot(bitangents[i]) < 0.0 { -1.0 } else { 1.0 };
+        vertex.tangent = tangent.extend(handedness);
+    }
+}
+
+impl Resource for Mesh {}
+
+impl ModelData for Mesh {
+    fn vertices(&self) -> Box<Vec<Vertex>> {
+        Box::new(self.vertices.clone())
     }
     fn indices(&self) -> Box<Vec<u16>> {
-        let obj_set = self.get_obj_set().expect("ObjectSet in Collada file not found.");
+        Box::new(self.indices.clone())
+    }
+}
 
-        // No iterators for tuples, so add to a new array.
-        let mut index_buffer = Vec::new();
+/// Loads resources once and shares them between everything that uses them.
+///
+/// Meshes are keyed by `<file>#<geometry id>`, which is also how components
+/// refer to them.
+pub struct Resources {
+    meshes: HashMap<String, Arc<Mesh>>,
+}
+
+impl Resources {
+    pub fn new() -> Resources {
+        Resources {
+            meshes: HashMap::new(),
+        }
+    }
+
+    /// The key a geometry of a COLLADA file is stored under.
+    pub fn mesh_key(file: &str, geometry: &str) -> String {
+        format!("{}#{}", file, geometry)
+    }
+
+    /// Registers every geometry of an already parsed COLLADA file.
+    pub fn add_collada(&mut self, file: &str, doc: &ColladaDocument) -> Result<()> {
+        let obj_set = try!(doc.get_obj_set().ok_or(ErrorKind::Collada(file.to_owned(), "no geometry library")));
         for obj in obj_set.objects.iter() {
-            for geo in obj.geometry.iter() {
-                for &shape in geo.shapes.iter() {
-                    match shape {
-                        ::collada::Shape::Triangle(u0, u1, u2) => {
-                            let (vertex_index, _, _) = u0;
-                            index_buffer.push(vertex_index as u16);
-
-                            let (vertex_index, _, _) = u1;
-                            index_buffer.push(vertex_index as u16);
-
-                            let (vertex_index, _, _) = u2;
-                            index_buffer.push(vertex_index as u16);
-                        },
-                        _ => panic!("Non-triangle shape!"),
-                    }
-                }
-            }
+            self.meshes.insert(Resources::mesh_key(file, &obj.id), Arc::new(try!(Mesh::from_object(obj))));
         }
-        return Box::new(index_buffer);
+        Ok(())
+    }
+
+    /// Returns the mesh stored under `key`, loading its COLLADA file if needed.
+    pub fn mesh(&mut self, key: &str) -> Result<Arc<Mesh>> {
+        if let Some(mesh) = self.meshes.get(key) {
+            return Ok(mesh.clone());
+        }
+
+        let file = key.split('#').next().unwrap_or(key);
+        let doc = try!(ColladaDocument::from_path(Path::new(file))
+                           .map_err(|reason| ErrorKind::Collada(file.to_owned(), reason)));
+        try!(self.add_collada(file, &doc));
+        self.meshes.get(key).cloned().ok_or(ErrorKind::MissingGeometry(key.to_owned()).into())
     }
 }
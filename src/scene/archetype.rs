@@ -0,0 +1,227 @@
+use ::std::any::{Any, TypeId};
+use ::std::cell::UnsafeCell;
+use super::Entity;
+
+/// Anything that can be attached to an entity. Components must be shareable
+/// between threads so systems can eventually run in parallel.
+pub trait Component: Any + Send + Sync {}
+
+impl<T> Component for T where T: Any + Send + Sync {}
+
+/// Type erased interface to the `Vec<T>` holding one component type of an
+/// archetype, so rows can be moved between tables without knowing `T`.
+pub trait Column: Any + Send + Sync {
+    fn len(&self) -> usize;
+
+    /// Drops the component in `row`, moving the last row into its place.
+    fn swap_remove(&mut self, row: usize);
+
+    /// Moves the component in `row` to the end of `other`, which must hold
+    /// the same type, and moves the last row into its place.
+    fn move_row(&mut self, row: usize, other: &mut Column);
+
+    /// Removes the component in `row` and hands it back boxed.
+    fn take(&mut self, row: usize) -> Box<Any + Send>;
+
+    /// Creates an empty column of the same type.
+    fn empty(&self) -> Box<Column>;
+
+    fn as_any(&self) -> &Any;
+    fn as_any_mut(&mut self) -> &mut Any;
+}
+
+impl<T> Column for Vec<T> where T: Component {
+    fn len(&self) -> usize {
+        Vec::len(self)
+    }
+
+    fn swap_remove(&mut self, row: usize) {
+        Vec::swap_remove(self, row);
+    }
+
+    fn move_row(&mut self, row: usize, other: &mut Column) {
+        let other = other.as_any_mut().downcast_mut::<Vec<T>>().expect("Moved a row between different column types");
+        other.push(Vec::swap_remove(self, row));
+    }
+
+    fn take(&mut self, row: usize) -> Box<Any + Send> {
+        Box::new(Vec::swap_remove(self, row))
+    }
+
+    fn empty(&self) -> Box<Column> {
+        Box::new(Vec::<T>::new())
+    }
+
+    fn as_any(&self) -> &Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut Any {
+        self
+    }
+}
+
+/// A table holding every entity with exactly the same set of component types.
+///
+/// Each component type is stored in its own tightly packed column, so
+/// iterating over a few components of many entities walks contiguous memory.
+/// Adding or removing a component moves the entity to another archetype.
+///
+/// Columns sit in `UnsafeCell`s so systems running in parallel can write to
+/// different columns of the same table through a shared reference.
+pub struct Archetype {
+    types: Vec<TypeId>,
+    columns: Vec<UnsafeCell<Box<Column>>>,
+    entities: Vec<Entity>,
+}
+
+// Shared access only hands out shared references to columns, through
+// `column`, or raw pointers to their elements, through `column_ptr`, whose
+// callers guarantee writes don't alias.
+unsafe impl Sync for Archetype {}
+
+impl Archetype {
+    /// Creates an empty table. `types` and `columns` must be sorted by type.
+    pub fn new(types: Vec<TypeId>, columns: Vec<Box<Column>>) -> Archetype {
+        debug_assert!(types.windows(2).all(|pair| pair[0] < pair[1]));
+        debug_assert_eq!(types.len(), columns.len());
+        Archetype {
+            types: types,
+            columns: columns.into_iter().map(UnsafeCell::new).collect(),
+            entities: Vec::new(),
+        }
+    }
+
+    /// The component types of this archetype, sorted.
+    pub fn types(&self) -> &[TypeId] {
+        &self.types
+    }
+
+    /// The entity stored in each row.
+    pub fn entities(&self) -> &[Entity] {
+        &self.entities
+    }
+
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn has(&self, ty: TypeId) -> bool {
+        self.types.binary_search(&ty).is_ok()
+    }
+
+    fn position(&self, ty: TypeId) -> Option<usize> {
+        self.types.binary_search(&ty).ok()
+    }
+
+    pub fn column<T>(&self) -> Option<&Vec<T>> where T: Component {
+        self.position(TypeId::of::<T>())
+            .and_then(move |i| unsafe { &*self.columns[i].get() }.as_any().downcast_ref::<Vec<T>>())
+    }
+
+    pub fn column_mut<T>(&mut self) -> Option<&mut Vec<T>> where T: Component {
+        match self.position(TypeId::of::<T>()) {
+            Some(i) => self.columns[i].get_mut().as_any_mut().downcast_mut::<Vec<T>>(),
+            None => None,
+        }
+    }
+
+    /// Pointer to the first component of type `T`, which may be written to
+    /// without borrowing the archetype mutably.
+    ///
+    /// The caller guarantees that while the pointer is used nothing else
+    /// reads or writes the column, and that the archetype is not changed.
+    pub unsafe fn column_ptr<T>(&self) -> Option<*mut T> where T: Component {
+        // Only the `Vec` itself is borrowed, and only shared. The elements
+        // live in its heap buffer, which the pointer may write to.
+        self.position(TypeId::of::<T>())
+            .and_then(move |i| (&*self.columns[i].get()).as_any().downcast_ref::<Vec<T>>())
+            .map(|column| column.as_ptr() as *mut T)
+    }
+
+    /// Builds an empty archetype with the same columns plus one for `T`.
+    pub fn with<T>(&self) -> Archetype where T: Component {
+        let ty = TypeId::of::<T>();
+        let at = self.types.binary_search(&ty).err().expect("Archetype already has this component");
+
+        let mut types = self.types.clone();
+        let mut columns = self.columns.iter().map(|column| unsafe { &*column.get() }.empty()).collect::<Vec<_>>();
+        types.insert(at, ty);
+        columns.insert(at, Box::new(Vec::<T>::new()));
+        Archetype::new(types, columns)
+    }
+
+    /// Builds an empty archetype with the same columns minus the one for `ty`.
+    pub fn without(&self, ty: TypeId) -> Archetype {
+        let mut types = Vec::with_capacity(self.types.len());
+        let mut columns = Vec::with_capacity(self.types.len());
+        for (&other, column) in self.types.iter().zip(self.columns.iter()) {
+            if other != ty {
+                types.push(other);
+                columns.push(unsafe { &*column.get() }.empty());
+            }
+        }
+        Archetype::new(types, columns)
+    }
+
+    /// Appends an entity whose components have already been pushed onto
+    /// every column. Returns its row.
+    pub fn push_entity(&mut self, entity: Entity) -> usize {
+        self.entities.push(entity);
+        let len = self.entities.len();
+        debug_assert!(self.columns.iter_mut().all(|column| column.get_mut().len() == len));
+        self.entities.len() - 1
+    }
+
+    /// Drops the row, returning the entity that was moved into its place.
+    pub fn remove_row(&mut self, row: usize) -> Option<Entity> {
+        for column in self.columns.iter_mut() {
+            column.get_mut().swap_remove(row);
+        }
+        self.entities.swap_remove(row);
+        self.entities.get(row).cloned()
+    }
+
+    /// Moves the row out into single-row columns, in type order, so it can be
+    /// put back later with `push_row`. Returns them and the entity that was
+    /// moved into the vacated row.
+    pub fn take_row(&mut self, row: usize) -> (Vec<Box<Column>>, Option<Entity>) {
+        let mut taken = Vec::with_capacity(self.columns.len());
+        for column in self.columns.iter_mut() {
+            let column = column.get_mut();
+            let mut single = column.empty();
+            column.move_row(row, &mut *single);
+            taken.push(single);
+        }
+        self.entities.swap_remove(row);
+        (taken, self.entities.get(row).cloned())
+    }
+
+    /// Appends an entity from the single-row columns made by `take_row` on an
+    /// archetype with the same types. Returns its row.
+    pub fn push_row(&mut self, entity: Entity, mut columns: Vec<Box<Column>>) -> usize {
+        debug_assert_eq!(columns.len(), self.columns.len());
+        for (column, taken) in self.columns.iter_mut().zip(columns.iter_mut()) {
+            taken.move_row(0, &mut **column.get_mut());
+        }
+        self.push_entity(entity)
+    }
+
+    /// Moves the row into `other`, carrying over every component both tables
+    /// share. Components `other` lacks are pushed onto `left` in type order,
+    /// and components `other` has on top of ours must be pushed by the caller
+    /// before calling `push_entity`.
+    ///
+    /// Returns the entity that was moved into the vacated row.
+    pub fn move_row(&mut self, row: usize, other: &mut Archetype, left: &mut Vec<Box<Any + Send>>) -> Option<Entity> {
+        for (ty, column) in self.types.iter().zip(self.columns.iter_mut()) {
+            let column = column.get_mut();
+            match other.position(*ty) {
+                Some(i) => column.move_row(row, &mut **other.columns[i].get_mut()),
+                None => left.push(column.take(row)),
+            }
+        }
+        self.entities.swap_remove(row);
+        self.entities.get(row).cloned()
+    }
+}
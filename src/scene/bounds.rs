@@ -0,0 +1,191 @@
+use ::cgmath::{EuclideanSpace, InnerSpace, Matrix, Matrix4, Point3, Vector3, Vector4};
+
+/// Axis aligned bounding box.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+impl Aabb {
+    pub fn new(min: Point3<f32>, max: Point3<f32>) -> Aabb {
+        Aabb { min: min, max: max }
+    }
+
+    /// The smallest box holding every point, or `None` if there are none.
+    pub fn from_points<I>(points: I) -> Option<Aabb> where I: IntoIterator<Item = Point3<f32>> {
+        let mut points = points.into_iter();
+        let first = match points.next() {
+            Some(first) => first,
+            None => return None,
+        };
+        Some(points.fold(Aabb::new(first, first), |aabb, point| aabb.union(&Aabb::new(point, point))))
+    }
+
+    pub fn center(&self) -> Point3<f32> {
+        self.min.midpoint(self.max)
+    }
+
+    /// Half the size of the box along each axis.
+    pub fn half_extents(&self) -> Vector3<f32> {
+        (self.max - self.min) * 0.5
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        let d = self.max - self.min;
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: Point3::new(self.min.x.min(other.min.x), self.min.y.min(other.min.y), self.min.z.min(other.min.z)),
+            max: Point3::new(self.max.x.max(other.max.x), self.max.y.max(other.max.y), self.max.z.max(other.max.z)),
+        }
+    }
+
+    /// Grows the box by `margin` on every side.
+    pub fn expand(&self, margin: f32) -> Aabb {
+        let margin = Vector3::new(margin, margin, margin);
+        Aabb { min: self.min + -margin, max: self.max + margin }
+    }
+
+    pub fn contains(&self, other: &Aabb) -> bool {
+        self.min.x <= other.min.x && self.min.y <= other.min.y && self.min.z <= other.min.z &&
+        self.max.x >= other.max.x && self.max.y >= other.max.y && self.max.z >= other.max.z
+    }
+
+    pub fn overlaps(&self, other: &Aabb) -> bool {
+        self.min.x <= other.max.x && self.max.x >= other.min.x &&
+        self.min.y <= other.max.y && self.max.y >= other.min.y &&
+        self.min.z <= other.max.z && self.max.z >= other.min.z
+    }
+
+    pub fn overlaps_sphere(&self, center: Point3<f32>, radius: f32) -> bool {
+        let closest = Point3::new(center.x.max(self.min.x).min(self.max.x),
+                                  center.y.max(self.min.y).min(self.max.y),
+                                  center.z.max(self.min.z).min(self.max.z));
+        (closest - center).magnitude2() <= radius * radius
+    }
+
+    /// Distance along the ray at which it enters the box, if it does so
+    /// within `max_distance`. A ray starting inside hits at 0.
+    pub fn ray_distance(&self, ray: &Ray, max_distance: f32) -> Option<f32> {
+        let mut near = 0.0f32;
+        let mut far = max_distance;
+        for axis in 0..3 {
+            let inverse = 1.0 / ray.direction[axis];
+            let mut t0 = (self.min[axis] - ray.origin[axis]) * inverse;
+            let mut t1 = (self.max[axis] - ray.origin[axis]) * inverse;
+            if t0 > t1 {
+                ::std::mem::swap(&mut t0, &mut t1);
+            }
+            // NaN from 0 * inf fails every comparison, leaving the slab open.
+            if t0 > near {
+                near = t0;
+            }
+            if t1 < far {
+                far = t1;
+            }
+            if near > far {
+                return None;
+            }
+        }
+        Some(near)
+    }
+
+    /// The box holding this one after transforming it by `matrix`.
+    pub fn transform(&self, matrix: &Matrix4<f32>) -> Aabb {
+        let center = Point3::from_homogeneous(matrix * self.center().to_homogeneous());
+        let extents = self.half_extents();
+
+        // Each new half extent is the projection of the transformed box axes.
+        let extents = Vector3::new(
+            matrix.x.x.abs() * extents.x + matrix.y.x.abs() * extents.y + matrix.z.x.abs() * extents.z,
+            matrix.x.y.abs() * extents.x + matrix.y.y.abs() * extents.y + matrix.z.y.abs() * extents.z,
+            matrix.x.z.abs() * extents.x + matrix.y.z.abs() * extents.y + matrix.z.z.abs() * extents.z);
+        Aabb { min: center + -extents, max: center + extents }
+    }
+}
+
+/// Half line starting at `origin`. `direction` does not need to be
+/// normalized, but distances are measured in multiples of it.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Ray {
+    pub origin: Point3<f32>,
+    pub direction: Vector3<f32>,
+}
+
+impl Ray {
+    pub fn new(origin: Point3<f32>, direction: Vector3<f32>) -> Ray {
+        Ray { origin: origin, direction: direction }
+    }
+
+    pub fn at(&self, distance: f32) -> Point3<f32> {
+        self.origin + self.direction * distance
+    }
+}
+
+/// Plane made of the points `p` for which `normal . p + distance == 0`. The
+/// normal points to the inside.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Plane {
+    pub normal: Vector3<f32>,
+    pub distance: f32,
+}
+
+impl Plane {
+    fn from_row(row: Vector4<f32>) -> Plane {
+        let normal = row.truncate();
+        let length = normal.magnitude();
+        Plane { normal: normal / length, distance: row.w / length }
+    }
+
+    pub fn signed_distance(&self, point: Point3<f32>) -> f32 {
+        self.normal.dot(point.to_vec()) + self.distance
+    }
+}
+
+/// The volume seen by a camera, as six inward facing planes.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Frustum {
+    pub planes: [Plane; 6],
+}
+
+impl Frustum {
+    /// Extracts the planes of a `proj * view` matrix built with cgmath's
+    /// projections, which map depth to -1..1.
+    pub fn from_matrix(matrix: &Matrix4<f32>) -> Frustum {
+        let (x, y, z, w) = (matrix.row(0), matrix.row(1), matrix.row(2), matrix.row(3));
+        Frustum {
+            planes: [
+                Plane::from_row(w + x),
+                Plane::from_row(w - x),
+                Plane::from_row(w + y),
+                Plane::from_row(w - y),
+                Plane::from_row(w + z),
+                Plane::from_row(w - z),
+            ],
+        }
+    }
+
+    /// Conservative test: may report boxes near the corners of the frustum
+    /// as visible even though they are not.
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            // The corner furthest along the normal.
+            let corner = Point3::new(if plane.normal.x >= 0.0 { aabb.max.x } else { aabb.min.x },
+                                     if plane.normal.y >= 0.0 { aabb.max.y } else { aabb.min.y },
+                                     if plane.normal.z >= 0.0 { aabb.max.z } else { aabb.min.z });
+            plane.signed_distance(corner) >= 0.0
+        })
+    }
+
+    pub fn intersects_sphere(&self, center: Point3<f32>, radius: f32) -> bool {
+        self.planes.iter().all(|plane| plane.signed_distance(center) >= -radius)
+    }
+}
+
+/// Local space bounding box of an entity. Combined with its `WorldTransform`
+/// it places the entity in the scene's `Bvh`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Bounds(pub Aabb);
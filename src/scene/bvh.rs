@@ -0,0 +1,511 @@
+use ::cgmath::Point3;
+use ::std::collections::HashMap;
+use super::{Aabb, Bounds, Entity, Frustum, Ray, Scene, WorldTransform};
+
+#[derive(Copy, Clone, Debug)]
+enum Kind {
+    /// `tight` is the exact world box of the entity. The node's own box is
+    /// fattened by the margin so small movements don't touch the tree.
+    Leaf { entity: Entity, tight: Aabb },
+    Branch { left: usize, right: usize },
+    Free,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Node {
+    aabb: Aabb,
+    parent: Option<usize>,
+    kind: Kind,
+}
+
+/// Dynamic bounding volume hierarchy over the world boxes of entities,
+/// answering "what is near here" and "what does this ray hit".
+///
+/// Leaves store a box fattened by a margin. Moving an entity only refits the
+/// tree when its box escapes the fattened one, so `sync` is cheap for scenes
+/// where most entities are still or move a little each frame.
+pub struct Bvh {
+    nodes: Vec<Node>,
+    free: Vec<usize>,
+    root: Option<usize>,
+    leaves: HashMap<Entity, usize>,
+    margin: f32,
+}
+
+impl Bvh {
+    /// `margin` is how far, in world units, an entity may move before its
+    /// leaf is reinserted.
+    pub fn new(margin: f32) -> Bvh {
+        Bvh {
+            nodes: Vec::new(),
+            free: Vec::new(),
+            root: None,
+            leaves: HashMap::new(),
+            margin: margin,
+        }
+    }
+
+    /// Number of entities in the tree.
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    /// The exact world box the entity was last updated with.
+    pub fn aabb(&self, entity: Entity) -> Option<Aabb> {
+        self.leaves.get(&entity).and_then(|&leaf| match self.nodes[leaf].kind {
+            Kind::Leaf { tight, .. } => Some(tight),
+            _ => None,
+        })
+    }
+
+    /// Brings the tree in line with the `Bounds` and `WorldTransform` of
+    /// every entity in the scene. Call after `transform::propagate`.
+    pub fn sync(&mut self, scene: &Scene) {
+        let stale = self.leaves.keys()
+                               .cloned()
+                               .filter(|&entity| !scene.has::<Bounds>(entity) || !scene.has::<WorldTransform>(entity))
+                               .collect::<Vec<_>>();
+        for entity in stale {
+            self.remove(entity);
+        }
+
+        for (entity, bounds) in scene.iter::<Bounds>() {
+            if let Some(world) = scene.get::<WorldTransform>(entity) {
+                self.update(entity, bounds.0.transform(&world.0));
+            }
+        }
+    }
+
+    /// Sets the world box of an entity, adding it to the tree if needed.
+    /// Returns true if the tree had to be restructured.
+    pub fn update(&mut self, entity: Entity, aabb: Aabb) -> bool {
+        if let Some(&leaf) = self.leaves.get(&entity) {
+            if self.nodes[leaf].aabb.contains(&aabb) {
+                self.nodes[leaf].kind = Kind::Leaf { entity: entity, tight: aabb };
+                return false;
+            }
+
+            self.remove_leaf(leaf);
+            self.nodes[leaf].aabb = aabb.expand(self.margin);
+            self.nodes[leaf].kind = Kind::Leaf { entity: entity, tight: aabb };
+            self.insert_leaf(leaf);
+            return true;
+        }
+
+        let leaf = self.allocate(Node {
+            aabb: aabb.expand(self.margin),
+            parent: None,
+            kind: Kind::Leaf { entity: entity, tight: aabb },
+        });
+        self.leaves.insert(entity, leaf);
+        self.insert_leaf(leaf);
+        true
+    }
+
+    /// Takes the entity out of the tree. Returns false if it wasn't in it.
+    pub fn remove(&mut self, entity: Entity) -> bool {
+        match self.leaves.remove(&entity) {
+            Some(leaf) => {
+                self.remove_leaf(leaf);
+                self.release(leaf);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Entities whose box overlaps `aabb`.
+    pub fn query_aabb(&self, aabb: &Aabb, out: &mut Vec<Entity>) {
+        self.traverse(|node| node.overlaps(aabb), |entity, tight| {
+            if tight.overlaps(aabb) {
+                out.push(entity);
+            }
+        });
+    }
+
+    /// Entities whose box overlaps the sphere.
+    pub fn query_sphere(&self, center: Point3<f32>, radius: f32, out: &mut Vec<Entity>) {
+        self.traverse(|node| node.overlaps_sphere(center, radius), |entity, tight| {
+            if tight.overlaps_sphere(center, radius) {
+                out.push(entity);
+            }
+        });
+    }
+
+    /// Entities whose box may be inside the frustum.
+    pub fn query_frustum(&self, frustum: &Frustum, out: &mut Vec<Entity>) {
+        self.traverse(|node| frustum.intersects_aabb(node), |entity, tight| {
+            if frustum.intersects_aabb(tight) {
+                out.push(entity);
+            }
+        });
+    }
+
+    /// Entities whose box the ray enters within `max_distance`, nearest first,
+    /// with the distance at which it does.
+    pub fn raycast(&self, ray: &Ray, max_distance: f32) -> Vec<(Entity, f32)> {
+        let mut hits = Vec::new();
+        self.traverse(|node| node.ray_distance(ray, max_distance).is_some(), |entity, tight| {
+            if let Some(distance) = tight.ray_distance(ray, max_distance) {
+                hits.push((entity, distance));
+            }
+        });
+        hits.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(::std::cmp::Ordering::Equal));
+        hits
+    }
+
+    /// Visits every leaf whose ancestors' boxes all pass `enter`.
+    fn traverse<F, G>(&self, enter: F, mut visit: G) where F: Fn(&Aabb) -> bool, G: FnMut(Entity, &Aabb) {
+        let mut stack = Vec::new();
+        stack.extend(self.root);
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if !enter(&node.aabb) {
+                continue;
+            }
+
+            match node.kind {
+                Kind::Leaf { entity, ref tight } => visit(entity, tight),
+                Kind::Branch { left, right } => {
+                    stack.push(left);
+                    stack.push(right);
+                },
+                Kind::Free => unreachable!(),
+            }
+        }
+    }
+
+    fn allocate(&mut self, node: Node) -> usize {
+        match self.free.pop() {
+            Some(index) => {
+                self.nodes[index] = node;
+                index
+            },
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        }
+    }
+
+    fn release(&mut self, index: usize) {
+        self.nodes[index].kind = Kind::Free;
+        self.nodes[index].parent = None;
+        self.free.push(index);
+    }
+
+    /// Links a detached leaf next to the sibling that grows the total surface
+    /// area of the tree the least.
+    fn insert_leaf(&mut self, leaf: usize) {
+        let root = match self.root {
+            Some(root) => root,
+            None => {
+                self.nodes[leaf].parent = None;
+                self.root = Some(leaf);
+                return;
+            }
+        };
+
+        let aabb = self.nodes[leaf].aabb;
+        let mut sibling = root;
+        while let Kind::Branch { left, right } = self.nodes[sibling].kind {
+            let area = self.nodes[sibling].aabb.surface_area();
+            let combined = self.nodes[sibling].aabb.union(&aabb).surface_area();
+
+            // Cost of pairing with this node, and the cost every child choice
+            // inherits from growing this node.
+            let cost = 2.0 * combined;
+            let inherited = 2.0 * (combined - area);
+            let child_cost = |child: usize| {
+                let node = &self.nodes[child];
+                let grown = node.aabb.union(&aabb).surface_area();
+                match node.kind {
+                    Kind::Leaf { .. } => grown + inherited,
+                    _ => grown - node.aabb.surface_area() + inherited,
+                }
+            };
+            let (left_cost, right_cost) = (child_cost(left), child_cost(right));
+
+            if cost < left_cost && cost < right_cost {
+                break;
+            }
+            sibling = if left_cost < right_cost { left } else { right };
+        }
+
+        let old_parent = self.nodes[sibling].parent;
+        let parent = self.allocate(Node {
+            aabb: self.nodes[sibling].aabb.union(&aabb),
+            parent: old_parent,
+            kind: Kind::Branch { left: sibling, right: leaf },
+        });
+        self.nodes[sibling].parent = Some(parent);
+        self.nodes[leaf].parent = Some(parent);
+
+        match old_parent {
+            Some(old_parent) => {
+                self.replace_child(old_parent, sibling, parent);
+                self.refit(old_parent);
+            },
+            None => self.root = Some(parent),
+        }
+    }
+
+    /// Unlinks a leaf from the tree without releasing it. Its sibling takes
+    /// the place of their parent.
+    fn remove_leaf(&mut self, leaf: usize) {
+        let parent = match self.nodes[leaf].parent {
+            Some(parent) => parent,
+            None => {
+                self.root = None;
+                return;
+            }
+        };
+
+        let sibling = match self.nodes[parent].kind {
+            Kind::Branch { left, right } => if left == leaf { right } else { left },
+            _ => unreachable!(),
+        };
+        let grandparent = self.nodes[parent].parent;
+        self.nodes[sibling].parent = grandparent;
+        self.nodes[leaf].parent = None;
+        self.release(parent);
+
+        match grandparent {
+            Some(grandparent) => {
+                self.replace_child(grandparent, parent, sibling);
+                self.refit(grandparent);
+            },
+            None => self.root = Some(sibling),
+        }
+    }
+
+    fn replace_child(&mut self, parent: usize, old: usize, new: usize) {
+        if let Kind::Branch { ref mut left, ref mut right } = self.nodes[parent].kind {
+            if *left == old {
+                *left = new;
+            } else {
+                *right = new;
+            }
+        }
+    }
+
+    /// Recomputes the boxes of a branch and its ancestors.
+    fn refit(&mut self, start: usize) {
+        let mut current = Some(start);
+        while let Some(index) = current {
+            if let Kind::Branch { left, right } = self.nodes[index].kind {
+                self.nodes[index].aabb = self.nodes[left].aabb.union(&self.nodes[right].aabb);
+            }
+            current = self.nodes[index].parent;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::cgmath::{Deg, Matrix4, Point3, Vector3};
+    use super::Kind;
+    use super::super::{transform, Aabb, Bounds, Bvh, Entity, Frustum, Ray, Scene, Transform, WorldTransform};
+
+    /// Xorshift, so every run makes the same boxes.
+    struct Rng(u32);
+
+    impl Rng {
+        /// Uniform in `-1..1`.
+        fn next(&mut self) -> f32 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            (self.0 as f32 / ::std::u32::MAX as f32) * 2.0 - 1.0
+        }
+
+        fn point(&mut self, scale: f32) -> Vector3<f32> {
+            Vector3::new(self.next(), self.next(), self.next()) * scale
+        }
+
+        fn aabb(&mut self) -> Aabb {
+            let center = Point3::new(0.0, 0.0, 0.0) + self.point(50.0);
+            let half = Vector3::new(self.next().abs(), self.next().abs(), self.next().abs()) * 3.0 + Vector3::new(0.1, 0.1, 0.1);
+            Aabb::new(center + -half, center + half)
+        }
+    }
+
+    fn sorted(mut entities: Vec<Entity>) -> Vec<Entity> {
+        entities.sort();
+        entities
+    }
+
+    fn sorted_hits(mut hits: Vec<(Entity, f32)>) -> Vec<(Entity, f32)> {
+        hits.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap().then(a.0.cmp(&b.0)));
+        hits
+    }
+
+    /// Checks parent links, branch boxes and the leaf map.
+    fn check_tree(bvh: &Bvh) {
+        let mut leaves = 0;
+        let mut stack = Vec::new();
+        stack.extend(bvh.root);
+        assert!(bvh.root.map_or(true, |root| bvh.nodes[root].parent.is_none()));
+        while let Some(index) = stack.pop() {
+            let node = &bvh.nodes[index];
+            match node.kind {
+                Kind::Leaf { entity, tight } => {
+                    assert_eq!(bvh.leaves.get(&entity), Some(&index));
+                    assert!(node.aabb.contains(&tight));
+                    leaves += 1;
+                },
+                Kind::Branch { left, right } => {
+                    for &child in &[left, right] {
+                        assert_eq!(bvh.nodes[child].parent, Some(index));
+                        assert!(node.aabb.contains(&bvh.nodes[child].aabb));
+                        stack.push(child);
+                    }
+                },
+                Kind::Free => panic!("free node {} is linked into the tree", index),
+            }
+        }
+        assert_eq!(leaves, bvh.len());
+    }
+
+    /// Compares every kind of query against testing each box.
+    fn check_queries(bvh: &Bvh, boxes: &[(Entity, Aabb)], rng: &mut Rng) {
+        check_tree(bvh);
+        assert_eq!(bvh.len(), boxes.len());
+        for _ in 0..20 {
+            let aabb = rng.aabb().expand(10.0);
+            let mut found = Vec::new();
+            bvh.query_aabb(&aabb, &mut found);
+            let brute = boxes.iter().filter(|&&(_, ref b)| b.overlaps(&aabb)).map(|&(entity, _)| entity).collect();
+            assert_eq!(sorted(found), sorted(brute));
+
+            let center = Point3::new(0.0, 0.0, 0.0) + rng.point(50.0);
+            let radius = rng.next().abs() * 20.0;
+            let mut found = Vec::new();
+            bvh.query_sphere(center, radius, &mut found);
+            let brute = boxes.iter().filter(|&&(_, ref b)| b.overlaps_sphere(center, radius)).map(|&(entity, _)| entity).collect();
+            assert_eq!(sorted(found), sorted(brute));
+
+            let eye = Point3::new(0.0, 0.0, 0.0) + rng.point(30.0);
+            let view = Matrix4::look_at(eye, eye + rng.point(1.0), Vector3::new(0.0, 1.0, 0.0));
+            let frustum = Frustum::from_matrix(&(::cgmath::perspective(Deg(60.0), 1.5, 0.1, 40.0) * view));
+            let mut found = Vec::new();
+            bvh.query_frustum(&frustum, &mut found);
+            let brute = boxes.iter().filter(|&&(_, ref b)| frustum.intersects_aabb(b)).map(|&(entity, _)| entity).collect();
+            assert_eq!(sorted(found), sorted(brute));
+
+            let ray = Ray::new(Point3::new(0.0, 0.0, 0.0) + rng.point(60.0), rng.point(1.0));
+            let brute = boxes.iter()
+                             .filter_map(|&(entity, ref b)| b.ray_distance(&ray, 80.0).map(|distance| (entity, distance)))
+                             .collect();
+            assert_eq!(sorted_hits(bvh.raycast(&ray, 80.0)), sorted_hits(brute));
+        }
+    }
+
+    #[test]
+    fn queries_match_brute_force() {
+        let mut rng = Rng(0x2545f491);
+        let mut scene = Scene::new();
+        let mut bvh = Bvh::new(0.5);
+        let mut boxes = Vec::new();
+        for _ in 0..500 {
+            let entity = scene.spawn();
+            let aabb = rng.aabb();
+            bvh.update(entity, aabb);
+            boxes.push((entity, aabb));
+        }
+        check_queries(&bvh, &boxes, &mut rng);
+    }
+
+    #[test]
+    fn queries_match_brute_force_after_updates_and_removals() {
+        let mut rng = Rng(0x9e3779b9);
+        let mut scene = Scene::new();
+        let mut bvh = Bvh::new(0.5);
+        let mut boxes = Vec::new();
+        for _ in 0..300 {
+            let entity = scene.spawn();
+            let aabb = rng.aabb();
+            bvh.update(entity, aabb);
+            boxes.push((entity, aabb));
+        }
+
+        for round in 0..10 {
+            // Nudge some boxes within their margin and move others far away.
+            for i in 0..boxes.len() {
+                if (i + round) % 3 != 0 {
+                    continue;
+                }
+                let offset = rng.point(if i % 2 == 0 { 0.2 } else { 20.0 });
+                let aabb = Aabb::new(boxes[i].1.min + offset, boxes[i].1.max + offset);
+                bvh.update(boxes[i].0, aabb);
+                boxes[i].1 = aabb;
+            }
+            for _ in 0..20 {
+                let i = ((rng.next().abs() * boxes.len() as f32) as usize).min(boxes.len() - 1);
+                assert!(bvh.remove(boxes.swap_remove(i).0));
+            }
+            for _ in 0..15 {
+                let entity = scene.spawn();
+                let aabb = rng.aabb();
+                bvh.update(entity, aabb);
+                boxes.push((entity, aabb));
+            }
+            check_queries(&bvh, &boxes, &mut rng);
+        }
+
+        for &(entity, _) in boxes.iter() {
+            assert!(bvh.remove(entity));
+        }
+        assert!(!bvh.remove(boxes[0].0));
+        check_queries(&bvh, &[], &mut rng);
+    }
+
+    #[test]
+    fn queries_match_brute_force_after_sync() {
+        let mut rng = Rng(0x6c078965);
+        let mut scene = Scene::new();
+        for _ in 0..300 {
+            let entity = scene.spawn();
+            let mut transform = Transform::new();
+            transform.set_translation(rng.point(50.0));
+            scene.insert(entity, transform);
+            scene.insert(entity, Bounds(Aabb::new(Point3::new(-1.0, -0.5, -0.5), Point3::new(1.0, 0.5, 0.5))));
+        }
+        let mut bvh = Bvh::new(0.5);
+
+        for round in 0..10 {
+            let entities = scene.entities().collect::<Vec<_>>();
+            for (i, &entity) in entities.iter().enumerate() {
+                match (i + round) % 7 {
+                    0 => {
+                        let offset = rng.point(if i % 2 == 0 { 0.2 } else { 10.0 });
+                        let transform = scene.get_mut::<Transform>(entity).unwrap();
+                        let translation = transform.translation() + offset;
+                        transform.set_translation(translation);
+                    },
+                    1 if i % 5 == 0 => { scene.despawn(entity); },
+                    2 if i % 5 == 0 => { scene.remove::<Bounds>(entity); },
+                    3 if i % 5 == 0 => { scene.insert(entity, Bounds(rng.aabb())); },
+                    _ => (),
+                }
+            }
+            for _ in 0..10 {
+                let entity = scene.spawn();
+                let mut transform = Transform::new();
+                transform.set_translation(rng.point(50.0));
+                scene.insert(entity, transform);
+                scene.insert(entity, Bounds(Aabb::new(Point3::new(-0.5, -0.5, -0.5), Point3::new(0.5, 0.5, 0.5))));
+            }
+            transform::propagate(&mut scene);
+            bvh.sync(&scene);
+
+            let boxes = scene.iter::<Bounds>()
+                             .filter_map(|(entity, bounds)| scene.get::<WorldTransform>(entity).map(|world| (entity, bounds.0.transform(&world.0))))
+                             .collect::<Vec<_>>();
+            for &(entity, aabb) in boxes.iter() {
+                assert_eq!(bvh.aabb(entity), Some(aabb));
+            }
+            check_queries(&bvh, &boxes, &mut rng);
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use ::cgmath::{Matrix4, Rad, SquareMatrix};
+use super::{Entity, Scene, WorldTransform};
+
+/// How a camera maps view space onto the screen.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Projection {
+    /// `fov` is the vertical field of view.
+    Perspective { fov: Rad<f32>, near: f32, far: f32 },
+    /// `height` is the vertical extent of the view volume in world units.
+    /// The width follows from the aspect ratio.
+    Orthographic { height: f32, near: f32, far: f32 },
+}
+
+/// Makes an entity a point of view. The camera looks down its local -Z axis
+/// with +Y up, and its view matrix is the inverse of its `WorldTransform`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Camera {
+    pub projection: Projection,
+}
+
+/// Marks the camera the renderer draws from. Managed by
+/// `Scene::set_active_camera`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ActiveCamera;
+
+impl Camera {
+    pub fn perspective(fov: Rad<f32>, near: f32, far: f32) -> Camera {
+        Camera { projection: Projection::Perspective { fov: fov, near: near, far: far } }
+    }
+
+    pub fn orthographic(height: f32, near: f32, far: f32) -> Camera {
+        Camera { projection: Projection::Orthographic { height: height, near: near, far: far } }
+    }
+
+    /// The projection matrix for a viewport with the given width / height.
+    pub fn projection(&self, aspect: f32) -> Matrix4<f32> {
+        match self.projection {
+            Projection::Perspective { fov, near, far } => ::cgmath::perspective(fov, aspect, near, far),
+            Projection::Orthographic { height, near, far } => {
+                let half_height = height * 0.5;
+                let half_width = half_height * aspect;
+                ::cgmath::ortho(-half_width, half_width, -half_height, half_height, near, far)
+            }
+        }
+    }
+}
+
+impl Scene {
+    /// Makes `entity` the camera the scene is viewed from. Returns false if it
+    /// is dead or has no `Camera`.
+    pub fn set_active_camera(&mut self, entity: Entity) -> bool {
+        if !self.has::<Camera>(entity) {
+            return false;
+        }
+
+        let previous = self.query::<&ActiveCamera>().map(|(entity, _)| entity).collect::<Vec<_>>();
+        for other in previous {
+            self.remove::<ActiveCamera>(other);
+        }
+        self.insert(entity, ActiveCamera);
+        true
+    }
+
+    /// The active camera, falling back to any camera if none was selected.
+    pub fn active_camera(&self) -> Option<Entity> {
+        self.iter::<ActiveCamera>()
+            .map(|(entity, _)| entity)
+            .find(|&entity| self.has::<Camera>(entity))
+            .or_else(|| self.iter::<Camera>().map(|(entity, _)| entity).next())
+    }
+}
+
+/// The view and projection matrices of the active camera for a viewport with
+/// the given aspect ratio. Call after `transform::propagate`.
+pub fn matrices(scene: &Scene, aspect: f32) -> Option<(Matrix4<f32>, Matrix4<f32>)> {
+    let entity = match scene.active_camera() {
+        Some(entity) => entity,
+        None => return None,
+    };
+    let camera = scene.get::<Camera>(entity).expect("Active camera has no Camera");
+
+    let view = match scene.get::<WorldTransform>(entity) {
+        Some(world) => world.0.invert().unwrap_or(Matrix4::identity()),
+        None => Matrix4::identity(),
+    };
+    Some((view, camera.projection(aspect)))
+}
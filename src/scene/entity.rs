@@ -0,0 +1,122 @@
+/// A handle to an entity living in a `Scene`.
+///
+/// Slots are reused once an entity is despawned, so a handle also carries the
+/// generation of its slot. A stale handle never refers to a newer entity.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Entity {
+    index: u32,
+    generation: u32,
+}
+
+impl Entity {
+    /// Rebuilds a handle from its parts, e.g. when reading a scene file.
+    pub fn from_raw(index: u32, generation: u32) -> Entity {
+        Entity { index: index, generation: generation }
+    }
+
+    /// The slot this entity occupies. Only unique among living entities.
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    /// How many times the slot has been recycled before this entity.
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+}
+
+/// Hands out entity handles and recycles the slots of despawned entities.
+pub struct Allocator {
+    /// Generation of the entity living in each slot, or of the last one that
+    /// did. Never goes backwards.
+    generations: Vec<u32>,
+    alive: Vec<bool>,
+    free: Vec<u32>,
+}
+
+impl Allocator {
+    pub fn new() -> Allocator {
+        Allocator {
+            generations: Vec::new(),
+            alive: Vec::new(),
+            free: Vec::new(),
+        }
+    }
+
+    /// Returns a new living entity, reusing a free slot if there is one.
+    pub fn allocate(&mut self) -> Entity {
+        match self.free.pop() {
+            Some(index) => {
+                let i = index as usize;
+                self.alive[i] = true;
+                self.generations[i] = self.generations[i].wrapping_add(1);
+                Entity { index: index, generation: self.generations[i] }
+            },
+            None => {
+                let index = self.generations.len() as u32;
+                self.generations.push(0);
+                self.alive.push(true);
+                Entity { index: index, generation: 0 }
+            }
+        }
+    }
+
+    /// Brings the exact entity to life, so handles stored elsewhere stay
+    /// valid. Returns false if its slot is already taken, or has since been
+    /// used by a newer generation, which would make stale handles valid
+    /// again.
+    pub fn reserve(&mut self, entity: Entity) -> bool {
+        let index = entity.index as usize;
+        while self.generations.len() <= index {
+            self.free.push(self.generations.len() as u32);
+            self.generations.push(0);
+            self.alive.push(false);
+        }
+        if self.alive[index] || entity.generation < self.generations[index] {
+            return false;
+        }
+
+        self.free.retain(|&free| free != entity.index);
+        self.alive[index] = true;
+        self.generations[index] = entity.generation;
+        true
+    }
+
+    /// Kills the entity. Its slot gets the next generation when it is reused.
+    /// Returns false if the entity was already dead.
+    pub fn free(&mut self, entity: Entity) -> bool {
+        if !self.is_alive(entity) {
+            return false;
+        }
+
+        let index = entity.index as usize;
+        self.alive[index] = false;
+        self.free.push(entity.index);
+        true
+    }
+
+    pub fn is_alive(&self, entity: Entity) -> bool {
+        let index = entity.index as usize;
+        index < self.generations.len() && self.alive[index] && self.generations[index] == entity.generation
+    }
+
+    /// Number of slots ever allocated, living or dead.
+    pub fn capacity(&self) -> usize {
+        self.generations.len()
+    }
+
+    /// Number of living entities.
+    pub fn len(&self) -> usize {
+        self.generations.len() - self.free.len()
+    }
+
+    /// Returns the living entity in the given slot, if any.
+    pub fn get(&self, index: u32) -> Option<Entity> {
+        let i = index as usize;
+        if i < self.alive.len() && self.alive[i] {
+            Some(Entity { index: index, generation: self.generations[i] })
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use ::std::any::{Any, TypeId};
+use ::std::collections::HashMap;
+use super::Component;
+
+/// Values shared by the whole scene rather than attached to an entity, such
+/// as the clock or the input state. At most one per type.
+///
+/// Each value is boxed and kept as a raw pointer, so systems running in
+/// parallel can write to different globals through a shared reference.
+pub struct Globals {
+    values: HashMap<TypeId, *mut (Any + Send + Sync)>,
+}
+
+// The pointers are owned boxes of `Send + Sync` values. Shared access only
+// hands out shared references, or raw pointers through `get_ptr`, whose
+// callers guarantee writes don't alias.
+unsafe impl Send for Globals {}
+unsafe impl Sync for Globals {}
+
+impl Globals {
+    pub fn new() -> Globals {
+        Globals { values: HashMap::new() }
+    }
+
+    /// Adds a value, handing back the one of the same type it replaces.
+    pub fn insert<T>(&mut self, value: T) -> Option<T> where T: Component {
+        let value: Box<Any + Send + Sync> = Box::new(value);
+        self.values.insert(TypeId::of::<T>(), Box::into_raw(value))
+                   .map(|previous| *unsafe { Box::from_raw(previous as *mut T) })
+    }
+
+    pub fn remove<T>(&mut self) -> Option<T> where T: Component {
+        self.values.remove(&TypeId::of::<T>())
+                   .map(|value| *unsafe { Box::from_raw(value as *mut T) })
+    }
+
+    pub fn get<T>(&self) -> Option<&T> where T: Component {
+        self.values.get(&TypeId::of::<T>()).map(|&value| unsafe { &*(value as *const T) })
+    }
+
+    pub fn get_mut<T>(&mut self) -> Option<&mut T> where T: Component {
+        self.values.get(&TypeId::of::<T>()).map(|&value| unsafe { &mut *(value as *mut T) })
+    }
+
+    /// Pointer to the value of type `T`, which may be written to without
+    /// borrowing the globals mutably.
+    ///
+    /// The caller guarantees that while the pointer is used nothing else
+    /// reads or writes the value, and that it is not removed.
+    pub unsafe fn get_ptr<T>(&self) -> Option<*mut T> where T: Component {
+        self.values.get(&TypeId::of::<T>()).map(|&value| value as *mut T)
+    }
+}
+
+impl Drop for Globals {
+    fn drop(&mut self) {
+        for (_, value) in self.values.drain() {
+            drop(unsafe { Box::from_raw(value) });
+        }
+    }
+}
@@ -0,0 +1,667 @@
+use ::std::any::Any;
+use super::{Children, Component, Detached, Entity, Scene, Transform};
+
+/// Commands kept by default before the oldest are forgotten.
+pub const DEFAULT_LIMIT: usize = 256;
+
+/// A reversible change to a scene.
+///
+/// `apply` is called once when the command is executed and again on every
+/// redo, so it must record whatever `undo` needs at that time.
+pub trait Command: Any + Send {
+    /// Short description for menus, e.g. "Undo Set parent".
+    fn name(&self) -> &str;
+
+    /// Makes the change. Returns false if it does not apply, e.g. because
+    /// the entity is dead, in which case nothing was changed.
+    fn apply(&mut self, scene: &mut Scene) -> bool;
+
+    /// Reverts the last `apply`.
+    fn undo(&mut self, scene: &mut Scene);
+
+    /// Absorbs `next`, which was just applied after this command, so both
+    /// are undone at once. Returns false if they don't combine.
+    fn merge(&mut self, _next: &Command) -> bool {
+        false
+    }
+
+    fn as_any(&self) -> &Any;
+}
+
+/// Spawns an empty entity. Redo brings back the same entity with the
+/// components it had when undone, under its parent and above its children.
+pub struct Spawn {
+    entity: Option<Entity>,
+    place: Option<(Entity, usize)>,
+    children: Vec<Entity>,
+    detached: Option<Detached>,
+}
+
+impl Spawn {
+    pub fn new() -> Spawn {
+        Spawn { entity: None, place: None, children: Vec::new(), detached: None }
+    }
+
+    /// The spawned entity, once applied.
+    pub fn entity(&self) -> Option<Entity> {
+        self.entity
+    }
+}
+
+impl Command for Spawn {
+    fn name(&self) -> &str {
+        "Spawn"
+    }
+
+    fn apply(&mut self, scene: &mut Scene) -> bool {
+        let entity = match self.entity {
+            Some(entity) => entity,
+            None => {
+                self.entity = Some(scene.spawn());
+                return true;
+            }
+        };
+        // Spawning anew would hand out another handle than later commands
+        // refer to.
+        let detached = match self.detached.take() {
+            Some(detached) => detached,
+            None => return false,
+        };
+        if !scene.restore(detached) {
+            return false;
+        }
+
+        reattach(scene, entity, self.place);
+        for &child in self.children.iter() {
+            scene.set_parent(child, Some(entity));
+        }
+        true
+    }
+
+    fn undo(&mut self, scene: &mut Scene) {
+        if let Some(entity) = self.entity {
+            self.place = place(scene, entity);
+            self.children = scene.children(entity).to_vec();
+            self.detached = scene.take(entity);
+        }
+    }
+
+    fn as_any(&self) -> &Any {
+        self
+    }
+}
+
+/// Despawns an entity. Undo brings it back with its components, under its
+/// parent and above its children.
+pub struct Despawn {
+    entity: Entity,
+    place: Option<(Entity, usize)>,
+    children: Vec<Entity>,
+    detached: Option<Detached>,
+}
+
+impl Despawn {
+    pub fn new(entity: Entity) -> Despawn {
+        Despawn { entity: entity, place: None, children: Vec::new(), detached: None }
+    }
+}
+
+impl Command for Despawn {
+    fn name(&self) -> &str {
+        "Despawn"
+    }
+
+    fn apply(&mut self, scene: &mut Scene) -> bool {
+        if !scene.is_alive(self.entity) {
+            return false;
+        }
+
+        self.place = place(scene, self.entity);
+        self.children = scene.children(self.entity).to_vec();
+        self.detached = scene.take(self.entity);
+        true
+    }
+
+    fn undo(&mut self, scene: &mut Scene) {
+        let detached = match self.detached.take() {
+            Some(detached) => detached,
+            None => return,
+        };
+        if !scene.restore(detached) {
+            warn!("Could not undo the despawn of {:?}, its slot was reused", self.entity);
+            return;
+        }
+
+        reattach(scene, self.entity, self.place);
+        for &child in self.children.iter() {
+            scene.set_parent(child, Some(self.entity));
+        }
+    }
+
+    fn as_any(&self) -> &Any {
+        self
+    }
+}
+
+/// Inserts, replaces or removes a component. Consecutive sets of the same
+/// component of the same entity between `History::begin_merge` and
+/// `History::end_merge` merge, so a drag is undone in one step.
+///
+/// Use `SetParent` for the hierarchy instead of setting `Parent` directly.
+pub struct SetComponent<T> {
+    entity: Entity,
+    value: Option<T>,
+    previous: Option<T>,
+}
+
+impl<T> SetComponent<T> where T: Component + Clone {
+    pub fn insert(entity: Entity, value: T) -> SetComponent<T> {
+        SetComponent { entity: entity, value: Some(value), previous: None }
+    }
+
+    pub fn remove(entity: Entity) -> SetComponent<T> {
+        SetComponent { entity: entity, value: None, previous: None }
+    }
+}
+
+impl<T> Command for SetComponent<T> where T: Component + Clone {
+    fn name(&self) -> &str {
+        if self.value.is_some() { "Set component" } else { "Remove component" }
+    }
+
+    fn apply(&mut self, scene: &mut Scene) -> bool {
+        if !scene.is_alive(self.entity) || (self.value.is_none() && !scene.has::<T>(self.entity)) {
+            return false;
+        }
+
+        self.previous = put(scene, self.entity, self.value.clone());
+        true
+    }
+
+    fn undo(&mut self, scene: &mut Scene) {
+        if scene.is_alive(self.entity) {
+            put(scene, self.entity, self.previous.take());
+        }
+    }
+
+    fn merge(&mut self, next: &Command) -> bool {
+        match next.as_any().downcast_ref::<SetComponent<T>>() {
+            Some(next) if next.entity == self.entity => {
+                self.value = next.value.clone();
+                true
+            },
+            _ => false,
+        }
+    }
+
+    fn as_any(&self) -> &Any {
+        self
+    }
+}
+
+/// Inserts or removes a component, marking transforms dirty so the change
+/// propagates. Returns the component it replaced.
+fn put<T>(scene: &mut Scene, entity: Entity, value: Option<T>) -> Option<T> where T: Component {
+    match value {
+        Some(mut value) => {
+            if let Some(transform) = (&mut value as &mut Any).downcast_mut::<Transform>() {
+                transform.mark_dirty();
+            }
+            scene.insert(entity, value)
+        },
+        None => scene.remove::<T>(entity),
+    }
+}
+
+/// Moves an entity in the hierarchy, see `Scene::set_parent`. Undo puts it
+/// back at the same place among its old siblings.
+pub struct SetParent {
+    entity: Entity,
+    parent: Option<Entity>,
+    previous: Option<(Entity, usize)>,
+}
+
+impl SetParent {
+    pub fn new(entity: Entity, parent: Option<Entity>) -> SetParent {
+        SetParent { entity: entity, parent: parent, previous: None }
+    }
+}
+
+impl Command for SetParent {
+    fn name(&self) -> &str {
+        "Set parent"
+    }
+
+    fn apply(&mut self, scene: &mut Scene) -> bool {
+        let previous = place(scene, self.entity);
+        if !scene.set_parent(self.entity, self.parent) {
+            return false;
+        }
+        self.previous = previous;
+        true
+    }
+
+    fn undo(&mut self, scene: &mut Scene) {
+        reattach(scene, self.entity, self.previous);
+    }
+
+    fn as_any(&self) -> &Any {
+        self
+    }
+}
+
+/// The parent of an entity and its position among the parent's children.
+fn place(scene: &Scene, entity: Entity) -> Option<(Entity, usize)> {
+    scene.parent(entity).map(|parent| {
+        (parent, scene.children(parent).iter().position(|&child| child == entity).unwrap_or(0))
+    })
+}
+
+/// Puts an entity back at a place returned by `place`.
+fn reattach(scene: &mut Scene, entity: Entity, place: Option<(Entity, usize)>) {
+    let (parent, index) = match place {
+        Some(place) => place,
+        None => {
+            scene.set_parent(entity, None);
+            return;
+        }
+    };
+    if !scene.set_parent(entity, Some(parent)) {
+        return;
+    }
+    if let Some(children) = scene.get_mut::<Children>(parent) {
+        children.0.retain(|&child| child != entity);
+        let index = ::std::cmp::min(index, children.0.len());
+        children.0.insert(index, entity);
+    }
+}
+
+/// Commands undone and redone together, built with `History::begin` and
+/// `History::commit`.
+pub struct Transaction {
+    name: String,
+    commands: Vec<Box<Command>>,
+}
+
+impl Command for Transaction {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn apply(&mut self, scene: &mut Scene) -> bool {
+        for i in 0..self.commands.len() {
+            if !self.commands[i].apply(scene) {
+                for command in self.commands[..i].iter_mut().rev() {
+                    command.undo(scene);
+                }
+                return false;
+            }
+        }
+        true
+    }
+
+    fn undo(&mut self, scene: &mut Scene) {
+        for command in self.commands.iter_mut().rev() {
+            command.undo(scene);
+        }
+    }
+
+    fn as_any(&self) -> &Any {
+        self
+    }
+}
+
+/// Undo and redo stacks of scene edits, for editing tools.
+///
+/// Edits made through the history can be undone as long as the scene is not
+/// changed behind its back in ways that conflict with them.
+///
+/// ```ignore
+/// history.begin("Duplicate");
+/// let copy = history.spawn(&mut scene);
+/// history.insert(&mut scene, copy, transform);
+/// history.set_parent(&mut scene, copy, parent);
+/// history.commit();
+///
+/// // While dragging, each move merges into the previous one.
+/// history.begin_merge();
+/// history.insert(&mut scene, copy, dragged);
+/// // When the drag ends:
+/// history.end_merge();
+/// ```
+pub struct History {
+    done: Vec<Box<Command>>,
+    undone: Vec<Box<Command>>,
+    open: Vec<Transaction>,
+    /// Whether commands are being merged, between `begin_merge` and
+    /// `end_merge`.
+    merging: bool,
+    /// Whether the next command may merge into the last one.
+    mergeable: bool,
+    limit: usize,
+}
+
+impl History {
+    pub fn new() -> History {
+        History::with_limit(DEFAULT_LIMIT)
+    }
+
+    /// A history remembering at most `limit` commands. Open transactions
+    /// count as one once committed.
+    pub fn with_limit(limit: usize) -> History {
+        History {
+            done: Vec::new(),
+            undone: Vec::new(),
+            open: Vec::new(),
+            merging: false,
+            mergeable: false,
+            limit: limit,
+        }
+    }
+
+    /// Applies the command and records it. Returns false, recording
+    /// nothing, if it did not apply.
+    pub fn execute(&mut self, scene: &mut Scene, mut command: Box<Command>) -> bool {
+        if !command.apply(scene) {
+            return false;
+        }
+        self.record(command);
+        true
+    }
+
+    pub fn spawn(&mut self, scene: &mut Scene) -> Entity {
+        let mut command = Spawn::new();
+        command.apply(scene);
+        let entity = command.entity().expect("Spawn did not spawn");
+        self.record(Box::new(command));
+        entity
+    }
+
+    pub fn despawn(&mut self, scene: &mut Scene, entity: Entity) -> bool {
+        self.execute(scene, Box::new(Despawn::new(entity)))
+    }
+
+    pub fn insert<T>(&mut self, scene: &mut Scene, entity: Entity, component: T) -> bool where T: Component + Clone {
+        self.execute(scene, Box::new(SetComponent::insert(entity, component)))
+    }
+
+    pub fn remove<T>(&mut self, scene: &mut Scene, entity: Entity) -> bool where T: Component + Clone {
+        self.execute(scene, Box::new(SetComponent::<T>::remove(entity)))
+    }
+
+    pub fn set_parent(&mut self, scene: &mut Scene, child: Entity, parent: Option<Entity>) -> bool {
+        self.execute(scene, Box::new(SetParent::new(child, parent)))
+    }
+
+    /// Lets consecutive commands merge into one until `end_merge`, e.g.
+    /// while dragging. The first of them does not merge into the command
+    /// before it.
+    pub fn begin_merge(&mut self) {
+        self.merging = true;
+        self.mergeable = false;
+    }
+
+    /// Stops commands from merging, e.g. when a drag ends.
+    pub fn end_merge(&mut self) {
+        self.merging = false;
+        self.mergeable = false;
+    }
+
+    /// Groups the commands executed until the matching `commit` into one.
+    /// Transactions can nest.
+    pub fn begin(&mut self, name: &str) {
+        self.open.push(Transaction { name: name.to_owned(), commands: Vec::new() });
+        self.mergeable = false;
+    }
+
+    /// Closes the innermost transaction. Empty transactions are dropped.
+    pub fn commit(&mut self) {
+        let transaction = self.open.pop().expect("Committed without an open transaction");
+        self.mergeable = false;
+        if !transaction.commands.is_empty() {
+            self.record(Box::new(transaction));
+            self.mergeable = false;
+        }
+    }
+
+    /// Undoes and drops the commands of the innermost transaction.
+    pub fn rollback(&mut self, scene: &mut Scene) {
+        let mut transaction = self.open.pop().expect("Rolled back without an open transaction");
+        transaction.undo(scene);
+        self.mergeable = false;
+    }
+
+    /// Undoes the last command. Returns false if there is none or a
+    /// transaction is still open.
+    pub fn undo(&mut self, scene: &mut Scene) -> bool {
+        if !self.open.is_empty() {
+            return false;
+        }
+        let mut command = match self.done.pop() {
+            Some(command) => command,
+            None => return false,
+        };
+        command.undo(scene);
+        self.undone.push(command);
+        self.mergeable = false;
+        true
+    }
+
+    /// Applies the last undone command again. A command that no longer
+    /// applies is dropped and false is returned.
+    pub fn redo(&mut self, scene: &mut Scene) -> bool {
+        if !self.open.is_empty() {
+            return false;
+        }
+        let mut command = match self.undone.pop() {
+            Some(command) => command,
+            None => return false,
+        };
+        self.mergeable = false;
+        if !command.apply(scene) {
+            warn!("Could not redo '{}', dropping it", command.name());
+            return false;
+        }
+        self.done.push(command);
+        true
+    }
+
+    /// Name of the command `undo` would revert.
+    pub fn undo_name(&self) -> Option<&str> {
+        self.done.last().map(|command| command.name())
+    }
+
+    /// Name of the command `redo` would apply.
+    pub fn redo_name(&self) -> Option<&str> {
+        self.undone.last().map(|command| command.name())
+    }
+
+    /// Forgets every command, e.g. after loading another scene.
+    pub fn clear(&mut self) {
+        self.done.clear();
+        self.undone.clear();
+        self.open.clear();
+        self.merging = false;
+        self.mergeable = false;
+    }
+
+    fn record(&mut self, command: Box<Command>) {
+        self.undone.clear();
+
+        let mergeable = self.mergeable;
+        self.mergeable = self.merging;
+        let done = match self.open.last_mut() {
+            Some(transaction) => &mut transaction.commands,
+            None => &mut self.done,
+        };
+        if mergeable {
+            if let Some(last) = done.last_mut() {
+                if last.merge(&*command) {
+                    return;
+                }
+            }
+        }
+        done.push(command);
+
+        if self.open.is_empty() && self.done.len() > self.limit {
+            let excess = self.done.len() - self.limit;
+            self.done.drain(..excess);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::History;
+    use super::super::{Entity, Scene};
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Health(u32);
+
+    fn health(scene: &Scene, entity: Entity) -> Option<u32> {
+        scene.get::<Health>(entity).map(|health| health.0)
+    }
+
+    #[test]
+    fn undo_and_redo_components() {
+        let mut scene = Scene::new();
+        let mut history = History::new();
+        let entity = scene.spawn();
+        assert!(history.insert(&mut scene, entity, Health(1)));
+        assert!(history.insert(&mut scene, entity, Health(2)));
+        assert!(history.remove::<Health>(&mut scene, entity));
+        assert!(!history.remove::<Health>(&mut scene, entity));
+        assert_eq!(history.undo_name(), Some("Remove component"));
+
+        assert!(history.undo(&mut scene));
+        assert_eq!(health(&scene, entity), Some(2));
+        assert!(history.undo(&mut scene));
+        assert_eq!(health(&scene, entity), Some(1));
+        assert!(history.undo(&mut scene));
+        assert_eq!(health(&scene, entity), None);
+        assert!(!history.undo(&mut scene));
+
+        assert!(history.redo(&mut scene));
+        assert!(history.redo(&mut scene));
+        assert_eq!(health(&scene, entity), Some(2));
+
+        // A new command forgets what was undone.
+        assert!(history.insert(&mut scene, entity, Health(3)));
+        assert_eq!(history.redo_name(), None);
+        assert!(!history.redo(&mut scene));
+    }
+
+    #[test]
+    fn redoing_a_spawn_brings_back_the_same_entity() {
+        let mut scene = Scene::new();
+        let mut history = History::new();
+        let parent = history.spawn(&mut scene);
+        let child = history.spawn(&mut scene);
+        history.set_parent(&mut scene, child, Some(parent));
+        history.insert(&mut scene, parent, Health(5));
+
+        for _ in 0..4 {
+            assert!(history.undo(&mut scene));
+        }
+        assert!(!scene.is_alive(parent) && !scene.is_alive(child));
+        // Later commands refer to the entities by handle, so the redone
+        // spawns must bring back the same ones.
+        while history.redo(&mut scene) {}
+        assert!(scene.is_alive(parent) && scene.is_alive(child));
+        assert_eq!(scene.parent(child), Some(parent));
+        assert_eq!(health(&scene, parent), Some(5));
+
+        // Undoing the despawn of the parent puts its child back under it.
+        assert!(history.despawn(&mut scene, parent));
+        assert_eq!(scene.parent(child), None);
+        assert!(history.undo(&mut scene));
+        assert_eq!(scene.children(parent), &[child]);
+        assert_eq!(health(&scene, parent), Some(5));
+    }
+
+    #[test]
+    fn commands_merge_only_between_begin_and_end_merge() {
+        let mut scene = Scene::new();
+        let mut history = History::new();
+        let entity = scene.spawn();
+        history.insert(&mut scene, entity, Health(1));
+        history.insert(&mut scene, entity, Health(2));
+
+        history.begin_merge();
+        for value in 3..6 {
+            history.insert(&mut scene, entity, Health(value));
+        }
+        history.end_merge();
+        history.insert(&mut scene, entity, Health(6));
+
+        let mut values = vec![health(&scene, entity)];
+        while history.undo(&mut scene) {
+            values.push(health(&scene, entity));
+        }
+        assert_eq!(values, vec![Some(6), Some(5), Some(2), Some(1), None]);
+        while history.redo(&mut scene) {}
+        assert_eq!(health(&scene, entity), Some(6));
+    }
+
+    #[test]
+    fn nested_transactions_undo_as_one() {
+        let mut scene = Scene::new();
+        let mut history = History::new();
+        let entity = scene.spawn();
+
+        history.begin("Outer");
+        history.insert(&mut scene, entity, Health(1));
+        history.begin("Inner");
+        let other = history.spawn(&mut scene);
+        history.set_parent(&mut scene, other, Some(entity));
+        history.commit();
+        // Empty transactions are dropped.
+        history.begin("Empty");
+        history.commit();
+        assert!(!history.undo(&mut scene));
+        history.commit();
+
+        assert_eq!(history.undo_name(), Some("Outer"));
+        assert!(history.undo(&mut scene));
+        assert_eq!(health(&scene, entity), None);
+        assert!(!scene.is_alive(other));
+        assert!(!history.undo(&mut scene));
+
+        assert!(history.redo(&mut scene));
+        assert_eq!(health(&scene, entity), Some(1));
+        assert_eq!(scene.children(entity), &[other]);
+    }
+
+    #[test]
+    fn rollback_reverts_and_forgets_the_transaction() {
+        let mut scene = Scene::new();
+        let mut history = History::new();
+        let entity = scene.spawn();
+        history.insert(&mut scene, entity, Health(1));
+
+        history.begin("Edit");
+        history.insert(&mut scene, entity, Health(2));
+        let spawned = history.spawn(&mut scene);
+        history.rollback(&mut scene);
+
+        assert_eq!(health(&scene, entity), Some(1));
+        assert!(!scene.is_alive(spawned));
+        assert_eq!(history.undo_name(), Some("Set component"));
+        assert!(history.undo(&mut scene));
+        assert!(!history.undo(&mut scene));
+    }
+
+    #[test]
+    fn only_the_last_commands_up_to_the_limit_are_kept() {
+        let mut scene = Scene::new();
+        let mut history = History::with_limit(2);
+        let entity = scene.spawn();
+        for value in 0..4 {
+            history.insert(&mut scene, entity, Health(value));
+        }
+        assert!(history.undo(&mut scene));
+        assert!(history.undo(&mut scene));
+        assert!(!history.undo(&mut scene));
+        assert_eq!(health(&scene, entity), Some(1));
+    }
+}
@@ -0,0 +1,172 @@
+use ::cgmath::{Deg, EuclideanSpace, InnerSpace, Matrix3, Matrix4, One, Point3, Quaternion, SquareMatrix, Vector3};
+use ::collada::document::ColladaDocument;
+use ::resource::Resources;
+use ::xml::{Element, Xml};
+use super::{Aabb, Bounds, Entity, Model, Scene, Transform};
+
+error_chain! {
+    links {
+        Resource(::resource::Error, ::resource::ErrorKind);
+    }
+
+    errors {
+        NoVisualScene(path: String) {
+            description("COLLADA file has no visual scene")
+            display("COLLADA file '{}' has no visual scene", path)
+        }
+        Malformed(path: String, element: String) {
+            description("malformed COLLADA element")
+            display("malformed <{}> in COLLADA file '{}'", element, path)
+        }
+    }
+}
+
+/// Builds a subtree of `scene` mirroring the visual scene of a COLLADA file.
+///
+/// Every `<node>` becomes an entity with its transform preserved and parented
+/// like in the file, under a root entity that converts the file's up axis to
+/// ours. Each `<instance_geometry>` becomes a `Model` referencing the mesh
+/// registered in `resources` under `<file>#<geometry id>`, so instances of the
+/// same geometry share it. Nodes with several geometries get one child entity
+/// per extra geometry, and every model gets the `Bounds` of its mesh.
+/// `<instance_node>`, cameras and lights are ignored.
+///
+/// Returns the root entity.
+pub fn import_collada(scene: &mut Scene, resources: &mut Resources, file: &str, doc: &ColladaDocument) -> Result<Entity> {
+    try!(resources.add_collada(file, doc));
+
+    let root_element = &doc.root_element;
+    let visual_scene = try!(find_visual_scene(root_element).ok_or(ErrorKind::NoVisualScene(file.to_owned())));
+
+    let root = scene.spawn();
+    let up_axis = child(root_element, "asset").and_then(|asset| child(asset, "up_axis"))
+                                              .map(|up_axis| up_axis.content_str());
+    let rotation = match up_axis.as_ref().map(|up_axis| up_axis.trim()) {
+        Some("Z_UP") => Quaternion::from(Matrix3::from_angle_x(Deg(-90.0))),
+        Some("X_UP") => Quaternion::from(Matrix3::from_angle_z(Deg(90.0))),
+        _ => Quaternion::one(),
+    };
+    scene.insert(root, Transform::from_parts(Vector3::new(0.0, 0.0, 0.0), rotation, Vector3::new(1.0, 1.0, 1.0)));
+
+    for node in children(visual_scene, "node") {
+        try!(import_node(scene, resources, file, node, root));
+    }
+    Ok(root)
+}
+
+fn import_node(scene: &mut Scene, resources: &mut Resources, file: &str, node: &Element, parent: Entity) -> Result<Entity> {
+    let entity = scene.spawn();
+    scene.insert(entity, decompose(try!(node_matrix(file, node))));
+    scene.set_parent(entity, Some(parent));
+
+    for (i, instance) in children(node, "instance_geometry").into_iter().enumerate() {
+        let url = try!(instance.get_attribute("url", None)
+                               .ok_or(ErrorKind::Malformed(file.to_owned(), "instance_geometry".to_owned())));
+        let key = Resources::mesh_key(file, url.trim_left_matches('#'));
+        let mesh = try!(resources.mesh(&key));
+
+        let target = if i == 0 {
+            entity
+        } else {
+            let extra = scene.spawn();
+            scene.insert(extra, Transform::new());
+            scene.set_parent(extra, Some(entity));
+            extra
+        };
+        if let Some(aabb) = Aabb::from_points(mesh.vertices.iter().map(|vertex| Point3::from_vec(vertex.position))) {
+            scene.insert(target, Bounds(aabb));
+        }
+        scene.insert(target, Model { path: key, mesh: mesh });
+    }
+
+    for child in children(node, "node") {
+        try!(import_node(scene, resources, file, child, entity));
+    }
+    Ok(entity)
+}
+
+/// The visual scene instanced by `<scene>`, or the first one in the library.
+fn find_visual_scene(root: &Element) -> Option<&Element> {
+    let library = match child(root, "library_visual_scenes") {
+        Some(library) => library,
+        None => return None,
+    };
+    let scenes = children(library, "visual_scene");
+
+    let instanced = child(root, "scene").and_then(|scene| child(scene, "instance_visual_scene"))
+                                        .and_then(|instance| instance.get_attribute("url", None))
+                                        .map(|url| url.trim_left_matches('#'));
+    if let Some(id) = instanced {
+        if let Some(scene) = scenes.iter().find(|scene| scene.get_attribute("id", None) == Some(id)) {
+            return Some(scene);
+        }
+    }
+    scenes.into_iter().next()
+}
+
+/// Composes the transform elements of a node in document order.
+fn node_matrix(file: &str, node: &Element) -> Result<Matrix4<f32>> {
+    let mut matrix = Matrix4::identity();
+    for element in node.children.iter().filter_map(as_element) {
+        let malformed = || ErrorKind::Malformed(file.to_owned(), element.name.clone());
+        let values = match element.name.as_str() {
+            "matrix" | "translate" | "rotate" | "scale" => try!(floats(element).ok_or_else(&malformed)),
+            _ => continue,
+        };
+
+        matrix = matrix * match (element.name.as_str(), values.len()) {
+            // COLLADA matrices are row major, cgmath takes columns.
+            ("matrix", 16) => Matrix4::new(values[0], values[4], values[8], values[12],
+                                           values[1], values[5], values[9], values[13],
+                                           values[2], values[6], values[10], values[14],
+                                           values[3], values[7], values[11], values[15]),
+            ("translate", 3) => Matrix4::from_translation(Vector3::new(values[0], values[1], values[2])),
+            ("rotate", 4) => Matrix4::from_axis_angle(Vector3::new(values[0], values[1], values[2]).normalize(),
+                                                      Deg(values[3])),
+            ("scale", 3) => Matrix4::from_nonuniform_scale(values[0], values[1], values[2]),
+            _ => return Err(malformed().into()),
+        };
+    }
+    Ok(matrix)
+}
+
+/// Splits an affine matrix into translation, rotation and scale. Shear and
+/// mirroring cannot be represented by a `Transform` and are lost.
+fn decompose(matrix: Matrix4<f32>) -> Transform {
+    let translation = matrix.w.truncate();
+    let (x, y, z) = (matrix.x.truncate(), matrix.y.truncate(), matrix.z.truncate());
+    let scale = Vector3::new(x.magnitude(), y.magnitude(), z.magnitude());
+
+    let rotation = if scale.x > 0.0 && scale.y > 0.0 && scale.z > 0.0 {
+        Quaternion::from(Matrix3::from_cols(x / scale.x, y / scale.y, z / scale.z)).normalize()
+    } else {
+        Quaternion::one()
+    };
+    Transform::from_parts(translation, rotation, scale)
+}
+
+fn floats(element: &Element) -> Option<Vec<f32>> {
+    element.content_str()
+           .split_whitespace()
+           .map(|value| value.parse::<f32>().ok())
+           .collect()
+}
+
+fn as_element(node: &Xml) -> Option<&Element> {
+    match *node {
+        Xml::ElementNode(ref element) => Some(element),
+        _ => None,
+    }
+}
+
+/// The child elements with the given name, ignoring namespaces.
+fn children<'a>(element: &'a Element, name: &str) -> Vec<&'a Element> {
+    element.children.iter()
+                    .filter_map(as_element)
+                    .filter(|child| child.name == name)
+                    .collect()
+}
+
+fn child<'a>(element: &'a Element, name: &str) -> Option<&'a Element> {
+    children(element, name).into_iter().next()
+}
@@ -0,0 +1,100 @@
+use ::cgmath::{EuclideanSpace, InnerSpace, Point3, Rad, Vector3};
+use super::{Scene, WorldTransform};
+
+/// The shape of the light a `Light` emits.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LightKind {
+    /// Lights everything evenly from every direction, like light bounced
+    /// around the scene. Position and orientation are ignored.
+    Ambient,
+    /// Parallel rays along the entity's -Z axis, like the sun.
+    Directional,
+    /// Shines in every direction from the entity, fading out at `range`.
+    Point { range: f32 },
+    /// Shines along the entity's -Z axis. Full strength inside the `inner`
+    /// half angle, fading to nothing at the `outer` one.
+    Spot { range: f32, inner: Rad<f32>, outer: Rad<f32> },
+}
+
+/// Makes an entity emit light. Position and direction come from its
+/// `WorldTransform`, the entity looking down -Z like a `Camera`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Light {
+    pub kind: LightKind,
+    /// Linear RGB.
+    pub color: Vector3<f32>,
+    pub intensity: f32,
+}
+
+impl Light {
+    pub fn ambient(color: Vector3<f32>, intensity: f32) -> Light {
+        Light { kind: LightKind::Ambient, color: color, intensity: intensity }
+    }
+
+    pub fn directional(color: Vector3<f32>, intensity: f32) -> Light {
+        Light { kind: LightKind::Directional, color: color, intensity: intensity }
+    }
+
+    pub fn point(color: Vector3<f32>, intensity: f32, range: f32) -> Light {
+        Light { kind: LightKind::Point { range: range }, color: color, intensity: intensity }
+    }
+
+    pub fn spot(color: Vector3<f32>, intensity: f32, range: f32, inner: Rad<f32>, outer: Rad<f32>) -> Light {
+        Light {
+            kind: LightKind::Spot { range: range, inner: inner, outer: outer },
+            color: color,
+            intensity: intensity,
+        }
+    }
+}
+
+/// A non-ambient light placed in world space.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct WorldLight {
+    pub kind: LightKind,
+    pub position: Point3<f32>,
+    /// Normalized direction the light shines in.
+    pub direction: Vector3<f32>,
+    /// Color scaled by intensity.
+    pub radiance: Vector3<f32>,
+}
+
+/// Every light of a scene for one frame, as handed to the renderer.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lights {
+    /// Sum of all ambient lights.
+    pub ambient: Vector3<f32>,
+    pub lights: Vec<WorldLight>,
+}
+
+/// Collects the lights of the scene in world space. Lights without a
+/// `WorldTransform` sit at the origin looking down -Z. Call after
+/// `transform::propagate`.
+pub fn gather(scene: &Scene) -> Lights {
+    let mut lights = Lights {
+        ambient: Vector3::new(0.0, 0.0, 0.0),
+        lights: Vec::new(),
+    };
+
+    for (entity, light) in scene.iter::<Light>() {
+        let radiance = light.color * light.intensity;
+        if let LightKind::Ambient = light.kind {
+            lights.ambient += radiance;
+            continue;
+        }
+
+        let (position, direction) = match scene.get::<WorldTransform>(entity) {
+            Some(world) => (Point3::from_vec(world.0.w.truncate()), -world.0.z.truncate()),
+            None => (Point3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, -1.0)),
+        };
+        let direction = if direction.magnitude2() > 0.0 { direction.normalize() } else { Vector3::new(0.0, 0.0, -1.0) };
+
+        lights.lights.push(WorldLight {
+            kind: light.kind,
+            position: position,
+            direction: direction,
+            radiance: radiance,
+        });
+    }
+    lights
+}
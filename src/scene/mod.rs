@@ -1,13 +1,504 @@
-use ::cgmath::Vector3;
-use ::resource::Resource;
+use ::std::any::TypeId;
+use ::std::collections::HashMap;
+use ::std::sync::{Mutex, MutexGuard};
+use ::std::sync::atomic::{AtomicBool, Ordering};
 
+pub use self::archetype::{Archetype, Column, Component};
+pub use self::bounds::{Aabb, Bounds, Frustum, Plane, Ray};
+pub use self::bvh::Bvh;
+pub use self::camera::{ActiveCamera, Camera, Projection};
+pub use self::entity::{Allocator, Entity};
+pub use self::light::{Light, LightKind};
+pub use self::model::Model;
+pub use self::query::{Query, QueryIter};
+pub use self::schedule::{Access, Schedule, SystemScene};
+pub use self::select::Select;
+pub use self::tag::{Name, Tags};
+pub use self::transform::{Children, Parent, PreviousWorldTransform, Transform, WorldTransform};
+
+mod archetype;
+mod bounds;
+mod bvh;
+pub mod camera;
+mod entity;
+mod global;
+pub mod import;
+pub mod history;
+pub mod light;
+mod model;
+pub mod prefab;
+mod query;
+pub mod raycast;
+pub mod schedule;
+pub mod select;
+pub mod serialize;
+mod tag;
+pub mod transform;
+
+/// Where the components of a living entity are stored.
+#[derive(Copy, Clone, Debug)]
+struct Location {
+    archetype: usize,
+    row: usize,
+}
+
+/// An entity taken out of a scene with `Scene::take`, holding all of its
+/// components until `Scene::restore` puts it back.
+pub struct Detached {
+    entity: Entity,
+    types: Vec<TypeId>,
+    columns: Vec<Box<Column>>,
+}
+
+impl Detached {
+    pub fn entity(&self) -> Entity {
+        self.entity
+    }
+}
+
+/// A collection of entities and the components attached to them.
+///
+/// Entities sharing the same set of component types are stored together in
+/// an `Archetype`, the first of which is always the empty archetype. Values
+/// that belong to no entity, like the clock, are kept as globals.
+///
 /// X is right. Y is up. Z is into the screen.
 pub struct Scene {
-    entities: [Entity]
+    allocator: Allocator,
+    locations: Vec<Location>,
+    archetypes: Vec<Archetype>,
+    archetype_index: HashMap<Vec<TypeId>, usize>,
+    index: Mutex<tag::Index>,
+    /// Set when names or tags may have been changed in place, so the index
+    /// catches up with them before its next use.
+    stale_index: AtomicBool,
+    globals: global::Globals,
+}
+
+impl Scene {
+    pub fn new() -> Scene {
+        let mut archetype_index = HashMap::new();
+        archetype_index.insert(Vec::new(), 0);
+
+        Scene {
+            allocator: Allocator::new(),
+            locations: Vec::new(),
+            archetypes: vec![Archetype::new(Vec::new(), Vec::new())],
+            archetype_index: archetype_index,
+            index: Mutex::new(tag::Index::new()),
+            stale_index: AtomicBool::new(false),
+            globals: global::Globals::new(),
+        }
+    }
+
+    /// Creates an entity without any components.
+    pub fn spawn(&mut self) -> Entity {
+        let entity = self.allocator.allocate();
+        let row = self.archetypes[0].push_entity(entity);
+        self.set_location(entity, Location { archetype: 0, row: row });
+        entity
+    }
+
+    /// Creates a specific entity without any components. Returns false if
+    /// its slot is taken by a living entity or was reused since.
+    pub fn spawn_at(&mut self, entity: Entity) -> bool {
+        if !self.allocator.reserve(entity) {
+            return false;
+        }
+
+        let row = self.archetypes[0].push_entity(entity);
+        self.set_location(entity, Location { archetype: 0, row: row });
+        true
+    }
+
+    /// Destroys the entity and drops all of its components. Its children are
+    /// detached and become roots. Returns false if the entity was already dead.
+    pub fn despawn(&mut self, entity: Entity) -> bool {
+        if !self.is_alive(entity) {
+            return false;
+        }
+
+        transform::detach(self, entity);
+        self.allocator.free(entity);
+
+        let location = self.locations[entity.index() as usize];
+        self.index().remove_row(&self.archetypes[location.archetype], location.row);
+        if let Some(moved) = self.archetypes[location.archetype].remove_row(location.row) {
+            self.locations[moved.index() as usize].row = location.row;
+        }
+        true
+    }
+
+    /// Like `despawn`, but hands the components back so the entity can be
+    /// restored, e.g. to undo the despawn. Returns `None` if the entity was
+    /// already dead.
+    pub fn take(&mut self, entity: Entity) -> Option<Detached> {
+        if !self.is_alive(entity) {
+            return None;
+        }
+
+        transform::detach(self, entity);
+        self.allocator.free(entity);
+
+        let location = self.locations[entity.index() as usize];
+        self.index().remove_row(&self.archetypes[location.archetype], location.row);
+        let archetype = &mut self.archetypes[location.archetype];
+        let types = archetype.types().to_vec();
+        let (columns, moved) = archetype.take_row(location.row);
+        if let Some(moved) = moved {
+            self.locations[moved.index() as usize].row = location.row;
+        }
+        Some(Detached { entity: entity, types: types, columns: columns })
+    }
+
+    /// Brings back an entity taken with `take`, under the same handle and
+    /// with all of its components. Returns false, dropping the components, if
+    /// its slot is taken by a living entity or was reused since.
+    pub fn restore(&mut self, detached: Detached) -> bool {
+        let Detached { entity, types, columns } = detached;
+        if !self.allocator.reserve(entity) {
+            return false;
+        }
+
+        let target = match self.archetype_index.get(&types).cloned() {
+            Some(target) => target,
+            None => {
+                let archetype = Archetype::new(types.clone(), columns.iter().map(|column| column.empty()).collect());
+                self.add_archetype(types, archetype)
+            }
+        };
+        let row = self.archetypes[target].push_row(entity, columns);
+        self.index().add_row(&self.archetypes[target], row);
+        self.set_location(entity, Location { archetype: target, row: row });
+        true
+    }
+
+    pub fn is_alive(&self, entity: Entity) -> bool {
+        self.allocator.is_alive(entity)
+    }
+
+    /// Number of living entities.
+    pub fn len(&self) -> usize {
+        self.allocator.len()
+    }
+
+    /// Iterates over every living entity.
+    pub fn entities<'a>(&'a self) -> Box<Iterator<Item = Entity> + 'a> {
+        Box::new(self.archetypes.iter().flat_map(|archetype| archetype.entities().iter().cloned()))
+    }
+
+    /// Every archetype table, including empty ones.
+    pub fn archetypes(&self) -> &[Archetype] {
+        &self.archetypes
+    }
+
+    /// Attaches a component to the entity, returning the one it replaced.
+    ///
+    /// Adding a new component type moves the entity to another archetype.
+    pub fn insert<T>(&mut self, entity: Entity, component: T) -> Option<T> where T: Component {
+        assert!(self.is_alive(entity), "Cannot insert a component into dead entity {:?}", entity);
+
+        let location = self.locations[entity.index() as usize];
+        if tag::Index::indexes(TypeId::of::<T>()) {
+            self.index().update(entity, Some(&component));
+        }
+        if let Some(column) = self.archetypes[location.archetype].column_mut::<T>() {
+            return Some(::std::mem::replace(&mut column[location.row], component));
+        }
+
+        let target = {
+            let mut types = self.archetypes[location.archetype].types().to_vec();
+            let at = types.binary_search(&TypeId::of::<T>()).unwrap_err();
+            types.insert(at, TypeId::of::<T>());
+            match self.archetype_index.get(&types).cloned() {
+                Some(target) => target,
+                None => {
+                    let archetype = self.archetypes[location.archetype].with::<T>();
+                    self.add_archetype(types, archetype)
+                }
+            }
+        };
+
+        let mut left = Vec::new();
+        let row = {
+            let (source, target) = pair_mut(&mut self.archetypes, location.archetype, target);
+            if let Some(moved) = source.move_row(location.row, target, &mut left) {
+                self.locations[moved.index() as usize].row = location.row;
+            }
+            target.column_mut::<T>().expect("Target archetype is missing the new column").push(component);
+            target.push_entity(entity)
+        };
+        debug_assert!(left.is_empty());
+        self.set_location(entity, Location { archetype: target, row: row });
+        None
+    }
+
+    /// Detaches a component from the entity and hands it back.
+    ///
+    /// The entity moves to the archetype without that component type.
+    pub fn remove<T>(&mut self, entity: Entity) -> Option<T> where T: Component {
+        if !self.is_alive(entity) {
+            return None;
+        }
+
+        let location = self.locations[entity.index() as usize];
+        if !self.archetypes[location.archetype].has(TypeId::of::<T>()) {
+            return None;
+        }
+        if tag::Index::indexes(TypeId::of::<T>()) {
+            self.index().update::<T>(entity, None);
+        }
+
+        let target = {
+            let mut types = self.archetypes[location.archetype].types().to_vec();
+            types.retain(|ty| *ty != TypeId::of::<T>());
+            match self.archetype_index.get(&types).cloned() {
+                Some(target) => target,
+                None => {
+                    let archetype = self.archetypes[location.archetype].without(TypeId::of::<T>());
+                    self.add_archetype(types, archetype)
+                }
+            }
+        };
+
+        let mut left = Vec::with_capacity(1);
+        let row = {
+            let (source, target) = pair_mut(&mut self.archetypes, location.archetype, target);
+            if let Some(moved) = source.move_row(location.row, target, &mut left) {
+                self.locations[moved.index() as usize].row = location.row;
+            }
+            target.push_entity(entity)
+        };
+        self.set_location(entity, Location { archetype: target, row: row });
+
+        let component = left.pop().expect("Removed component was not left behind");
+        Some(*component.downcast::<T>().ok().expect("Removed component has the wrong type"))
+    }
+
+    pub fn get<T>(&self, entity: Entity) -> Option<&T> where T: Component {
+        if !self.is_alive(entity) {
+            return None;
+        }
+
+        let location = self.locations[entity.index() as usize];
+        self.archetypes[location.archetype].column::<T>().map(|column| &column[location.row])
+    }
+
+    pub fn get_mut<T>(&mut self, entity: Entity) -> Option<&mut T> where T: Component {
+        if !self.is_alive(entity) {
+            return None;
+        }
+
+        self.changing(TypeId::of::<T>());
+        let location = self.locations[entity.index() as usize];
+        match self.archetypes[location.archetype].column_mut::<T>() {
+            Some(column) => Some(&mut column[location.row]),
+            None => None,
+        }
+    }
+
+    /// Like `get_mut`, without borrowing the scene mutably. The caller
+    /// guarantees no other reference to the component is alive.
+    pub unsafe fn get_unchecked_mut<T>(&self, entity: Entity) -> Option<&mut T> where T: Component {
+        if !self.is_alive(entity) {
+            return None;
+        }
+
+        self.changing(TypeId::of::<T>());
+        let location = self.locations[entity.index() as usize];
+        self.archetypes[location.archetype].column_ptr::<T>().map(|column| &mut *column.offset(location.row as isize))
+    }
+
+    pub fn has<T>(&self, entity: Entity) -> bool where T: Component {
+        self.is_alive(entity) && self.archetypes[self.locations[entity.index() as usize].archetype].has(TypeId::of::<T>())
+    }
+
+    /// Iterates over every entity that has all the components of `Q`.
+    ///
+    /// ```ignore
+    /// for (entity, (position, velocity)) in scene.query::<(&mut Position, &Velocity)>() { ... }
+    /// ```
+    pub fn query<'a, Q>(&'a mut self) -> QueryIter<'a, Q> where Q: Query<'a> {
+        self.querying::<Q>();
+        QueryIter::new(&mut self.archetypes)
+    }
+
+    /// Like `query`, without borrowing the scene mutably, so systems can run
+    /// queries side by side. The caller guarantees nothing else accesses the
+    /// components `Q` writes, or writes the ones it reads, meanwhile.
+    pub unsafe fn query_unchecked<'a, Q>(&'a self) -> QueryIter<'a, Q> where Q: Query<'a> {
+        self.querying::<Q>();
+        QueryIter::new_unchecked(&self.archetypes)
+    }
+
+    /// Adds a global value, handing back the one of the same type it
+    /// replaces.
+    pub fn insert_global<T>(&mut self, value: T) -> Option<T> where T: Component {
+        self.globals.insert(value)
+    }
+
+    pub fn remove_global<T>(&mut self) -> Option<T> where T: Component {
+        self.globals.remove::<T>()
+    }
+
+    pub fn global<T>(&self) -> Option<&T> where T: Component {
+        self.globals.get::<T>()
+    }
+
+    pub fn global_mut<T>(&mut self) -> Option<&mut T> where T: Component {
+        self.globals.get_mut::<T>()
+    }
+
+    /// Like `global_mut`, without borrowing the scene mutably. The caller
+    /// guarantees no other reference to the global is alive.
+    pub unsafe fn global_unchecked_mut<T>(&self) -> Option<&mut T> where T: Component {
+        self.globals.get_ptr::<T>().map(|value| &mut *value)
+    }
+
+    /// Iterates over every entity with a `T` without needing the scene
+    /// mutably. Use `query` to fetch several components at once.
+    pub fn iter<'a, T>(&'a self) -> Box<Iterator<Item = (Entity, &'a T)> + 'a> where T: Component {
+        Box::new(self.archetypes.iter().flat_map(|archetype| {
+            archetype.entities().iter().cloned().zip(archetype.column::<T>().map(|column| &column[..]).unwrap_or(&[]))
+        }))
+    }
+
+    /// The name and tag index, caught up first if it went stale.
+    fn index(&self) -> MutexGuard<tag::Index> {
+        let mut index = self.index.lock().expect("Name and tag index poisoned");
+        if self.stale_index.swap(false, Ordering::SeqCst) {
+            index.sync(&self.archetypes);
+        }
+        index
+    }
+
+    /// Notes that components of the type are handed out mutably, so if they
+    /// are names or tags the index may go stale.
+    fn changing(&self, ty: TypeId) {
+        if tag::Index::indexes(ty) {
+            self.stale_index.store(true, Ordering::SeqCst);
+        }
+    }
+
+    fn querying<'a, Q>(&self) where Q: Query<'a> {
+        let mut access = Vec::new();
+        Q::access(&mut access);
+        for (ty, write) in access {
+            if write {
+                self.changing(ty);
+            }
+        }
+    }
+
+    fn set_location(&mut self, entity: Entity, location: Location) {
+        let index = entity.index() as usize;
+        if self.locations.len() <= index {
+            self.locations.resize(index + 1, Location { archetype: 0, row: 0 });
+        }
+        self.locations[index] = location;
+    }
+
+    fn add_archetype(&mut self, types: Vec<TypeId>, archetype: Archetype) -> usize {
+        self.archetypes.push(archetype);
+        self.archetype_index.insert(types, self.archetypes.len() - 1);
+        self.archetypes.len() - 1
+    }
 }
 
-/// Data concerning a single entity
-pub struct Entity<'a> {
-    resources: [&'a Resource],
-    position: Vector3,
+/// Borrows two distinct elements of a slice mutably.
+fn pair_mut<T>(slice: &mut [T], a: usize, b: usize) -> (&mut T, &mut T) {
+    assert!(a != b);
+    if a < b {
+        let (left, right) = slice.split_at_mut(b);
+        (&mut left[a], &mut right[0])
+    } else {
+        let (left, right) = slice.split_at_mut(a);
+        (&mut right[0], &mut left[b])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::std::any::TypeId;
+    use super::Scene;
+
+    #[derive(Debug, PartialEq)]
+    struct Position(i32);
+
+    #[derive(Debug, PartialEq)]
+    struct Velocity(i32);
+
+    /// The component types of the archetype the entity is stored in.
+    fn types_of(scene: &Scene, entity: super::Entity) -> Vec<TypeId> {
+        scene.archetypes[scene.locations[entity.index() as usize].archetype].types().to_vec()
+    }
+
+    #[test]
+    fn insert_moves_the_entity_to_the_archetype_with_the_component() {
+        let mut scene = Scene::new();
+        let entities = (0..3).map(|i| {
+            let entity = scene.spawn();
+            scene.insert(entity, Position(i));
+            entity
+        }).collect::<Vec<_>>();
+
+        // Moving the first row out fills its place with the last one.
+        assert_eq!(scene.insert(entities[0], Velocity(10)), None);
+        let mut both = vec![TypeId::of::<Position>(), TypeId::of::<Velocity>()];
+        both.sort();
+        assert_eq!(types_of(&scene, entities[0]), both);
+        assert_eq!(types_of(&scene, entities[2]), vec![TypeId::of::<Position>()]);
+        for (i, &entity) in entities.iter().enumerate() {
+            assert_eq!(scene.get::<Position>(entity), Some(&Position(i as i32)));
+        }
+        assert_eq!(scene.get::<Velocity>(entities[0]), Some(&Velocity(10)));
+        assert_eq!(scene.get::<Velocity>(entities[1]), None);
+
+        // Inserting a type the entity has replaces it where it is.
+        assert_eq!(scene.insert(entities[0], Velocity(20)), Some(Velocity(10)));
+        assert_eq!(types_of(&scene, entities[0]), both);
+        assert_eq!(scene.get::<Velocity>(entities[0]), Some(&Velocity(20)));
+    }
+
+    #[test]
+    fn remove_moves_the_entity_to_the_archetype_without_the_component() {
+        let mut scene = Scene::new();
+        let entities = (0..3).map(|i| {
+            let entity = scene.spawn();
+            scene.insert(entity, Position(i));
+            scene.insert(entity, Velocity(i * 10));
+            entity
+        }).collect::<Vec<_>>();
+
+        assert_eq!(scene.remove::<Velocity>(entities[0]), Some(Velocity(0)));
+        assert_eq!(scene.remove::<Velocity>(entities[0]), None);
+        assert_eq!(types_of(&scene, entities[0]), vec![TypeId::of::<Position>()]);
+        for (i, &entity) in entities.iter().enumerate().skip(1) {
+            assert_eq!(scene.get::<Position>(entity), Some(&Position(i as i32)));
+            assert_eq!(scene.get::<Velocity>(entity), Some(&Velocity(i as i32 * 10)));
+        }
+
+        assert_eq!(scene.remove::<Position>(entities[0]), Some(Position(0)));
+        assert_eq!(types_of(&scene, entities[0]), vec![]);
+        assert!(scene.is_alive(entities[0]));
+        assert_eq!(scene.len(), 3);
+        assert_eq!(scene.iter::<Velocity>().count(), 2);
+    }
+
+    #[test]
+    fn despawned_rows_are_filled_by_other_entities() {
+        let mut scene = Scene::new();
+        let entities = (0..3).map(|i| {
+            let entity = scene.spawn();
+            scene.insert(entity, Position(i));
+            entity
+        }).collect::<Vec<_>>();
+
+        assert!(scene.despawn(entities[0]));
+        assert!(!scene.despawn(entities[0]));
+        assert_eq!(scene.get::<Position>(entities[0]), None);
+        assert_eq!(scene.get::<Position>(entities[1]), Some(&Position(1)));
+        assert_eq!(scene.get::<Position>(entities[2]), Some(&Position(2)));
+        assert_eq!(scene.len(), 2);
+    }
 }
@@ -0,0 +1,12 @@
+use ::resource::Mesh;
+use ::std::sync::Arc;
+
+/// Draws a mesh at the entity's `WorldTransform`.
+///
+/// `path` is the key the mesh is stored under in `Resources`, so entities
+/// instancing the same geometry share one copy of it.
+#[derive(Clone, Debug)]
+pub struct Model {
+    pub path: String,
+    pub mesh: Arc<Mesh>,
+}
@@ -0,0 +1,311 @@
+use ::resource::Resources;
+use ::serde_json::{self, Value};
+use ::std::collections::{BTreeMap, HashMap};
+use ::std::fs::{self, File};
+use ::std::io::Read;
+use ::std::sync::Arc;
+use ::std::time::SystemTime;
+use super::{Children, Entity, Parent, Scene};
+use super::serialize::{Persist, Registry};
+
+error_chain! {
+    links {
+        Serialize(super::serialize::Error, super::serialize::ErrorKind);
+    }
+
+    foreign_links {
+        Io(::std::io::Error);
+        Json(::serde_json::Error);
+    }
+}
+
+/// One entity of a prefab and the entities below it. Components are stored
+/// like in scene files, under the key of their `Persist` implementation.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PrefabNode {
+    #[serde(default)]
+    pub components: BTreeMap<String, Value>,
+    #[serde(default)]
+    pub children: Vec<PrefabNode>,
+}
+
+/// A template for an entity subtree, e.g. a lamp made of a model and a light
+/// child, that can be instantiated many times.
+///
+/// Prefab files are JSON holding a single `PrefabNode`:
+///
+/// ```json
+/// {
+///     "components": { "model": "lamp.dae#Lamp" },
+///     "children": [
+///         { "components": { "light": { "type": "point", "range": 5.0, "color": [1, 1, 1], "intensity": 1.0 } } }
+///     ]
+/// }
+/// ```
+///
+/// Hierarchy comes from `children`. A `parent` component is ignored.
+#[derive(Clone, Debug, Default)]
+pub struct Prefab {
+    pub root: PrefabNode,
+}
+
+impl Prefab {
+    pub fn from_str(contents: &str) -> Result<Prefab> {
+        Ok(Prefab { root: try!(serde_json::from_str(contents)) })
+    }
+}
+
+/// Marks the root entity of a prefab instance.
+///
+/// `overrides` are merged over the components of the prefab's root: objects
+/// are merged key by key, anything else replaces the prefab's value, and
+/// `null` leaves the component out. Components the prefab does not have are
+/// added.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PrefabInstance {
+    pub path: String,
+    pub overrides: BTreeMap<String, Value>,
+}
+
+/// Marks the entities spawned below a `PrefabInstance`. They are despawned
+/// and spawned again whenever the instance is re-synced, so changes made to
+/// them at runtime are lost.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PrefabPart;
+
+struct Loaded {
+    prefab: Arc<Prefab>,
+    modified: Option<SystemTime>,
+}
+
+/// Loads prefab files, instantiates them, and keeps instances in sync with
+/// the files as they are edited.
+pub struct Prefabs {
+    loaded: HashMap<String, Loaded>,
+}
+
+impl Prefabs {
+    pub fn new() -> Prefabs {
+        Prefabs { loaded: HashMap::new() }
+    }
+
+    /// The prefab stored at `path`, reading the file the first time.
+    pub fn get(&mut self, path: &str) -> Result<Arc<Prefab>> {
+        if let Some(loaded) = self.loaded.get(path) {
+            return Ok(loaded.prefab.clone());
+        }
+
+        let loaded = try!(read(path));
+        let prefab = loaded.prefab.clone();
+        self.loaded.insert(path.to_owned(), loaded);
+        Ok(prefab)
+    }
+
+    /// Spawns an instance of the prefab at `path` with the given overrides,
+    /// returning its root entity.
+    pub fn instantiate(&mut self, scene: &mut Scene, resources: &mut Resources, registry: &Registry,
+                       path: &str, overrides: BTreeMap<String, Value>) -> Result<Entity> {
+        let prefab = try!(self.get(path));
+        let root = scene.spawn();
+        let instance = PrefabInstance { path: path.to_owned(), overrides: overrides };
+        try!(build(scene, resources, registry, root, &prefab.root, Some(&instance.overrides)));
+        scene.insert(root, instance);
+        Ok(root)
+    }
+
+    /// Reloads prefab files that changed on disk since they were read and
+    /// re-syncs their instances. Returns the paths that were reloaded.
+    ///
+    /// A file that fails to parse is reported and keeps its previous
+    /// contents, so a half saved file does not break the scene.
+    pub fn poll(&mut self, scene: &mut Scene, resources: &mut Resources, registry: &Registry) -> Result<Vec<String>> {
+        let changed = self.loaded.iter()
+                                 .filter(|&(path, loaded)| modified(path).unwrap_or(None) != loaded.modified)
+                                 .map(|(path, _)| path.clone())
+                                 .collect::<Vec<_>>();
+
+        let mut reloaded = Vec::new();
+        for path in changed {
+            let loaded = match read(&path) {
+                Ok(loaded) => loaded,
+                Err(e) => {
+                    warn!("Could not reload prefab '{}': {}", path, e);
+                    // Don't retry until the file changes again.
+                    if let Some(previous) = self.loaded.get_mut(&path) {
+                        previous.modified = modified(&path).unwrap_or(None);
+                    }
+                    continue;
+                }
+            };
+            let old = self.loaded.insert(path.clone(), loaded).map(|old| old.prefab).unwrap_or_default();
+            let new = self.loaded[&path].prefab.clone();
+
+            let instances = scene.query::<&PrefabInstance>()
+                                 .filter(|&(_, instance)| instance.path == path)
+                                 .map(|(entity, _)| entity)
+                                 .collect::<Vec<_>>();
+            for entity in instances {
+                try!(resync(scene, resources, registry, entity, &old, &new));
+            }
+            info!("Reloaded prefab '{}'", path);
+            reloaded.push(path);
+        }
+        Ok(reloaded)
+    }
+
+    /// Rebuilds an instance from its prefab and overrides, e.g. after
+    /// changing its `overrides`. Returns false if `entity` is not an instance.
+    pub fn resync(&mut self, scene: &mut Scene, resources: &mut Resources, registry: &Registry, entity: Entity) -> Result<bool> {
+        let path = match scene.get::<PrefabInstance>(entity) {
+            Some(instance) => instance.path.clone(),
+            None => return Ok(false),
+        };
+        let prefab = try!(self.get(&path));
+        try!(resync(scene, resources, registry, entity, &prefab, &prefab));
+        Ok(true)
+    }
+}
+
+fn read(path: &str) -> Result<Loaded> {
+    let mut contents = String::new();
+    try!(try!(File::open(path)).read_to_string(&mut contents));
+    Ok(Loaded {
+        prefab: Arc::new(try!(Prefab::from_str(&contents))),
+        modified: try!(modified(path)),
+    })
+}
+
+/// Modification time of a file, if the platform keeps one.
+fn modified(path: &str) -> Result<Option<SystemTime>> {
+    Ok(try!(fs::metadata(path)).modified().ok())
+}
+
+/// Loads the components of `node` onto `entity` and spawns its children as
+/// `PrefabPart`s below it.
+fn build(scene: &mut Scene, resources: &mut Resources, registry: &Registry,
+         entity: Entity, node: &PrefabNode, overrides: Option<&BTreeMap<String, Value>>) -> Result<()> {
+    let mut components = node.components.clone();
+    for (key, value) in overrides.into_iter().flat_map(|overrides| overrides.iter()) {
+        let merged = match components.remove(key) {
+            Some(base) => merge(base, value.clone()),
+            None => value.clone(),
+        };
+        components.insert(key.clone(), merged);
+    }
+
+    for (key, value) in components {
+        if value.is_null() {
+            continue;
+        }
+        if key == Parent::key() {
+            warn!("Ignoring parent component in prefab, use children instead");
+            continue;
+        }
+        if !try!(registry.load_component(scene, entity, &key, value, resources)) {
+            warn!("Skipping unknown prefab component '{}'", key);
+        }
+    }
+
+    for child in node.children.iter() {
+        let part = scene.spawn();
+        scene.insert(part, PrefabPart);
+        scene.set_parent(part, Some(entity));
+        try!(build(scene, resources, registry, part, child, None));
+    }
+    Ok(())
+}
+
+/// Brings an instance built from `old` in line with `new`. The root keeps
+/// its entity, so references to it stay valid, and children that are not
+/// prefab parts are left alone.
+fn resync(scene: &mut Scene, resources: &mut Resources, registry: &Registry,
+          entity: Entity, old: &Prefab, new: &Prefab) -> Result<()> {
+    let instance = match scene.get::<PrefabInstance>(entity) {
+        Some(instance) => instance.clone(),
+        None => return Ok(()),
+    };
+
+    let parts = scene.get::<Children>(entity)
+                     .map(|children| children.0.iter().cloned().filter(|&child| scene.has::<PrefabPart>(child)).collect())
+                     .unwrap_or(Vec::new());
+    for part in parts {
+        despawn_recursive(scene, part);
+    }
+
+    // Drop the components the new prefab no longer provides.
+    for key in old.root.components.keys().chain(instance.overrides.keys()) {
+        let kept = new.root.components.contains_key(key) || instance.overrides.get(key).map_or(false, |value| !value.is_null());
+        if !kept {
+            registry.remove_component(scene, entity, key);
+        }
+    }
+    for (key, value) in instance.overrides.iter() {
+        if value.is_null() {
+            registry.remove_component(scene, entity, key);
+        }
+    }
+
+    build(scene, resources, registry, entity, &new.root, Some(&instance.overrides))
+}
+
+fn despawn_recursive(scene: &mut Scene, entity: Entity) {
+    let children = scene.children(entity).to_vec();
+    for child in children {
+        despawn_recursive(scene, child);
+    }
+    scene.despawn(entity);
+}
+
+/// Merges `patch` over `base`. Objects are merged key by key, anything else
+/// is replaced.
+fn merge(base: Value, patch: Value) -> Value {
+    match (base, patch) {
+        (Value::Object(mut base), Value::Object(patch)) => {
+            for (key, value) in patch {
+                let merged = match base.remove(&key) {
+                    Some(previous) => merge(previous, value),
+                    None => value,
+                };
+                base.insert(key, merged);
+            }
+            Value::Object(base)
+        },
+        (_, patch) => patch,
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct PrefabInstanceData {
+    path: String,
+    #[serde(default)]
+    overrides: BTreeMap<String, Value>,
+}
+
+impl Persist for PrefabInstance {
+    fn key() -> &'static str {
+        "prefab"
+    }
+
+    fn save(&self) -> super::serialize::Result<Value> {
+        Ok(try!(serde_json::to_value(PrefabInstanceData { path: self.path.clone(), overrides: self.overrides.clone() })))
+    }
+
+    fn load(value: Value, _: &mut Resources) -> super::serialize::Result<PrefabInstance> {
+        let data: PrefabInstanceData = try!(serde_json::from_value(value));
+        Ok(PrefabInstance { path: data.path, overrides: data.overrides })
+    }
+}
+
+impl Persist for PrefabPart {
+    fn key() -> &'static str {
+        "prefab_part"
+    }
+
+    fn save(&self) -> super::serialize::Result<Value> {
+        Ok(Value::Bool(true))
+    }
+
+    fn load(_: Value, _: &mut Resources) -> super::serialize::Result<PrefabPart> {
+        Ok(PrefabPart)
+    }
+}
@@ -0,0 +1,193 @@
+use ::std::any::TypeId;
+use super::{Archetype, Component, Entity};
+
+/// A set of components fetched together for every entity that has all of
+/// them, such as `(&Transform, &mut Velocity)` or `Option<&Name>`.
+pub trait Query<'a> {
+    type Item;
+    type State;
+
+    /// Records which component types are read (false) or written (true).
+    fn access(access: &mut Vec<(TypeId, bool)>);
+
+    /// Whether entities of the archetype can match the query.
+    fn matches(archetype: &Archetype) -> bool;
+
+    /// Grabs pointers to the columns the query needs from a matching
+    /// archetype. The caller guarantees the written columns are not accessed
+    /// elsewhere while the state is in use.
+    unsafe fn state(archetype: &Archetype) -> Self::State;
+
+    /// Fetches the components of a single row. The caller guarantees no
+    /// other reference to a written component is alive.
+    unsafe fn fetch(state: &Self::State, row: usize) -> Self::Item;
+}
+
+impl<'a, T> Query<'a> for &'a T where T: Component {
+    type Item = &'a T;
+    type State = *const T;
+
+    fn access(access: &mut Vec<(TypeId, bool)>) {
+        access.push((TypeId::of::<T>(), false));
+    }
+
+    fn matches(archetype: &Archetype) -> bool {
+        archetype.has(TypeId::of::<T>())
+    }
+
+    unsafe fn state(archetype: &Archetype) -> Self::State {
+        archetype.column::<T>().expect("Query state of a non-matching archetype").as_ptr()
+    }
+
+    unsafe fn fetch(state: &Self::State, row: usize) -> Self::Item {
+        &*state.offset(row as isize)
+    }
+}
+
+impl<'a, T> Query<'a> for &'a mut T where T: Component {
+    type Item = &'a mut T;
+    type State = *mut T;
+
+    fn access(access: &mut Vec<(TypeId, bool)>) {
+        access.push((TypeId::of::<T>(), true));
+    }
+
+    fn matches(archetype: &Archetype) -> bool {
+        archetype.has(TypeId::of::<T>())
+    }
+
+    unsafe fn state(archetype: &Archetype) -> Self::State {
+        archetype.column_ptr::<T>().expect("Query state of a non-matching archetype")
+    }
+
+    unsafe fn fetch(state: &Self::State, row: usize) -> Self::Item {
+        &mut *state.offset(row as isize)
+    }
+}
+
+impl<'a, T> Query<'a> for Option<&'a T> where T: Component {
+    type Item = Option<&'a T>;
+    type State = Option<*const T>;
+
+    fn access(access: &mut Vec<(TypeId, bool)>) {
+        access.push((TypeId::of::<T>(), false));
+    }
+
+    fn matches(_: &Archetype) -> bool {
+        true
+    }
+
+    unsafe fn state(archetype: &Archetype) -> Self::State {
+        archetype.column::<T>().map(|column| column.as_ptr())
+    }
+
+    unsafe fn fetch(state: &Self::State, row: usize) -> Self::Item {
+        state.map(|column| &*column.offset(row as isize))
+    }
+}
+
+macro_rules! impl_query {
+    ($($name:ident),*) => {
+        impl<'a, $($name),*> Query<'a> for ($($name,)*) where $($name: Query<'a>),* {
+            type Item = ($(<$name as Query<'a>>::Item,)*);
+            type State = ($(<$name as Query<'a>>::State,)*);
+
+            fn access(access: &mut Vec<(TypeId, bool)>) {
+                $($name::access(access);)*
+            }
+
+            fn matches(archetype: &Archetype) -> bool {
+                $($name::matches(archetype))&&*
+            }
+
+            unsafe fn state(archetype: &Archetype) -> Self::State {
+                ($($name::state(archetype),)*)
+            }
+
+            #[allow(non_snake_case)]
+            unsafe fn fetch(state: &Self::State, row: usize) -> Self::Item {
+                let ($(ref $name,)*) = *state;
+                ($($name::fetch($name, row),)*)
+            }
+        }
+    }
+}
+
+impl_query!(A);
+impl_query!(A, B);
+impl_query!(A, B, C);
+impl_query!(A, B, C, D);
+impl_query!(A, B, C, D, E);
+impl_query!(A, B, C, D, E, F);
+impl_query!(A, B, C, D, E, F, G);
+impl_query!(A, B, C, D, E, F, G, H);
+
+/// Panics if the same component type is requested twice and at least one of
+/// the requests writes to it, as that would hand out aliasing references.
+pub fn check_access(access: &[(TypeId, bool)]) {
+    for (i, &(ty, write)) in access.iter().enumerate() {
+        for &(other, other_write) in access[i + 1..].iter() {
+            if ty == other && (write || other_write) {
+                panic!("Query borrows the same component mutably more than once");
+            }
+        }
+    }
+}
+
+/// Iterator over the entities matching a `Query`, yielding the entity and
+/// its components. Walks one archetype table at a time.
+pub struct QueryIter<'a, Q> where Q: Query<'a> {
+    archetypes: ::std::slice::Iter<'a, Archetype>,
+    current: Option<(Q::State, &'a [Entity])>,
+    row: usize,
+}
+
+impl<'a, Q> QueryIter<'a, Q> where Q: Query<'a> {
+    pub fn new(archetypes: &'a mut [Archetype]) -> QueryIter<'a, Q> {
+        unsafe { QueryIter::new_unchecked(archetypes) }
+    }
+
+    /// Iterates without borrowing the archetypes mutably. The caller
+    /// guarantees nothing else accesses the components the query writes, or
+    /// writes the components it reads, while the iterator or its items live.
+    pub unsafe fn new_unchecked(archetypes: &'a [Archetype]) -> QueryIter<'a, Q> {
+        let mut access = Vec::new();
+        Q::access(&mut access);
+        check_access(&access);
+
+        QueryIter {
+            archetypes: archetypes.iter(),
+            current: None,
+            row: 0,
+        }
+    }
+}
+
+impl<'a, Q> Iterator for QueryIter<'a, Q> where Q: Query<'a> {
+    type Item = (Entity, Q::Item);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((ref state, entities)) = self.current {
+                if self.row < entities.len() {
+                    let row = self.row;
+                    self.row += 1;
+                    // Each row is visited once, so the references never alias.
+                    return Some((entities[row], unsafe { Q::fetch(state, row) }));
+                }
+            }
+
+            let archetype = match self.archetypes.next() {
+                Some(archetype) => archetype,
+                None => return None,
+            };
+            if archetype.len() == 0 || !Q::matches(archetype) {
+                continue;
+            }
+
+            let state = unsafe { Q::state(archetype) };
+            self.current = Some((state, archetype.entities()));
+            self.row = 0;
+        }
+    }
+}
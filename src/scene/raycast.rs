@@ -0,0 +1,377 @@
+use ::cgmath::{EuclideanSpace, InnerSpace, Matrix, Matrix3, Matrix4, Point3, SquareMatrix, Vector3};
+use ::resource::{Mesh, ModelData};
+use ::std::collections::HashMap;
+use ::std::sync::Arc;
+use super::{Aabb, Bvh, Entity, Model, Ray, Scene, WorldTransform};
+
+/// Triangles per leaf of a `MeshBvh`.
+const LEAF_SIZE: usize = 4;
+
+/// Where a ray hit the geometry of an entity.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Hit {
+    pub entity: Entity,
+    /// Index of the triangle in the mesh, i.e. its first index is at
+    /// `3 * triangle` in the index buffer.
+    pub triangle: usize,
+    /// Weights of the three corners of the triangle at the hit point.
+    pub barycentric: Vector3<f32>,
+    /// Distance along the ray, in multiples of its direction.
+    pub distance: f32,
+    pub point: Point3<f32>,
+    /// World space normal of the triangle, facing the ray.
+    pub normal: Vector3<f32>,
+}
+
+/// Triangle hit in mesh space.
+#[derive(Copy, Clone, Debug)]
+struct TriangleHit {
+    triangle: usize,
+    corners: [Point3<f32>; 3],
+    u: f32,
+    v: f32,
+    distance: f32,
+}
+
+#[derive(Clone, Debug)]
+enum MeshNode {
+    /// `triangles[start..end]` of the `MeshBvh`.
+    Leaf { aabb: Aabb, start: usize, end: usize },
+    Branch { aabb: Aabb, left: usize, right: usize },
+}
+
+impl MeshNode {
+    fn aabb(&self) -> &Aabb {
+        match *self {
+            MeshNode::Leaf { ref aabb, .. } | MeshNode::Branch { ref aabb, .. } => aabb,
+        }
+    }
+}
+
+/// Static bounding volume hierarchy over the triangles of a mesh, in the
+/// mesh's own space. Built once per mesh.
+#[derive(Clone, Debug)]
+pub struct MeshBvh {
+    /// Index and corners of every triangle, ordered so every leaf covers a
+    /// contiguous range.
+    triangles: Vec<(usize, [Point3<f32>; 3])>,
+    nodes: Vec<MeshNode>,
+}
+
+impl MeshBvh {
+    /// Builds the hierarchy by splitting triangles at the median centroid
+    /// along the longest axis until leaves are small enough. Triangles with
+    /// indices past the end of the vertex buffer are skipped.
+    pub fn new(model: &ModelData) -> MeshBvh {
+        let vertices = model.vertices();
+        let corner = |index: u16| vertices.get(index as usize).map(|vertex| Point3::from_vec(vertex.position));
+        let triangles = model.indices()
+                             .chunks(3)
+                             .enumerate()
+                             .filter(|&(_, triangle)| triangle.len() == 3)
+                             .filter_map(|(i, triangle)| match (corner(triangle[0]), corner(triangle[1]), corner(triangle[2])) {
+                                 (Some(a), Some(b), Some(c)) => Some((i, [a, b, c])),
+                                 _ => None,
+                             })
+                             .collect::<Vec<_>>();
+
+        let mut bvh = MeshBvh {
+            triangles: triangles,
+            nodes: Vec::new(),
+        };
+        if !bvh.triangles.is_empty() {
+            let count = bvh.triangles.len();
+            bvh.build(0, count);
+        }
+        bvh
+    }
+
+    /// Number of triangles in the hierarchy.
+    pub fn len(&self) -> usize {
+        self.triangles.len()
+    }
+
+    /// Bounds of the whole mesh, or `None` if it has no triangles.
+    pub fn aabb(&self) -> Option<Aabb> {
+        self.nodes.first().map(|node| *node.aabb())
+    }
+
+    /// Appends the node covering `triangles[start..end]` and its subtree.
+    /// Returns its index.
+    fn build(&mut self, start: usize, end: usize) -> usize {
+        let aabb = Aabb::from_points(self.triangles[start..end].iter().flat_map(|triangle| triangle.1.iter().cloned()))
+                        .expect("empty mesh bvh node");
+
+        let index = self.nodes.len();
+        if end - start <= LEAF_SIZE {
+            self.nodes.push(MeshNode::Leaf { aabb: aabb, start: start, end: end });
+            return index;
+        }
+
+        let extents = aabb.half_extents();
+        let axis = if extents.x >= extents.y && extents.x >= extents.z {
+            0
+        } else if extents.y >= extents.z {
+            1
+        } else {
+            2
+        };
+        let centroid = |triangle: &(usize, [Point3<f32>; 3])| triangle.1[0][axis] + triangle.1[1][axis] + triangle.1[2][axis];
+        self.triangles[start..end].sort_by(|a, b| centroid(a).partial_cmp(&centroid(b)).unwrap_or(::std::cmp::Ordering::Equal));
+
+        // Reserve our slot before the children so the root ends up first.
+        self.nodes.push(MeshNode::Leaf { aabb: aabb, start: start, end: end });
+        let middle = start + (end - start) / 2;
+        let left = self.build(start, middle);
+        let right = self.build(middle, end);
+        self.nodes[index] = MeshNode::Branch { aabb: aabb, left: left, right: right };
+        index
+    }
+
+    /// Nearest triangle hit by a ray in mesh space within `max_distance`.
+    fn raycast(&self, ray: &Ray, max_distance: f32) -> Option<TriangleHit> {
+        let mut best: Option<TriangleHit> = None;
+        let mut stack = Vec::new();
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+
+        while let Some(index) = stack.pop() {
+            let limit = best.map_or(max_distance, |hit| hit.distance);
+            let node = &self.nodes[index];
+            if node.aabb().ray_distance(ray, limit).is_none() {
+                continue;
+            }
+
+            match *node {
+                MeshNode::Leaf { start, end, .. } => {
+                    for &(triangle, ref corners) in self.triangles[start..end].iter() {
+                        let limit = best.map_or(max_distance, |hit| hit.distance);
+                        if let Some((u, v, distance)) = intersect(ray, corners, limit) {
+                            best = Some(TriangleHit { triangle: triangle, corners: *corners, u: u, v: v, distance: distance });
+                        }
+                    }
+                },
+                MeshNode::Branch { left, right, .. } => {
+                    stack.push(left);
+                    stack.push(right);
+                },
+            }
+        }
+        best
+    }
+}
+
+/// Möller–Trumbore ray/triangle intersection. Both sides of the triangle are
+/// hit. Returns the barycentric `u` and `v` of the second and third corners,
+/// and the distance along the ray.
+fn intersect(ray: &Ray, corners: &[Point3<f32>; 3], max_distance: f32) -> Option<(f32, f32, f32)> {
+    const EPSILON: f32 = 1e-7;
+
+    let edge1 = corners[1] - corners[0];
+    let edge2 = corners[2] - corners[0];
+    let p = ray.direction.cross(edge2);
+    let determinant = edge1.dot(p);
+    if determinant.abs() < EPSILON {
+        // The ray is parallel to the triangle, or the triangle is degenerate.
+        return None;
+    }
+
+    let inverse = 1.0 / determinant;
+    let t = ray.origin - corners[0];
+    let u = t.dot(p) * inverse;
+    if u < 0.0 || u > 1.0 {
+        return None;
+    }
+
+    let q = t.cross(edge1);
+    let v = ray.direction.dot(q) * inverse;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+
+    let distance = edge2.dot(q) * inverse;
+    if distance < 0.0 || distance > max_distance {
+        return None;
+    }
+    Some((u, v, distance))
+}
+
+/// Casts rays against the meshes of `Model` entities.
+///
+/// Candidates come from the scene `Bvh`, then each mesh is tested triangle by
+/// triangle through its own `MeshBvh`. Those are built the first time a mesh
+/// is hit and cached by model path, like the renderer caches uploads. A
+/// model whose path now holds a different mesh, e.g. after reloading it, gets
+/// its tree rebuilt.
+pub struct Raycaster {
+    /// The mesh each tree was built from, kept so it can't be freed and its
+    /// address reused by a different mesh.
+    meshes: HashMap<String, (Arc<Mesh>, Arc<MeshBvh>)>,
+}
+
+impl Raycaster {
+    pub fn new() -> Raycaster {
+        Raycaster { meshes: HashMap::new() }
+    }
+
+    /// The nearest hit within `max_distance`.
+    pub fn raycast(&mut self, scene: &Scene, bvh: &Bvh, ray: &Ray, max_distance: f32) -> Option<Hit> {
+        let mut best: Option<Hit> = None;
+        for (entity, entry) in bvh.raycast(ray, max_distance) {
+            // Candidates are sorted by where the ray enters their box, so no
+            // later one can be nearer than a hit before it.
+            if best.map_or(false, |hit| hit.distance < entry) {
+                break;
+            }
+
+            let limit = best.map_or(max_distance, |hit| hit.distance);
+            if let Some(hit) = self.raycast_entity(scene, entity, ray, limit) {
+                best = Some(hit);
+            }
+        }
+        best
+    }
+
+    /// The nearest hit on every entity the ray crosses within
+    /// `max_distance`, nearest first.
+    pub fn raycast_all(&mut self, scene: &Scene, bvh: &Bvh, ray: &Ray, max_distance: f32) -> Vec<Hit> {
+        let mut hits = bvh.raycast(ray, max_distance)
+                          .into_iter()
+                          .filter_map(|(entity, _)| self.raycast_entity(scene, entity, ray, max_distance))
+                          .collect::<Vec<_>>();
+        hits.sort_by(|a, b| a.distance.partial_cmp(&b.distance).unwrap_or(::std::cmp::Ordering::Equal));
+        hits
+    }
+
+    /// Tests a single entity, whether or not it is in a `Bvh`.
+    pub fn raycast_entity(&mut self, scene: &Scene, entity: Entity, ray: &Ray, max_distance: f32) -> Option<Hit> {
+        let (model, world) = match (scene.get::<Model>(entity), scene.get::<WorldTransform>(entity)) {
+            (Some(model), Some(world)) => (model, world.0),
+            _ => return None,
+        };
+        let inverse = match world.invert() {
+            Some(inverse) => inverse,
+            None => return None,
+        };
+
+        // Not normalizing the direction keeps distances the same in both spaces.
+        let local = Ray::new(Point3::from_homogeneous(inverse * ray.origin.to_homogeneous()),
+                             (inverse * ray.direction.extend(0.0)).truncate());
+        let mesh = self.mesh(model);
+        let hit = match mesh.raycast(&local, max_distance) {
+            Some(hit) => hit,
+            None => return None,
+        };
+
+        let corners = hit.corners;
+        let face = (corners[1] - corners[0]).cross(corners[2] - corners[0]);
+        let mut normal = (normal_matrix(&world) * face).normalize();
+        if normal.dot(ray.direction) > 0.0 {
+            normal = -normal;
+        }
+
+        Some(Hit {
+            entity: entity,
+            triangle: hit.triangle,
+            barycentric: Vector3::new(1.0 - hit.u - hit.v, hit.u, hit.v),
+            distance: hit.distance,
+            point: ray.at(hit.distance),
+            normal: normal,
+        })
+    }
+
+    fn mesh(&mut self, model: &Model) -> Arc<MeshBvh> {
+        if let Some(&(ref mesh, ref bvh)) = self.meshes.get(&model.path) {
+            if Arc::ptr_eq(mesh, &model.mesh) {
+                return bvh.clone();
+            }
+        }
+
+        let bvh = Arc::new(MeshBvh::new(&*model.mesh));
+        self.meshes.insert(model.path.clone(), (model.mesh.clone(), bvh.clone()));
+        bvh
+    }
+}
+
+/// Inverse transpose of the upper 3x3 of `world`, which keeps normals
+/// perpendicular under non-uniform scale.
+fn normal_matrix(world: &Matrix4<f32>) -> Matrix3<f32> {
+    let linear = Matrix3::from_cols(world.x.truncate(), world.y.truncate(), world.z.truncate());
+    linear.invert().map(|inverse| inverse.transpose()).unwrap_or(linear)
+}
+
+#[cfg(test)]
+mod tests {
+    use ::cgmath::{Point3, Vector3};
+    use ::core::Vertex;
+    use ::core::rng::Rng;
+    use ::resource::Mesh;
+    use super::{intersect, MeshBvh};
+    use super::super::Ray;
+
+    /// The triangle with corners at the origin, +X and +Y.
+    fn corners() -> [Point3<f32>; 3] {
+        [Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 0.0, 0.0), Point3::new(0.0, 1.0, 0.0)]
+    }
+
+    fn point(rng: &mut Rng, scale: f64) -> Vector3<f32> {
+        Vector3::new(rng.range(-scale, scale) as f32, rng.range(-scale, scale) as f32, rng.range(-scale, scale) as f32)
+    }
+
+    #[test]
+    fn intersect_hits_inside_and_on_the_edges() {
+        let down = Vector3::new(0.0, 0.0, -1.0);
+        assert_eq!(intersect(&Ray::new(Point3::new(0.25, 0.5, 2.0), down), &corners(), 10.0), Some((0.25, 0.5, 2.0)));
+        assert_eq!(intersect(&Ray::new(Point3::new(0.5, 0.5, 1.0), down), &corners(), 10.0), Some((0.5, 0.5, 1.0)));
+        assert_eq!(intersect(&Ray::new(Point3::new(0.0, 0.0, 1.0), down), &corners(), 10.0), Some((0.0, 0.0, 1.0)));
+        assert_eq!(intersect(&Ray::new(Point3::new(0.75, 0.5, 1.0), down), &corners(), 10.0), None);
+        // Both sides are hit.
+        let up = Vector3::new(0.0, 0.0, 1.0);
+        assert_eq!(intersect(&Ray::new(Point3::new(0.25, 0.25, -1.0), up), &corners(), 10.0), Some((0.25, 0.25, 1.0)));
+    }
+
+    #[test]
+    fn intersect_misses_parallel_rays_and_hits_behind_or_too_far() {
+        let along = Ray::new(Point3::new(-1.0, 0.25, 0.0), Vector3::new(1.0, 0.0, 0.0));
+        assert_eq!(intersect(&along, &corners(), 10.0), None);
+        let above = Ray::new(Point3::new(-1.0, 0.25, 1.0), Vector3::new(1.0, 0.0, 0.0));
+        assert_eq!(intersect(&above, &corners(), 10.0), None);
+
+        let away = Ray::new(Point3::new(0.25, 0.25, 1.0), Vector3::new(0.0, 0.0, 1.0));
+        assert_eq!(intersect(&away, &corners(), 10.0), None);
+        let toward = Ray::new(Point3::new(0.25, 0.25, 1.0), Vector3::new(0.0, 0.0, -1.0));
+        assert_eq!(intersect(&toward, &corners(), 0.5), None);
+    }
+
+    #[test]
+    fn mesh_raycast_matches_brute_force() {
+        let mut rng = Rng::new(7);
+        let mut mesh = Mesh { vertices: Vec::new(), indices: Vec::new() };
+        for i in 0..200 {
+            let center = point(&mut rng, 10.0);
+            for _ in 0..3 {
+                mesh.vertices.push(Vertex::new(center + point(&mut rng, 1.0)));
+            }
+            mesh.indices.extend(&[i * 3, i * 3 + 1, i * 3 + 2]);
+        }
+        let bvh = MeshBvh::new(&mesh);
+        assert_eq!(bvh.len(), 200);
+
+        let mut hits = 0;
+        for _ in 0..500 {
+            let ray = Ray::new(Point3::new(0.0, 0.0, 0.0) + point(&mut rng, 15.0), point(&mut rng, 1.0));
+            let brute = bvh.triangles
+                           .iter()
+                           .filter_map(|&(triangle, ref corners)| intersect(&ray, corners, 30.0).map(|hit| (triangle, hit.2)))
+                           .fold(None, |best: Option<(usize, f32)>, hit| match best {
+                               Some(best) if best.1 <= hit.1 => Some(best),
+                               _ => Some(hit),
+                           });
+            let found = bvh.raycast(&ray, 30.0).map(|hit| (hit.triangle, hit.distance));
+            assert_eq!(found, brute);
+            hits += found.is_some() as usize;
+        }
+        assert!(hits > 0);
+    }
+}
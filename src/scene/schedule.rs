@@ -0,0 +1,509 @@
+use ::rayon::{ThreadPool, ThreadPoolBuilder};
+use ::std::any::TypeId;
+use super::{Component, Entity, Query, QueryIter, Scene};
+
+error_chain! {
+    errors {
+        UnknownStage(stage: String) {
+            description("unknown stage")
+            display("no stage named '{}'", stage)
+        }
+        DuplicateStage(stage: String) {
+            description("stage added twice")
+            display("stage '{}' was already added", stage)
+        }
+        DuplicateSystem(system: String) {
+            description("system added twice")
+            display("system '{}' was already added", system)
+        }
+        UnknownSystem(system: String, other: String) {
+            description("system ordered against an unknown system")
+            display("system '{}' is ordered against '{}', which is not in the same stage", system, other)
+        }
+        Cycle(stage: String) {
+            description("systems ordered in a cycle")
+            display("the systems of stage '{}' are ordered in a cycle", stage)
+        }
+        Ambiguous(stage: String, first: String, second: String) {
+            description("conflicting systems without an order")
+            display("systems '{}' and '{}' of stage '{}' conflict but are not ordered", first, second, stage)
+        }
+    }
+}
+
+/// The component types a system reads and writes. Two systems conflict if
+/// one writes a type the other uses; conflicting systems never run at the
+/// same time. Globals are declared the same way, by their type.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Access {
+    reads: Vec<TypeId>,
+    writes: Vec<TypeId>,
+    exclusive: bool,
+}
+
+impl Access {
+    /// No access at all.
+    pub fn new() -> Access {
+        Access::default()
+    }
+
+    /// Access to the whole scene, conflicting with every other system.
+    pub fn exclusive() -> Access {
+        Access { reads: Vec::new(), writes: Vec::new(), exclusive: true }
+    }
+
+    pub fn read<T>(mut self) -> Access where T: Component {
+        push(&mut self.reads, TypeId::of::<T>());
+        self
+    }
+
+    pub fn write<T>(mut self) -> Access where T: Component {
+        push(&mut self.writes, TypeId::of::<T>());
+        self
+    }
+
+    /// Everything the query `Q` reads and writes.
+    pub fn query<'a, Q>(mut self) -> Access where Q: Query<'a> {
+        let mut access = Vec::new();
+        Q::access(&mut access);
+        for (ty, write) in access {
+            push(if write { &mut self.writes } else { &mut self.reads }, ty);
+        }
+        self
+    }
+
+    pub fn is_exclusive(&self) -> bool {
+        self.exclusive
+    }
+
+    pub fn conflicts(&self, other: &Access) -> bool {
+        self.exclusive || other.exclusive ||
+        self.writes.iter().any(|ty| other.reads.contains(ty) || other.writes.contains(ty)) ||
+        other.writes.iter().any(|ty| self.reads.contains(ty))
+    }
+
+    fn reads(&self, ty: TypeId) -> bool {
+        self.reads.contains(&ty) || self.writes.contains(&ty)
+    }
+
+    fn writes(&self, ty: TypeId) -> bool {
+        self.writes.contains(&ty)
+    }
+}
+
+fn push(types: &mut Vec<TypeId>, ty: TypeId) {
+    if !types.contains(&ty) {
+        types.push(ty);
+    }
+}
+
+/// The view of the scene a non-exclusive system gets. It can only touch the
+/// components and globals its `Access` declares and cannot spawn, despawn,
+/// insert or remove; use an exclusive system for that.
+pub struct SystemScene<'a> {
+    scene: &'a Scene,
+    access: &'a Access,
+    name: &'a str,
+}
+
+impl<'a> SystemScene<'a> {
+    pub fn is_alive(&self, entity: Entity) -> bool {
+        self.scene.is_alive(entity)
+    }
+
+    pub fn has<T>(&self, entity: Entity) -> bool where T: Component {
+        self.scene.has::<T>(entity)
+    }
+
+    pub fn get<T>(&self, entity: Entity) -> Option<&T> where T: Component {
+        self.check(TypeId::of::<T>(), false);
+        self.scene.get::<T>(entity)
+    }
+
+    pub fn get_mut<T>(&mut self, entity: Entity) -> Option<&mut T> where T: Component {
+        self.check(TypeId::of::<T>(), true);
+        unsafe { self.scene.get_unchecked_mut::<T>(entity) }
+    }
+
+    pub fn global<T>(&self) -> Option<&T> where T: Component {
+        self.check(TypeId::of::<T>(), false);
+        self.scene.global::<T>()
+    }
+
+    pub fn global_mut<T>(&mut self) -> Option<&mut T> where T: Component {
+        self.check(TypeId::of::<T>(), true);
+        unsafe { self.scene.global_unchecked_mut::<T>() }
+    }
+
+    /// Like `Scene::query`. Only one query can be iterated at a time.
+    pub fn query<'b, Q>(&'b mut self) -> QueryIter<'b, Q> where Q: Query<'b> {
+        let mut access = Vec::new();
+        Q::access(&mut access);
+        for (ty, write) in access {
+            self.check(ty, write);
+        }
+        unsafe { self.scene.query_unchecked() }
+    }
+
+    /// Panics if the system did not declare the access.
+    fn check(&self, ty: TypeId, write: bool) {
+        let declared = if write { self.access.writes(ty) } else { self.access.reads(ty) };
+        if !declared {
+            panic!("System '{}' {} a component it did not declare", self.name, if write { "writes" } else { "reads" });
+        }
+    }
+}
+
+enum Run {
+    Shared(Box<FnMut(&mut SystemScene) + Send>),
+    Exclusive(Box<FnMut(&mut Scene) + Send>),
+}
+
+/// A named piece of logic run once per `Schedule::run`.
+///
+/// Within a stage, systems run in the order given by `after` and `before`.
+/// Systems that conflict must be ordered that way; anything else may run in
+/// parallel.
+pub struct System {
+    name: String,
+    access: Access,
+    run: Run,
+    after: Vec<String>,
+    before: Vec<String>,
+}
+
+impl System {
+    pub fn new<F>(name: &str, access: Access, run: F) -> System where F: FnMut(&mut SystemScene) + Send + 'static {
+        System {
+            name: name.to_owned(),
+            access: access,
+            run: Run::Shared(Box::new(run)),
+            after: Vec::new(),
+            before: Vec::new(),
+        }
+    }
+
+    /// A system that gets the whole scene mutably and runs alone.
+    pub fn exclusive<F>(name: &str, run: F) -> System where F: FnMut(&mut Scene) + Send + 'static {
+        System {
+            name: name.to_owned(),
+            access: Access::exclusive(),
+            run: Run::Exclusive(Box::new(run)),
+            after: Vec::new(),
+            before: Vec::new(),
+        }
+    }
+
+    /// Runs after the named system of the same stage.
+    pub fn after(mut self, name: &str) -> System {
+        self.after.push(name.to_owned());
+        self
+    }
+
+    /// Runs before the named system of the same stage.
+    pub fn before(mut self, name: &str) -> System {
+        self.before.push(name.to_owned());
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn access(&self) -> &Access {
+        &self.access
+    }
+
+    fn run(&mut self, scene: &Scene) {
+        match self.run {
+            Run::Shared(ref mut run) => run(&mut SystemScene { scene: scene, access: &self.access, name: &self.name }),
+            Run::Exclusive(_) => unreachable!("exclusive system run in parallel"),
+        }
+    }
+}
+
+struct Stage {
+    name: String,
+    systems: Vec<System>,
+    /// Indices of the systems that run together, in order. `None` until the
+    /// stage is built.
+    batches: Option<Vec<Vec<usize>>>,
+}
+
+/// Runs systems stage by stage, replacing hand-written update calls.
+///
+/// Stages run one after the other in the order they were added. Within a
+/// stage, systems whose `Access` does not conflict run in parallel on a
+/// thread pool.
+///
+/// ```ignore
+/// let mut schedule = Schedule::new();
+/// schedule.add_stage("update");
+/// schedule.add_system("update", System::new("spin", Access::new().write::<Transform>(), |scene| {
+///     for (_, transform) in scene.query::<&mut Transform>() { ... }
+/// }));
+/// schedule.run(&mut scene);
+/// ```
+pub struct Schedule {
+    stages: Vec<Stage>,
+    pool: ThreadPool,
+}
+
+impl Schedule {
+    /// A schedule with a thread per core.
+    pub fn new() -> Schedule {
+        Schedule::with_threads(0)
+    }
+
+    /// A schedule running at most `threads` systems at once, or one per
+    /// core if 0.
+    pub fn with_threads(threads: usize) -> Schedule {
+        Schedule {
+            stages: Vec::new(),
+            pool: ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .thread_name(|i| format!("system-{}", i))
+                .build()
+                .expect("Could not start the system thread pool"),
+        }
+    }
+
+    /// Adds a stage that runs after the existing ones.
+    pub fn add_stage(&mut self, name: &str) -> Result<()> {
+        if self.stages.iter().any(|stage| stage.name == name) {
+            bail!(ErrorKind::DuplicateStage(name.to_owned()));
+        }
+        self.stages.push(Stage { name: name.to_owned(), systems: Vec::new(), batches: None });
+        Ok(())
+    }
+
+    /// Fails if the system conflicts with one already in the stage and
+    /// neither is ordered before the other.
+    pub fn add_system(&mut self, stage: &str, system: System) -> Result<()> {
+        if self.stages.iter().any(|stage| stage.systems.iter().any(|other| other.name == system.name)) {
+            bail!(ErrorKind::DuplicateSystem(system.name));
+        }
+        let stage = match self.stages.iter_mut().find(|other| other.name == stage) {
+            Some(stage) => stage,
+            None => bail!(ErrorKind::UnknownStage(stage.to_owned())),
+        };
+        stage.systems.push(system);
+
+        // Systems added later may still be missing from the order.
+        let reach = try!(order(stage, false)).1;
+        let new = stage.systems.len() - 1;
+        let ambiguous = (0..new).find(|&other| {
+            !reach[other][new] && !reach[new][other] && stage.systems[other].access.conflicts(&stage.systems[new].access)
+        });
+        if let Some(other) = ambiguous {
+            let system = stage.systems.pop().expect("System was just added");
+            bail!(ErrorKind::Ambiguous(stage.name.clone(), stage.systems[other].name.clone(), system.name));
+        }
+        stage.batches = None;
+        Ok(())
+    }
+
+    /// Works out which systems can run together. Called by `run` whenever
+    /// systems were added.
+    pub fn build(&mut self) -> Result<()> {
+        for stage in self.stages.iter_mut().filter(|stage| stage.batches.is_none()) {
+            stage.batches = Some(try!(plan(stage)));
+        }
+        Ok(())
+    }
+
+    /// Runs every system once.
+    pub fn run(&mut self, scene: &mut Scene) -> Result<()> {
+        try!(self.build());
+
+        let pool = &self.pool;
+        for stage in self.stages.iter_mut() {
+            let systems = &mut stage.systems;
+            for batch in stage.batches.as_ref().expect("Stage was not built").iter() {
+                if batch.len() == 1 {
+                    let system = &mut systems[batch[0]];
+                    match system.run {
+                        Run::Shared(ref mut run) => run(&mut SystemScene { scene: scene, access: &system.access, name: &system.name }),
+                        Run::Exclusive(ref mut run) => run(scene),
+                    }
+                    continue;
+                }
+
+                let scene = &*scene;
+                pool.scope(|scope| {
+                    for (_, system) in systems.iter_mut().enumerate().filter(|&(i, _)| batch.contains(&i)) {
+                        scope.spawn(move |_| system.run(scene));
+                    }
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The systems of a stage that run directly before each other, and the
+/// transitive closure of that. Unless `strict`, orderings against systems
+/// that are not in the stage are left out.
+fn order(stage: &Stage, strict: bool) -> Result<(Vec<Vec<bool>>, Vec<Vec<bool>>)> {
+    let systems = &stage.systems;
+    let count = systems.len();
+    let index = |system: &System, name: &String| -> Result<Option<usize>> {
+        match systems.iter().position(|other| other.name == *name) {
+            Some(i) => Ok(Some(i)),
+            None if strict => bail!(ErrorKind::UnknownSystem(system.name.clone(), name.clone())),
+            None => Ok(None),
+        }
+    };
+
+    // edges[a][b]: a runs before b. reach is the transitive closure.
+    let mut edges = vec![vec![false; count]; count];
+    for (i, system) in systems.iter().enumerate() {
+        for name in system.after.iter() {
+            if let Some(other) = try!(index(system, name)) {
+                edges[other][i] = true;
+            }
+        }
+        for name in system.before.iter() {
+            if let Some(other) = try!(index(system, name)) {
+                edges[i][other] = true;
+            }
+        }
+    }
+    let mut reach = edges.clone();
+    for k in 0..count {
+        for i in 0..count {
+            if reach[i][k] {
+                for j in 0..count {
+                    if reach[k][j] {
+                        reach[i][j] = true;
+                    }
+                }
+            }
+        }
+    }
+    Ok((edges, reach))
+}
+
+/// Groups the systems of a stage into batches that can run in parallel.
+/// Conflicting systems are always ordered, `add_system` sees to that.
+fn plan(stage: &Stage) -> Result<Vec<Vec<usize>>> {
+    let count = stage.systems.len();
+    let (edges, reach) = try!(order(stage, true));
+    if (0..count).any(|i| reach[i][i]) {
+        bail!(ErrorKind::Cycle(stage.name.clone()));
+    }
+
+    // Each batch holds the systems whose predecessors all ran already.
+    let mut done = vec![false; count];
+    let mut batches = Vec::new();
+    while done.iter().any(|&done| !done) {
+        let batch = (0..count).filter(|&i| !done[i] && (0..count).all(|p| done[p] || !edges[p][i])).collect::<Vec<_>>();
+        debug_assert!(!batch.is_empty());
+        for &i in batch.iter() {
+            done[i] = true;
+        }
+        batches.push(batch);
+    }
+    Ok(batches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Access, ErrorKind, Schedule, System};
+    use super::super::Scene;
+
+    struct Position;
+    struct Velocity;
+    struct Steps(Vec<&'static str>);
+
+    fn system(name: &str, access: Access) -> System {
+        System::new(name, access, |_| ())
+    }
+
+    fn batches(schedule: &mut Schedule) -> Vec<Vec<usize>> {
+        schedule.build().unwrap();
+        schedule.stages[0].batches.clone().unwrap()
+    }
+
+    #[test]
+    fn conflicting_systems_must_be_ordered() {
+        let mut schedule = Schedule::with_threads(2);
+        schedule.add_stage("update").unwrap();
+        schedule.add_system("update", system("read", Access::new().read::<Position>())).unwrap();
+        schedule.add_system("update", system("also_read", Access::new().read::<Position>())).unwrap();
+
+        match *schedule.add_system("update", system("write", Access::new().write::<Position>())).unwrap_err().kind() {
+            ErrorKind::Ambiguous(ref stage, ref first, ref second) => {
+                assert_eq!((&**stage, &**first, &**second), ("update", "read", "write"));
+            },
+            ref kind => panic!("unexpected error {:?}", kind),
+        }
+        // Globals conflict like components.
+        schedule.add_system("update", system("steps", Access::new().write::<Steps>())).unwrap();
+        match *schedule.add_system("update", system("global", Access::new().read::<Steps>())).unwrap_err().kind() {
+            ErrorKind::Ambiguous(_, ref first, _) => assert_eq!(first, "steps"),
+            ref kind => panic!("unexpected error {:?}", kind),
+        }
+
+        // The rejected system was not kept, so it can be added again ordered.
+        let write = system("write", Access::new().write::<Position>()).after("read").after("also_read");
+        schedule.add_system("update", write).unwrap();
+        // Ordered through `write`, without naming `read`.
+        let last = system("last", Access::new().write::<Position>()).after("write");
+        schedule.add_system("update", last).unwrap();
+        match *schedule.add_system("update", System::exclusive("alone", |_| ())).unwrap_err().kind() {
+            ErrorKind::Ambiguous(..) => (),
+            ref kind => panic!("unexpected error {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn systems_are_batched_after_their_predecessors() {
+        let mut schedule = Schedule::with_threads(2);
+        schedule.add_stage("update").unwrap();
+        schedule.add_system("update", system("a", Access::new().read::<Position>())).unwrap();
+        schedule.add_system("update", system("b", Access::new().write::<Velocity>())).unwrap();
+        schedule.add_system("update", system("c", Access::new().write::<Position>()).after("a")).unwrap();
+        schedule.add_system("update", system("d", Access::new().read::<Velocity>()).after("b")).unwrap();
+        assert_eq!(batches(&mut schedule), vec![vec![0, 1], vec![2, 3]]);
+
+        let e = System::exclusive("e", |_| ()).after("c").after("d");
+        schedule.add_system("update", e).unwrap();
+        assert_eq!(batches(&mut schedule), vec![vec![0, 1], vec![2, 3], vec![4]]);
+
+        schedule.add_system("update", system("f", Access::new()).after("e").after("missing")).unwrap();
+        match *schedule.build().unwrap_err().kind() {
+            ErrorKind::UnknownSystem(ref system, ref other) => assert_eq!((&**system, &**other), ("f", "missing")),
+            ref kind => panic!("unexpected error {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn cycles_are_rejected() {
+        let mut schedule = Schedule::with_threads(1);
+        schedule.add_stage("update").unwrap();
+        schedule.add_system("update", system("a", Access::new()).after("b")).unwrap();
+        schedule.add_system("update", system("b", Access::new()).after("a")).unwrap();
+        match *schedule.run(&mut Scene::new()).unwrap_err().kind() {
+            ErrorKind::Cycle(ref stage) => assert_eq!(stage, "update"),
+            ref kind => panic!("unexpected error {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn stages_and_ordered_systems_run_in_order() {
+        let mut scene = Scene::new();
+        scene.insert_global(Steps(Vec::new()));
+        let mut schedule = Schedule::with_threads(4);
+        schedule.add_stage("first").unwrap();
+        schedule.add_stage("second").unwrap();
+        let step = |name: &'static str| System::new(name, Access::new().write::<Steps>(), move |scene| {
+            scene.global_mut::<Steps>().unwrap().0.push(name);
+        });
+        schedule.add_system("second", step("c")).unwrap();
+        schedule.add_system("first", step("b").after("a")).unwrap();
+        schedule.add_system("first", step("a")).unwrap();
+        schedule.run(&mut scene).unwrap();
+        assert_eq!(scene.global::<Steps>().unwrap().0, vec!["a", "b", "c"]);
+    }
+}
@@ -0,0 +1,115 @@
+use ::cgmath::{EuclideanSpace, Point3};
+use super::{Aabb, Bounds, Component, Entity, Frustum, Scene, WorldTransform};
+
+/// Finds entities by combining name, tag, component and spatial filters.
+/// An entity is selected if it passes every filter.
+///
+/// ```ignore
+/// let nearby = Select::new()
+///     .tagged("enemy")
+///     .not_tagged("dead")
+///     .with::<Light>()
+///     .within_sphere(player_position, 10.0)
+///     .entities(&scene);
+/// ```
+///
+/// Spatial filters test the world space `Bounds` of an entity, or its
+/// position if it has no bounds, and skip entities without either. Call
+/// them after `transform::propagate`.
+pub struct Select {
+    name: Option<String>,
+    tags: Vec<String>,
+    filters: Vec<Box<Fn(&Scene, Entity) -> bool>>,
+}
+
+impl Select {
+    /// Selects every entity.
+    pub fn new() -> Select {
+        Select { name: None, tags: Vec::new(), filters: Vec::new() }
+    }
+
+    pub fn named(mut self, name: &str) -> Select {
+        self.name = Some(name.to_owned());
+        self
+    }
+
+    pub fn tagged(mut self, tag: &str) -> Select {
+        self.tags.push(tag.to_owned());
+        self
+    }
+
+    pub fn not_tagged(self, tag: &str) -> Select {
+        let tag = tag.to_owned();
+        self.filter(move |scene, entity| !scene.has_tag(entity, &tag))
+    }
+
+    pub fn with<T>(self) -> Select where T: Component {
+        self.filter(|scene, entity| scene.has::<T>(entity))
+    }
+
+    pub fn without<T>(self) -> Select where T: Component {
+        self.filter(|scene, entity| !scene.has::<T>(entity))
+    }
+
+    pub fn within_aabb(self, aabb: Aabb) -> Select {
+        self.filter(move |scene, entity| world_bounds(scene, entity).map_or(false, |bounds| bounds.overlaps(&aabb)))
+    }
+
+    pub fn within_sphere(self, center: Point3<f32>, radius: f32) -> Select {
+        self.filter(move |scene, entity| world_bounds(scene, entity).map_or(false, |bounds| bounds.overlaps_sphere(center, radius)))
+    }
+
+    pub fn in_frustum(self, frustum: Frustum) -> Select {
+        self.filter(move |scene, entity| world_bounds(scene, entity).map_or(false, |bounds| frustum.intersects_aabb(&bounds)))
+    }
+
+    /// Any other condition.
+    pub fn filter<F>(mut self, filter: F) -> Select where F: Fn(&Scene, Entity) -> bool + 'static {
+        self.filters.push(Box::new(filter));
+        self
+    }
+
+    /// The selected entities, in no particular order.
+    pub fn iter<'a>(&'a self, scene: &'a Scene) -> Box<Iterator<Item = Entity> + 'a> {
+        // Start from the smallest index that applies instead of scanning
+        // every entity.
+        let indexed = self.name.iter().map(|name| scene.named(name))
+                          .chain(self.tags.iter().map(|tag| scene.tagged(tag)))
+                          .min_by_key(|entities| entities.len());
+        let candidates: Box<Iterator<Item = Entity> + 'a> = match indexed {
+            Some(entities) => Box::new(entities.into_iter()),
+            None => scene.entities(),
+        };
+
+        Box::new(candidates.filter(move |&entity| self.matches(scene, entity)))
+    }
+
+    pub fn entities(&self, scene: &Scene) -> Vec<Entity> {
+        self.iter(scene).collect()
+    }
+
+    pub fn first(&self, scene: &Scene) -> Option<Entity> {
+        self.iter(scene).next()
+    }
+
+    pub fn matches(&self, scene: &Scene, entity: Entity) -> bool {
+        scene.is_alive(entity) &&
+        self.name.as_ref().map_or(true, |name| scene.get::<super::Name>(entity).map_or(false, |other| other.as_str() == name)) &&
+        self.tags.iter().all(|tag| scene.has_tag(entity, tag)) &&
+        self.filters.iter().all(|filter| filter(scene, entity))
+    }
+}
+
+/// World space bounds of an entity, a point if it has a position but no
+/// `Bounds`.
+fn world_bounds(scene: &Scene, entity: Entity) -> Option<Aabb> {
+    match (scene.get::<Bounds>(entity), scene.get::<WorldTransform>(entity)) {
+        (Some(bounds), Some(world)) => Some(bounds.0.transform(&world.0)),
+        (Some(bounds), None) => Some(bounds.0),
+        (None, Some(world)) => {
+            let position = Point3::from_vec(world.0.w.truncate());
+            Some(Aabb::new(position, position))
+        },
+        (None, None) => None,
+    }
+}
@@ -0,0 +1,528 @@
+use ::cgmath::{Deg, Point3, Quaternion, Rad, Vector3};
+use ::resource::Resources;
+use ::serde_json::{self, Value};
+use ::std::collections::{BTreeMap, HashMap};
+use ::std::fs::File;
+use ::std::io::{Read, Write};
+use ::std::path::Path;
+use super::prefab::{PrefabInstance, PrefabPart};
+use super::{Aabb, ActiveCamera, Bounds, Camera, Component, Entity, Light, LightKind, Model, Name, Parent, Projection, Scene, Tags, Transform};
+
+error_chain! {
+    links {
+        Resource(::resource::Error, ::resource::ErrorKind);
+    }
+
+    foreign_links {
+        Io(::std::io::Error);
+        Json(::serde_json::Error);
+    }
+
+    errors {
+        UnsupportedVersion(version: u64) {
+            description("unsupported scene file version")
+            display("scene file version {} is newer than the supported version {}", version, VERSION)
+        }
+        DuplicateEntity(entity: Entity) {
+            description("entity appears twice in the scene file")
+            display("entity {:?} appears twice in the scene file", entity)
+        }
+    }
+}
+
+/// Version of the scene files written by this build.
+pub const VERSION: u64 = 1;
+
+/// Upgrades files written by older builds one version at a time:
+/// `MIGRATIONS[n]` turns a version `n + 1` file into a version `n + 2` file.
+/// Bump `VERSION` and append here whenever the format changes.
+static MIGRATIONS: &'static [fn(Value) -> Result<Value>] = &[];
+
+/// A component that can be written to and read from scene files.
+pub trait Persist: Component + Sized {
+    /// The name the component is stored under. Never change it once files
+    /// using it exist; add a migration instead.
+    fn key() -> &'static str;
+
+    fn save(&self) -> Result<Value>;
+
+    /// Rebuilds the component, loading the resources it refers to.
+    fn load(value: Value, resources: &mut Resources) -> Result<Self>;
+}
+
+#[derive(Serialize, Deserialize)]
+struct SceneFile {
+    version: u64,
+    entities: Vec<EntityFile>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct EntityFile {
+    index: u32,
+    generation: u32,
+    components: BTreeMap<String, Value>,
+}
+
+type SaveFn = fn(&Scene, Entity) -> Option<Result<Value>>;
+type LoadFn = fn(&mut Scene, Entity, Value, &mut Resources) -> Result<()>;
+type RemoveFn = fn(&mut Scene, Entity) -> bool;
+type HasFn = fn(&Scene, Entity) -> bool;
+
+/// Knows how to save and load every persistent component type.
+///
+/// Files are JSON with entities sorted by id and components sorted by key,
+/// so saving an unchanged scene produces an identical file. Entities keep
+/// their ids across a round trip, so references between them stay valid.
+/// Components that are derived from others, like `Children` and
+/// `WorldTransform`, are not stored.
+pub struct Registry {
+    savers: Vec<(&'static str, SaveFn)>,
+    loaders: HashMap<&'static str, LoadFn>,
+    removers: HashMap<&'static str, RemoveFn>,
+    checkers: HashMap<&'static str, HasFn>,
+}
+
+impl Registry {
+    /// A registry knowing about every component of the `scene` module.
+    pub fn new() -> Registry {
+        let mut registry = Registry {
+            savers: Vec::new(),
+            loaders: HashMap::new(),
+            removers: HashMap::new(),
+            checkers: HashMap::new(),
+        };
+        registry.register::<Transform>();
+        registry.register::<Parent>();
+        registry.register::<Camera>();
+        registry.register::<ActiveCamera>();
+        registry.register::<Model>();
+        registry.register::<Bounds>();
+        registry.register::<Light>();
+        registry.register::<Name>();
+        registry.register::<Tags>();
+        registry.register::<PrefabInstance>();
+        registry.register::<PrefabPart>();
+        registry
+    }
+
+    pub fn register<T>(&mut self) where T: Persist {
+        self.savers.push((T::key(), save_component::<T>));
+        self.loaders.insert(T::key(), load_component::<T>);
+        self.removers.insert(T::key(), remove_component::<T>);
+        self.checkers.insert(T::key(), has_component::<T>);
+    }
+
+    /// Whether `key` names a registered component.
+    pub fn is_registered(&self, key: &str) -> bool {
+        self.checkers.contains_key(key)
+    }
+
+    /// Whether `entity` has the component stored under `key`.
+    pub fn has_component(&self, scene: &Scene, entity: Entity, key: &str) -> bool {
+        self.checkers.get(key).map_or(false, |has| has(scene, entity))
+    }
+
+    /// Saves the component stored under `key`, if the entity has one.
+    pub fn save_component(&self, scene: &Scene, entity: Entity, key: &str) -> Option<Result<Value>> {
+        self.savers.iter()
+                   .find(|&&(saver_key, _)| saver_key == key)
+                   .and_then(|&(_, save)| save(scene, entity))
+    }
+
+    /// Loads the component stored under `key` onto `entity`, replacing any
+    /// previous one. Returns false if no component is registered for `key`.
+    pub fn load_component(&self, scene: &mut Scene, entity: Entity, key: &str, value: Value, resources: &mut Resources) -> Result<bool> {
+        match self.loaders.get(key) {
+            Some(load) => {
+                try!(load(scene, entity, value, resources));
+                Ok(true)
+            },
+            None => Ok(false),
+        }
+    }
+
+    /// Removes the component stored under `key` from `entity`. Returns false
+    /// if it had none or no component is registered for `key`.
+    pub fn remove_component(&self, scene: &mut Scene, entity: Entity, key: &str) -> bool {
+        self.removers.get(key).map_or(false, |remove| remove(scene, entity))
+    }
+
+    pub fn save(&self, scene: &Scene) -> Result<String> {
+        let mut entities = scene.entities().collect::<Vec<_>>();
+        entities.sort();
+
+        let mut file = SceneFile {
+            version: VERSION,
+            entities: Vec::with_capacity(entities.len()),
+        };
+        for entity in entities {
+            let mut components = BTreeMap::new();
+            for &(key, save) in self.savers.iter() {
+                if let Some(value) = save(scene, entity) {
+                    components.insert(key.to_owned(), try!(value));
+                }
+            }
+
+            file.entities.push(EntityFile {
+                index: entity.index(),
+                generation: entity.generation(),
+                components: components,
+            });
+        }
+
+        Ok(try!(serde_json::to_string_pretty(&file)))
+    }
+
+    /// Builds a new scene from the contents of a scene file, migrating it
+    /// first if it was written by an older build.
+    pub fn load(&self, contents: &str, resources: &mut Resources) -> Result<Scene> {
+        let value = try!(migrate(try!(serde_json::from_str(contents))));
+        let file: SceneFile = try!(serde_json::from_value(value));
+
+        let mut scene = Scene::new();
+        for entity in file.entities.iter() {
+            let handle = Entity::from_raw(entity.index, entity.generation);
+            if !scene.spawn_at(handle) {
+                return Err(ErrorKind::DuplicateEntity(handle).into());
+            }
+        }
+
+        for entity in file.entities {
+            let handle = Entity::from_raw(entity.index, entity.generation);
+            for (key, value) in entity.components {
+                if !try!(self.load_component(&mut scene, handle, &key, value, resources)) {
+                    warn!("Skipping unknown component '{}' of entity {:?}", key, handle);
+                }
+            }
+        }
+
+        // Only parents are stored. Rebuild the children lists from them.
+        let parents = scene.query::<&Parent>().map(|(entity, parent)| (entity, parent.0)).collect::<Vec<_>>();
+        for (entity, parent) in parents {
+            scene.remove::<Parent>(entity);
+            if !scene.set_parent(entity, Some(parent)) {
+                warn!("Entity {:?} has an invalid parent {:?}", entity, parent);
+            }
+        }
+
+        Ok(scene)
+    }
+
+    pub fn save_file<P>(&self, scene: &Scene, path: P) -> Result<()> where P: AsRef<Path> {
+        let contents = try!(self.save(scene));
+        let mut file = try!(File::create(path));
+        try!(file.write_all(contents.as_bytes()));
+        Ok(())
+    }
+
+    pub fn load_file<P>(&self, path: P, resources: &mut Resources) -> Result<Scene> where P: AsRef<Path> {
+        let mut contents = String::new();
+        try!(try!(File::open(path)).read_to_string(&mut contents));
+        self.load(&contents, resources)
+    }
+}
+
+fn save_component<T>(scene: &Scene, entity: Entity) -> Option<Result<Value>> where T: Persist {
+    scene.get::<T>(entity).map(T::save)
+}
+
+fn load_component<T>(scene: &mut Scene, entity: Entity, value: Value, resources: &mut Resources) -> Result<()> where T: Persist {
+    let component = try!(T::load(value, resources));
+    scene.insert(entity, component);
+    Ok(())
+}
+
+fn remove_component<T>(scene: &mut Scene, entity: Entity) -> bool where T: Persist {
+    scene.remove::<T>(entity).is_some()
+}
+
+fn has_component<T>(scene: &Scene, entity: Entity) -> bool where T: Persist {
+    scene.has::<T>(entity)
+}
+
+/// Brings a parsed scene file up to `VERSION`.
+fn migrate(mut value: Value) -> Result<Value> {
+    let mut version = value.get("version").and_then(Value::as_u64).unwrap_or(1);
+    if version > VERSION {
+        return Err(ErrorKind::UnsupportedVersion(version).into());
+    }
+
+    while version < VERSION {
+        value = try!(MIGRATIONS[version as usize - 1](value));
+        version += 1;
+        if let Some(object) = value.as_object_mut() {
+            object.insert("version".to_owned(), Value::from(version));
+        }
+    }
+    Ok(value)
+}
+
+#[derive(Serialize, Deserialize)]
+struct TransformData {
+    translation: [f32; 3],
+    /// x, y, z, w
+    rotation: [f32; 4],
+    scale: [f32; 3],
+}
+
+impl Persist for Transform {
+    fn key() -> &'static str {
+        "transform"
+    }
+
+    fn save(&self) -> Result<Value> {
+        let (t, r, s) = (self.translation(), self.rotation(), self.scale());
+        Ok(try!(serde_json::to_value(TransformData {
+            translation: [t.x, t.y, t.z],
+            rotation: [r.v.x, r.v.y, r.v.z, r.s],
+            scale: [s.x, s.y, s.z],
+        })))
+    }
+
+    fn load(value: Value, _: &mut Resources) -> Result<Transform> {
+        let data: TransformData = try!(serde_json::from_value(value));
+        let (t, r, s) = (data.translation, data.rotation, data.scale);
+        Ok(Transform::from_parts(Vector3::new(t[0], t[1], t[2]),
+                                 Quaternion::new(r[3], r[0], r[1], r[2]),
+                                 Vector3::new(s[0], s[1], s[2])))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct EntityData {
+    index: u32,
+    generation: u32,
+}
+
+impl Persist for Parent {
+    fn key() -> &'static str {
+        "parent"
+    }
+
+    fn save(&self) -> Result<Value> {
+        Ok(try!(serde_json::to_value(EntityData { index: self.0.index(), generation: self.0.generation() })))
+    }
+
+    fn load(value: Value, _: &mut Resources) -> Result<Parent> {
+        let data: EntityData = try!(serde_json::from_value(value));
+        Ok(Parent(Entity::from_raw(data.index, data.generation)))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum CameraData {
+    /// `fov` is in degrees, which is friendlier to edit by hand.
+    Perspective { fov: f32, near: f32, far: f32 },
+    Orthographic { height: f32, near: f32, far: f32 },
+}
+
+impl Persist for Camera {
+    fn key() -> &'static str {
+        "camera"
+    }
+
+    fn save(&self) -> Result<Value> {
+        let data = match self.projection {
+            Projection::Perspective { fov, near, far } => CameraData::Perspective { fov: Deg::from(fov).0, near: near, far: far },
+            Projection::Orthographic { height, near, far } => CameraData::Orthographic { height: height, near: near, far: far },
+        };
+        Ok(try!(serde_json::to_value(data)))
+    }
+
+    fn load(value: Value, _: &mut Resources) -> Result<Camera> {
+        Ok(match try!(serde_json::from_value(value)) {
+            CameraData::Perspective { fov, near, far } => Camera::perspective(Rad::from(Deg(fov)), near, far),
+            CameraData::Orthographic { height, near, far } => Camera::orthographic(height, near, far),
+        })
+    }
+}
+
+impl Persist for ActiveCamera {
+    fn key() -> &'static str {
+        "active_camera"
+    }
+
+    fn save(&self) -> Result<Value> {
+        Ok(Value::Bool(true))
+    }
+
+    fn load(_: Value, _: &mut Resources) -> Result<ActiveCamera> {
+        Ok(ActiveCamera)
+    }
+}
+
+impl Persist for Model {
+    fn key() -> &'static str {
+        "model"
+    }
+
+    /// Only the resource path is stored. The mesh is loaded again on load.
+    fn save(&self) -> Result<Value> {
+        Ok(Value::String(self.path.clone()))
+    }
+
+    fn load(value: Value, resources: &mut Resources) -> Result<Model> {
+        let path: String = try!(serde_json::from_value(value));
+        let mesh = try!(resources.mesh(&path));
+        Ok(Model { path: path, mesh: mesh })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct BoundsData {
+    min: [f32; 3],
+    max: [f32; 3],
+}
+
+impl Persist for Bounds {
+    fn key() -> &'static str {
+        "bounds"
+    }
+
+    fn save(&self) -> Result<Value> {
+        let (min, max) = (self.0.min, self.0.max);
+        Ok(try!(serde_json::to_value(BoundsData {
+            min: [min.x, min.y, min.z],
+            max: [max.x, max.y, max.z],
+        })))
+    }
+
+    fn load(value: Value, _: &mut Resources) -> Result<Bounds> {
+        let data: BoundsData = try!(serde_json::from_value(value));
+        let (min, max) = (data.min, data.max);
+        Ok(Bounds(Aabb::new(Point3::new(min[0], min[1], min[2]), Point3::new(max[0], max[1], max[2]))))
+    }
+}
+
+impl Persist for Name {
+    fn key() -> &'static str {
+        "name"
+    }
+
+    fn save(&self) -> Result<Value> {
+        Ok(Value::String(self.as_str().to_owned()))
+    }
+
+    fn load(value: Value, _: &mut Resources) -> Result<Name> {
+        let name: String = try!(serde_json::from_value(value));
+        Ok(Name::new(name))
+    }
+}
+
+impl Persist for Tags {
+    fn key() -> &'static str {
+        "tags"
+    }
+
+    fn save(&self) -> Result<Value> {
+        Ok(try!(serde_json::to_value(self.iter().collect::<Vec<_>>())))
+    }
+
+    fn load(value: Value, _: &mut Resources) -> Result<Tags> {
+        let tags: Vec<String> = try!(serde_json::from_value(value));
+        Ok(tags.into_iter().fold(Tags::new(), |tags, tag| tags.with(tag)))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum LightKindData {
+    Ambient,
+    Directional,
+    Point { range: f32 },
+    /// Angles are in degrees.
+    Spot { range: f32, inner: f32, outer: f32 },
+}
+
+#[derive(Serialize, Deserialize)]
+struct LightData {
+    #[serde(flatten)]
+    kind: LightKindData,
+    color: [f32; 3],
+    intensity: f32,
+}
+
+impl Persist for Light {
+    fn key() -> &'static str {
+        "light"
+    }
+
+    fn save(&self) -> Result<Value> {
+        let kind = match self.kind {
+            LightKind::Ambient => LightKindData::Ambient,
+            LightKind::Directional => LightKindData::Directional,
+            LightKind::Point { range } => LightKindData::Point { range: range },
+            LightKind::Spot { range, inner, outer } => LightKindData::Spot {
+                range: range,
+                inner: Deg::from(inner).0,
+                outer: Deg::from(outer).0,
+            },
+        };
+        let c = self.color;
+        Ok(try!(serde_json::to_value(LightData { kind: kind, color: [c.x, c.y, c.z], intensity: self.intensity })))
+    }
+
+    fn load(value: Value, _: &mut Resources) -> Result<Light> {
+        let data: LightData = try!(serde_json::from_value(value));
+        let kind = match data.kind {
+            LightKindData::Ambient => LightKind::Ambient,
+            LightKindData::Directional => LightKind::Directional,
+            LightKindData::Point { range } => LightKind::Point { range: range },
+            LightKindData::Spot { range, inner, outer } => LightKind::Spot {
+                range: range,
+                inner: Rad::from(Deg(inner)),
+                outer: Rad::from(Deg(outer)),
+            },
+        };
+        let c = data.color;
+        Ok(Light { kind: kind, color: Vector3::new(c[0], c[1], c[2]), intensity: data.intensity })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::cgmath::Vector3;
+    use ::resource::Resources;
+    use super::Registry;
+    use super::super::{Name, Scene, Transform};
+
+    #[test]
+    fn round_trip_keeps_entity_ids_and_parents() {
+        let mut scene = Scene::new();
+        let root = scene.spawn();
+        let dead = scene.spawn();
+        let child = scene.spawn();
+        scene.despawn(dead);
+        // Reuses the slot of `dead` with a newer generation.
+        let grandchild = scene.spawn();
+        assert_eq!(grandchild.index(), dead.index());
+
+        let mut transform = Transform::new();
+        transform.set_translation(Vector3::new(1.0, 2.0, 3.0));
+        scene.insert(child, transform);
+        scene.insert(root, Name::new("root"));
+        scene.set_parent(child, Some(root));
+        scene.set_parent(grandchild, Some(child));
+
+        let registry = Registry::new();
+        let saved = registry.save(&scene).unwrap();
+        let mut loaded = registry.load(&saved, &mut Resources::new()).unwrap();
+
+        assert_eq!(loaded.len(), 3);
+        assert!(loaded.is_alive(root) && loaded.is_alive(child) && loaded.is_alive(grandchild));
+        assert!(!loaded.is_alive(dead));
+        assert_eq!(loaded.parent(child), Some(root));
+        assert_eq!(loaded.parent(grandchild), Some(child));
+        assert_eq!(loaded.children(root), &[child]);
+        assert_eq!(loaded.children(child), &[grandchild]);
+        assert_eq!(loaded.get::<Transform>(child).unwrap().translation(), Vector3::new(1.0, 2.0, 3.0));
+        assert_eq!(loaded.named("root"), vec![root]);
+
+        // New entities don't take the ids of loaded ones.
+        let spawned = loaded.spawn();
+        assert!(spawned != root && spawned != child && spawned != grandchild);
+        loaded.despawn(spawned);
+        assert_eq!(registry.save(&loaded).unwrap(), saved);
+    }
+}
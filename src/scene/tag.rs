@@ -0,0 +1,304 @@
+use ::std::any::{Any, TypeId};
+use ::std::collections::{BTreeSet, HashMap};
+use super::{Archetype, Component, Entity, Scene};
+
+/// A human readable name, e.g. "player". Several entities may share a name.
+///
+/// Rename entities with `Scene::insert`. Names changed through `get_mut` or
+/// a query also work, but make the next lookup compare every name with the
+/// index to find them.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Name(String);
+
+impl Name {
+    pub fn new<S>(name: S) -> Name where S: Into<String> {
+        Name(name.into())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Labels grouping entities, e.g. "enemy". Change them with `Scene::add_tag`
+/// and `Scene::remove_tag`, which keep lookups by tag up to date without
+/// having to look for changes.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Tags(BTreeSet<String>);
+
+impl Tags {
+    pub fn new() -> Tags {
+        Tags::default()
+    }
+
+    /// The same tags plus `tag`, for building them before inserting.
+    pub fn with<S>(mut self, tag: S) -> Tags where S: Into<String> {
+        self.0.insert(tag.into());
+        self
+    }
+
+    pub fn has(&self, tag: &str) -> bool {
+        self.0.contains(tag)
+    }
+
+    /// The tags in alphabetical order.
+    pub fn iter<'a>(&'a self) -> Box<Iterator<Item = &'a str> + 'a> {
+        Box::new(self.0.iter().map(|tag| tag.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+/// Which entities carry each name and tag. Kept up to date by `Scene`
+/// whenever a `Name` or `Tags` component is inserted or removed, and caught
+/// up after they may have been changed in place.
+pub struct Index {
+    names: HashMap<String, Vec<Entity>>,
+    tags: HashMap<String, Vec<Entity>>,
+    /// What each entity is indexed under, to find its entries again and to
+    /// tell which ones were changed in place.
+    entity_names: HashMap<Entity, String>,
+    entity_tags: HashMap<Entity, BTreeSet<String>>,
+}
+
+impl Index {
+    pub fn new() -> Index {
+        Index {
+            names: HashMap::new(),
+            tags: HashMap::new(),
+            entity_names: HashMap::new(),
+            entity_tags: HashMap::new(),
+        }
+    }
+
+    /// Whether components of the type are indexed.
+    pub fn indexes(ty: TypeId) -> bool {
+        ty == TypeId::of::<Name>() || ty == TypeId::of::<Tags>()
+    }
+
+    /// Records that the entity's component of type `T` is now `current`,
+    /// or that it has none.
+    pub fn update<T>(&mut self, entity: Entity, current: Option<&T>) where T: Component {
+        let current = current.map(|current| current as &Any);
+        if TypeId::of::<T>() == TypeId::of::<Name>() {
+            self.set_name(entity, current.and_then(|current| current.downcast_ref::<Name>()));
+        } else if TypeId::of::<T>() == TypeId::of::<Tags>() {
+            self.set_tags(entity, current.and_then(|current| current.downcast_ref::<Tags>()));
+        }
+    }
+
+    /// Records the indexed components of a row that was added to the scene.
+    pub fn add_row(&mut self, archetype: &Archetype, row: usize) {
+        let entity = archetype.entities()[row];
+        if let Some(names) = archetype.column::<Name>() {
+            self.set_name(entity, Some(&names[row]));
+        }
+        if let Some(tags) = archetype.column::<Tags>() {
+            self.set_tags(entity, Some(&tags[row]));
+        }
+    }
+
+    /// Forgets the indexed components of a row about to leave the scene.
+    pub fn remove_row(&mut self, archetype: &Archetype, row: usize) {
+        let entity = archetype.entities()[row];
+        self.set_name(entity, None);
+        self.set_tags(entity, None);
+    }
+
+    /// Catches up with names and tags changed in place, re-indexing only the
+    /// entities whose values differ from what they are indexed under.
+    pub fn sync(&mut self, archetypes: &[Archetype]) {
+        for archetype in archetypes {
+            if let Some(names) = archetype.column::<Name>() {
+                for (&entity, name) in archetype.entities().iter().zip(names.iter()) {
+                    if self.entity_names.get(&entity) != Some(&name.0) {
+                        self.set_name(entity, Some(name));
+                    }
+                }
+            }
+            if let Some(tags) = archetype.column::<Tags>() {
+                for (&entity, tags) in archetype.entities().iter().zip(tags.iter()) {
+                    if self.entity_tags.get(&entity) != Some(&tags.0) {
+                        self.set_tags(entity, Some(tags));
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn named(&self, name: &str) -> &[Entity] {
+        self.names.get(name).map(|entities| &entities[..]).unwrap_or(&[])
+    }
+
+    pub fn tagged(&self, tag: &str) -> &[Entity] {
+        self.tags.get(tag).map(|entities| &entities[..]).unwrap_or(&[])
+    }
+
+    fn set_name(&mut self, entity: Entity, name: Option<&Name>) {
+        if let Some(previous) = self.entity_names.remove(&entity) {
+            unlink(&mut self.names, &previous, entity);
+        }
+        if let Some(name) = name {
+            self.names.entry(name.0.clone()).or_insert_with(Vec::new).push(entity);
+            self.entity_names.insert(entity, name.0.clone());
+        }
+    }
+
+    fn set_tags(&mut self, entity: Entity, tags: Option<&Tags>) {
+        if let Some(previous) = self.entity_tags.remove(&entity) {
+            for tag in previous.iter() {
+                unlink(&mut self.tags, tag, entity);
+            }
+        }
+        if let Some(tags) = tags {
+            for tag in tags.0.iter() {
+                self.tags.entry(tag.clone()).or_insert_with(Vec::new).push(entity);
+            }
+            self.entity_tags.insert(entity, tags.0.clone());
+        }
+    }
+}
+
+fn unlink(index: &mut HashMap<String, Vec<Entity>>, key: &str, entity: Entity) {
+    let empty = match index.get_mut(key) {
+        Some(entities) => {
+            entities.retain(|&other| other != entity);
+            entities.is_empty()
+        },
+        None => false,
+    };
+    if empty {
+        index.remove(key);
+    }
+}
+
+impl Scene {
+    /// Every entity with the name, in no particular order.
+    pub fn named(&self, name: &str) -> Vec<Entity> {
+        self.index().named(name).to_vec()
+    }
+
+    /// Some entity with the name, for names used only once like "player".
+    pub fn find_named(&self, name: &str) -> Option<Entity> {
+        self.index().named(name).first().cloned()
+    }
+
+    /// Every entity with the tag, in no particular order.
+    pub fn tagged(&self, tag: &str) -> Vec<Entity> {
+        self.index().tagged(tag).to_vec()
+    }
+
+    pub fn has_tag(&self, entity: Entity, tag: &str) -> bool {
+        self.get::<Tags>(entity).map_or(false, |tags| tags.has(tag))
+    }
+
+    /// Returns false if the entity is dead or already had the tag.
+    pub fn add_tag(&mut self, entity: Entity, tag: &str) -> bool {
+        if !self.is_alive(entity) || self.has_tag(entity, tag) {
+            return false;
+        }
+
+        let tags = self.get::<Tags>(entity).cloned().unwrap_or_default().with(tag);
+        self.insert(entity, tags);
+        true
+    }
+
+    /// Returns false if the entity did not have the tag. The `Tags`
+    /// component is removed along with the last tag.
+    pub fn remove_tag(&mut self, entity: Entity, tag: &str) -> bool {
+        if !self.has_tag(entity, tag) {
+            return false;
+        }
+
+        let mut tags = self.get::<Tags>(entity).cloned().unwrap_or_default();
+        tags.0.remove(tag);
+        if tags.0.is_empty() {
+            self.remove::<Tags>(entity);
+        } else {
+            self.insert(entity, tags);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Name, Tags};
+    use super::super::{Entity, Scene};
+
+    fn sorted(mut entities: Vec<Entity>) -> Vec<Entity> {
+        entities.sort();
+        entities
+    }
+
+    #[test]
+    fn lookups_follow_inserts_removes_and_despawns() {
+        let mut scene = Scene::new();
+        let a = scene.spawn();
+        let b = scene.spawn();
+        scene.insert(a, Name::new("crate"));
+        scene.insert(b, Name::new("crate"));
+        assert!(scene.add_tag(a, "enemy"));
+        assert!(!scene.add_tag(a, "enemy"));
+        assert!(scene.add_tag(b, "enemy"));
+        assert_eq!(sorted(scene.named("crate")), vec![a, b]);
+
+        scene.insert(a, Name::new("barrel"));
+        assert_eq!(scene.named("crate"), vec![b]);
+        assert_eq!(scene.find_named("barrel"), Some(a));
+        assert!(scene.remove_tag(b, "enemy"));
+        assert!(!scene.has::<Tags>(b));
+        assert_eq!(scene.tagged("enemy"), vec![a]);
+
+        let detached = scene.take(a).unwrap();
+        assert_eq!(scene.named("barrel"), vec![]);
+        assert_eq!(scene.tagged("enemy"), vec![]);
+        assert!(scene.restore(detached));
+        assert_eq!(scene.tagged("enemy"), vec![a]);
+
+        scene.despawn(a);
+        scene.remove::<Name>(b);
+        assert_eq!(scene.named("barrel"), vec![]);
+        assert_eq!(scene.named("crate"), vec![]);
+        assert_eq!(scene.tagged("enemy"), vec![]);
+    }
+
+    #[test]
+    fn lookups_see_names_and_tags_changed_in_place() {
+        let mut scene = Scene::new();
+        let a = scene.spawn();
+        let b = scene.spawn();
+        scene.insert(a, Name::new("crate"));
+        scene.insert(b, Name::new("crate"));
+        scene.insert(a, Tags::new().with("enemy").with("heavy"));
+
+        scene.get_mut::<Name>(a).unwrap().0 = "barrel".to_owned();
+        assert_eq!(scene.named("crate"), vec![b]);
+        assert_eq!(scene.named("barrel"), vec![a]);
+
+        for (_, tags) in scene.query::<&mut Tags>() {
+            tags.0.remove("enemy");
+            tags.0.insert("friend".to_owned());
+        }
+        assert_eq!(scene.tagged("enemy"), vec![]);
+        assert_eq!(scene.tagged("heavy"), vec![a]);
+        assert_eq!(scene.tagged("friend"), vec![a]);
+
+        // Edits that end up where they started change nothing.
+        {
+            let name = scene.get_mut::<Name>(b).unwrap();
+            name.0 = "other".to_owned();
+            name.0 = "crate".to_owned();
+        }
+        assert_eq!(scene.named("crate"), vec![b]);
+
+        // Moving rows between archetypes after an edit keeps the index right.
+        scene.get_mut::<Name>(b).unwrap().0 = "box".to_owned();
+        scene.insert(b, Tags::new().with("heavy"));
+        assert_eq!(scene.named("crate"), vec![]);
+        assert_eq!(scene.named("box"), vec![b]);
+        assert_eq!(sorted(scene.tagged("heavy")), vec![a, b]);
+    }
+}
@@ -0,0 +1,266 @@
+use ::cgmath::{EuclideanSpace, InnerSpace, Matrix3, Matrix4, One, Point3, Quaternion, SquareMatrix, Vector3};
+use ::std::collections::HashSet;
+use super::{Entity, Scene};
+
+/// Position, orientation and size of an entity relative to its parent, or to
+/// the world if it has none.
+///
+/// The setters mark the transform dirty so `propagate` knows which subtrees
+/// need their world matrices recomputed.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Transform {
+    translation: Vector3<f32>,
+    rotation: Quaternion<f32>,
+    scale: Vector3<f32>,
+    dirty: bool,
+}
+
+impl Transform {
+    /// The identity transform.
+    pub fn new() -> Transform {
+        Transform {
+            translation: Vector3::new(0.0, 0.0, 0.0),
+            rotation: Quaternion::one(),
+            scale: Vector3::new(1.0, 1.0, 1.0),
+            dirty: true,
+        }
+    }
+
+    pub fn from_parts(translation: Vector3<f32>, rotation: Quaternion<f32>, scale: Vector3<f32>) -> Transform {
+        Transform {
+            translation: translation,
+            rotation: rotation,
+            scale: scale,
+            dirty: true,
+        }
+    }
+
+    /// A transform placed at `eye` whose -Z axis points at `target`, which is
+    /// how cameras look.
+    pub fn looking_at(eye: Point3<f32>, target: Point3<f32>, up: Vector3<f32>) -> Transform {
+        let forward = (target - eye).normalize();
+        let side = forward.cross(up).normalize();
+        let up = side.cross(forward);
+        let rotation = Quaternion::from(Matrix3::from_cols(side, up, -forward));
+        Transform::from_parts(eye.to_vec(), rotation, Vector3::new(1.0, 1.0, 1.0))
+    }
+
+    pub fn translation(&self) -> Vector3<f32> {
+        self.translation
+    }
+
+    pub fn rotation(&self) -> Quaternion<f32> {
+        self.rotation
+    }
+
+    pub fn scale(&self) -> Vector3<f32> {
+        self.scale
+    }
+
+    pub fn set_translation(&mut self, translation: Vector3<f32>) {
+        self.translation = translation;
+        self.dirty = true;
+    }
+
+    pub fn set_rotation(&mut self, rotation: Quaternion<f32>) {
+        self.rotation = rotation;
+        self.dirty = true;
+    }
+
+    pub fn set_scale(&mut self, scale: Vector3<f32>) {
+        self.scale = scale;
+        self.dirty = true;
+    }
+
+    /// Forces the world matrix of this entity and its descendants to be
+    /// recomputed on the next `propagate`.
+    pub fn mark_dirty(&mut self) {
+        self.dirty = true;
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// The local matrix: scale first, then rotate, then translate.
+    pub fn matrix(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.translation) *
+        Matrix4::from(self.rotation) *
+        Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+}
+
+/// The world matrix of an entity, written by `propagate`. This is what ends
+/// up in the `world` uniform of the vertex shader.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct WorldTransform(pub Matrix4<f32>);
+
+/// The `WorldTransform` an entity had on the previous simulation tick, kept
+/// by `snapshot` so rendering can interpolate between ticks.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PreviousWorldTransform(pub Matrix4<f32>);
+
+/// The entity this one is attached to. Managed by `Scene::set_parent`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Parent(pub Entity);
+
+/// The entities attached to this one. Managed by `Scene::set_parent`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Children(pub Vec<Entity>);
+
+impl Scene {
+    /// Attaches `child` to `parent`, or makes it a root when `parent` is
+    /// `None`. Returns false and leaves the hierarchy untouched if either
+    /// entity is dead or the change would create a cycle.
+    pub fn set_parent(&mut self, child: Entity, parent: Option<Entity>) -> bool {
+        if !self.is_alive(child) {
+            return false;
+        }
+        if let Some(parent) = parent {
+            if !self.is_alive(parent) || self.is_ancestor(child, parent) {
+                return false;
+            }
+        }
+
+        if let Some(Parent(old)) = self.remove::<Parent>(child) {
+            if let Some(children) = self.get_mut::<Children>(old) {
+                children.0.retain(|&other| other != child);
+            }
+        }
+
+        if let Some(parent) = parent {
+            self.insert(child, Parent(parent));
+            let mut children = self.remove::<Children>(parent).unwrap_or(Children(Vec::new()));
+            children.0.push(child);
+            self.insert(parent, children);
+        }
+
+        moved(self, child);
+        true
+    }
+
+    pub fn parent(&self, entity: Entity) -> Option<Entity> {
+        self.get::<Parent>(entity).map(|parent| parent.0)
+    }
+
+    pub fn children(&self, entity: Entity) -> &[Entity] {
+        match self.get::<Children>(entity) {
+            Some(children) => &children.0,
+            None => &[],
+        }
+    }
+
+    /// Whether `ancestor` is `entity` or one of its parents.
+    pub fn is_ancestor(&self, ancestor: Entity, entity: Entity) -> bool {
+        let mut current = Some(entity);
+        while let Some(entity) = current {
+            if entity == ancestor {
+                return true;
+            }
+            current = self.parent(entity);
+        }
+        false
+    }
+}
+
+/// Cuts the entity out of the hierarchy before it is despawned. Its children
+/// become roots.
+pub fn detach(scene: &mut Scene, entity: Entity) {
+    scene.set_parent(entity, None);
+
+    if let Some(Children(children)) = scene.remove::<Children>(entity) {
+        for child in children {
+            scene.remove::<Parent>(child);
+            moved(scene, child);
+        }
+    }
+}
+
+/// Makes `propagate` recompute the world matrix of an entity that moved in
+/// the hierarchy, and those of its descendants.
+fn moved(scene: &mut Scene, entity: Entity) {
+    let marked = match scene.get_mut::<Transform>(entity) {
+        Some(transform) => {
+            transform.mark_dirty();
+            true
+        },
+        None => false,
+    };
+    if !marked {
+        scene.remove::<WorldTransform>(entity);
+    }
+}
+
+/// Recomputes the `WorldTransform` of every entity whose transform, or the
+/// transform of one of its ancestors, changed since the last call. Only
+/// those subtrees are visited.
+///
+/// Entities without a `Transform` that have a parent or children pass their
+/// parent's world matrix through, or the identity if they are roots.
+pub fn propagate(scene: &mut Scene) {
+    let mut dirty = scene.query::<&Transform>()
+                         .filter(|&(_, transform)| transform.dirty)
+                         .map(|(entity, _)| entity)
+                         .collect::<Vec<_>>();
+    dirty.extend(scene.entities().filter(|&entity| {
+        !scene.has::<Transform>(entity) && !scene.has::<WorldTransform>(entity) &&
+        (scene.has::<Parent>(entity) || scene.has::<Children>(entity))
+    }).collect::<Vec<_>>());
+
+    // Subtrees under another dirty entity are recomputed along with it.
+    let set = dirty.iter().cloned().collect::<HashSet<_>>();
+    let roots = dirty.into_iter()
+                     .filter(|&entity| {
+                         let mut current = scene.parent(entity);
+                         while let Some(ancestor) = current {
+                             if set.contains(&ancestor) {
+                                 return false;
+                             }
+                             current = scene.parent(ancestor);
+                         }
+                         true
+                     })
+                     .collect::<Vec<_>>();
+
+    for root in roots {
+        let parent_world = scene.parent(root)
+                                .and_then(|parent| scene.get::<WorldTransform>(parent))
+                                .map_or(Matrix4::identity(), |world| world.0);
+        let mut stack = vec![(root, parent_world)];
+        while let Some((entity, parent_world)) = stack.pop() {
+            let world = match scene.get_mut::<Transform>(entity) {
+                Some(transform) => {
+                    transform.dirty = false;
+                    parent_world * transform.matrix()
+                },
+                None => parent_world,
+            };
+            scene.insert(entity, WorldTransform(world));
+
+            for &child in scene.children(entity) {
+                stack.push((child, world));
+            }
+        }
+    }
+}
+
+/// Remembers the current world matrices as the previous ones. Call at the
+/// start of every simulation tick, before anything moves.
+pub fn snapshot(scene: &mut Scene) {
+    let worlds = scene.query::<&WorldTransform>()
+                      .map(|(entity, world)| (entity, world.0))
+                      .collect::<Vec<_>>();
+    for (entity, world) in worlds {
+        scene.insert(entity, PreviousWorldTransform(world));
+    }
+}
+
+/// The world matrix `alpha` of the way from the previous tick to the
+/// current one. Matrices are blended element by element, which is close
+/// enough for the motion of a single tick.
+pub fn interpolate(previous: Option<&PreviousWorldTransform>, current: &WorldTransform, alpha: f32) -> Matrix4<f32> {
+    match previous {
+        Some(previous) => previous.0 + (current.0 - previous.0) * alpha,
+        None => current.0,
+    }
+}
@@ -0,0 +1,443 @@
+//! Gameplay logic written in Rhai scripts.
+//!
+//! A script runs its top level statements once when loaded, then gets its
+//! functions called as things happen:
+//!
+//! ```rhai
+//! fn init() { this.speed = 2.0; }
+//! fn update(dt) {
+//!     for e in query(["transform", "light"]) {
+//!         let t = get(e, "transform");
+//!         t.translation[1] += this.speed * dt;
+//!         set(e, "transform", t);
+//!     }
+//! }
+//! fn input(event) { if event.kind == "key" && event.key == "Space" { this.speed = -this.speed; } }
+//! ```
+//!
+//! Every callback is optional. `this` is a map private to the script that
+//! survives hot reloads. Components are read and written as maps in the same
+//! shape as in scene files, through the `scene::serialize::Registry`.
+//!
+//! Scripts are sandboxed: they can only `import` other scripts from the
+//! scripts directory, `eval` is disabled, and each call is cut off after a
+//! fixed number of operations so a runaway loop cannot hang the game.
+
+use ::resource::Resources;
+use ::rhai::{self, Array, CallFnOptions, Dynamic, Engine, EvalAltResult, Map, Position, Scope, AST};
+use ::rhai::module_resolvers::FileModuleResolver;
+use ::scene::{Entity, Parent, Scene};
+use ::scene::serialize::{Persist, Registry};
+use ::std::cell::Cell;
+use ::std::fs::{self, File};
+use ::std::io::Read;
+use ::std::path::{Component, Path, PathBuf};
+use ::std::rc::Rc;
+use ::std::time::SystemTime;
+
+error_chain! {
+    foreign_links {
+        Io(::std::io::Error);
+    }
+
+    errors {
+        OutsideSandbox(path: String) {
+            description("script path outside the scripts directory")
+            display("script '{}' is outside the scripts directory", path)
+        }
+        Parse(path: String, message: String) {
+            description("script failed to compile")
+            display("could not compile script '{}': {}", path, message)
+        }
+        Runtime(path: String, message: String) {
+            description("script failed to run")
+            display("script '{}' failed: {}", path, message)
+        }
+    }
+}
+
+/// Operations a single callback may run before it is aborted.
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+/// The scene and resources scripts work on. Only set while a callback runs.
+type Context = Rc<Cell<Option<(*mut Scene, *mut Resources)>>>;
+
+struct Script {
+    /// Relative to the scripts directory.
+    path: String,
+    ast: AST,
+    scope: Scope<'static>,
+    this: Dynamic,
+    modified: Option<SystemTime>,
+}
+
+/// Loads scripts from a directory and runs their callbacks.
+pub struct Scripts {
+    engine: Engine,
+    root: PathBuf,
+    scripts: Vec<Script>,
+    context: Context,
+}
+
+impl Scripts {
+    /// Scripts will be loaded from, and may only import from, `root`.
+    pub fn new<P>(root: P) -> Scripts where P: AsRef<Path> {
+        let root = root.as_ref().to_path_buf();
+        let context: Context = Rc::new(Cell::new(None));
+
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS)
+              .set_max_call_levels(64)
+              .set_max_expr_depths(64, 32)
+              .disable_symbol("eval")
+              .on_print(|text| info!("[script] {}", text))
+              .on_debug(|text, source, position| debug!("[script {}:{}] {}", source.unwrap_or("?"), position, text));
+
+        let mut resolver = FileModuleResolver::new_with_path_and_extension(root.clone(), "rhai");
+        // Imported scripts are read again on every import, so edits show up
+        // after a hot reload of the importing script.
+        resolver.enable_cache(false);
+        engine.set_module_resolver(Sandbox { inner: resolver });
+
+        register(&mut engine, &context, Rc::new(Registry::new()));
+
+        Scripts {
+            engine: engine,
+            root: root,
+            scripts: Vec::new(),
+            context: context,
+        }
+    }
+
+    /// Loads the script at `path`, relative to the scripts directory, runs
+    /// its top level statements and calls its `init`.
+    pub fn load(&mut self, scene: &mut Scene, resources: &mut Resources, path: &str) -> Result<()> {
+        let (ast, modified) = try!(self.compile(path));
+        let mut script = Script {
+            path: path.to_owned(),
+            ast: ast,
+            scope: Scope::new(),
+            this: Dynamic::from(Map::new()),
+            modified: modified,
+        };
+        try!(self.start(scene, resources, &mut script, true));
+        self.scripts.push(script);
+        Ok(())
+    }
+
+    /// Calls `update(dt)` on every script. `dt` is in seconds.
+    pub fn update(&mut self, scene: &mut Scene, resources: &mut Resources, dt: f64) {
+        self.dispatch(scene, resources, "update", Dynamic::from(dt));
+    }
+
+    /// Calls `input(event)` on every script. See `input_event` for the shape
+    /// of events.
+    pub fn input(&mut self, scene: &mut Scene, resources: &mut Resources, event: Map) {
+        self.dispatch(scene, resources, "input", Dynamic::from(event));
+    }
+
+    /// Reloads scripts whose files changed since they were loaded, keeping
+    /// their `this`. A script that no longer compiles keeps running its
+    /// previous version. Returns the paths that were reloaded.
+    pub fn poll(&mut self, scene: &mut Scene, resources: &mut Resources) -> Vec<String> {
+        let mut reloaded = Vec::new();
+        for i in 0..self.scripts.len() {
+            let path = self.scripts[i].path.clone();
+            if modified(&self.root.join(&path)) == self.scripts[i].modified {
+                continue;
+            }
+
+            match self.compile(&path) {
+                Ok((ast, modified)) => {
+                    let mut script = Script {
+                        path: path.clone(),
+                        ast: ast,
+                        scope: Scope::new(),
+                        this: self.scripts[i].this.clone(),
+                        modified: modified,
+                    };
+                    match self.start(scene, resources, &mut script, false) {
+                        Ok(()) => info!("Reloaded script '{}'", path),
+                        Err(e) => error!("{}", e),
+                    }
+                    self.scripts[i] = script;
+                    reloaded.push(path);
+                },
+                Err(e) => {
+                    error!("{}", e);
+                    // Don't retry until the file changes again.
+                    self.scripts[i].modified = modified(&self.root.join(&path));
+                }
+            }
+        }
+        reloaded
+    }
+
+    fn compile(&self, path: &str) -> Result<(AST, Option<SystemTime>)> {
+        if !is_sandboxed(path) {
+            return Err(ErrorKind::OutsideSandbox(path.to_owned()).into());
+        }
+
+        let full_path = self.root.join(path);
+        let mut source = String::new();
+        try!(try!(File::open(&full_path)).read_to_string(&mut source));
+        let mut ast = try!(self.engine.compile(&source).map_err(|e| ErrorKind::Parse(path.to_owned(), e.to_string())));
+        ast.set_source(path);
+        Ok((ast, modified(&full_path)))
+    }
+
+    /// Runs the top level statements of a script, then its `init` unless it
+    /// is being reloaded.
+    fn start(&self, scene: &mut Scene, resources: &mut Resources, script: &mut Script, init: bool) -> Result<()> {
+        let _bound = Bind::new(&self.context, scene, resources);
+
+        try!(self.engine.run_ast_with_scope(&mut script.scope, &script.ast)
+                        .map_err(|e| ErrorKind::Runtime(script.path.clone(), e.to_string())));
+        if init && has_fn(&script.ast, "init", 0) {
+            try!(call(&self.engine, script, "init", Vec::new())
+                     .map_err(|e| ErrorKind::Runtime(script.path.clone(), e.to_string())));
+        }
+        Ok(())
+    }
+
+    /// Calls a one argument callback on every script that defines it. A
+    /// failing script is reported and does not stop the others.
+    fn dispatch(&mut self, scene: &mut Scene, resources: &mut Resources, name: &str, argument: Dynamic) {
+        let _bound = Bind::new(&self.context, scene, resources);
+
+        for script in self.scripts.iter_mut() {
+            if !has_fn(&script.ast, name, 1) {
+                continue;
+            }
+            if let Err(e) = call(&self.engine, script, name, vec![argument.clone()]) {
+                error!("{}", Error::from(ErrorKind::Runtime(script.path.clone(), e.to_string())));
+            }
+        }
+    }
+}
+
+/// Turns a window event into the map handed to the `input` callback, or
+/// `None` for events scripts don't see.
+///
+/// * `#{ kind: "key", key: "W", pressed: true }`
+/// * `#{ kind: "button", button: "Left", pressed: false }`
+/// * `#{ kind: "cursor", x: 10, y: 20 }`
+pub fn input_event(event: &::winit::Event) -> Option<Map> {
+    use ::winit::{ElementState, Event};
+
+    let mut map = Map::new();
+    match *event {
+        Event::KeyboardInput(state, _, Some(key)) => {
+            map.insert("kind".into(), "key".into());
+            map.insert("key".into(), format!("{:?}", key).into());
+            map.insert("pressed".into(), (state == ElementState::Pressed).into());
+        },
+        Event::MouseInput(state, button) => {
+            map.insert("kind".into(), "button".into());
+            map.insert("button".into(), format!("{:?}", button).into());
+            map.insert("pressed".into(), (state == ElementState::Pressed).into());
+        },
+        Event::MouseMoved(x, y) => {
+            map.insert("kind".into(), "cursor".into());
+            map.insert("x".into(), (x as rhai::INT).into());
+            map.insert("y".into(), (y as rhai::INT).into());
+        },
+        _ => return None,
+    }
+    Some(map)
+}
+
+/// Points the context at the scene for as long as it lives.
+struct Bind<'a> {
+    context: &'a Context,
+}
+
+impl<'a> Bind<'a> {
+    fn new(context: &'a Context, scene: &mut Scene, resources: &mut Resources) -> Bind<'a> {
+        context.set(Some((scene as *mut Scene, resources as *mut Resources)));
+        Bind { context: context }
+    }
+}
+
+impl<'a> Drop for Bind<'a> {
+    fn drop(&mut self) {
+        self.context.set(None);
+    }
+}
+
+/// Rejects `import`s that could reach outside the scripts directory.
+struct Sandbox {
+    inner: FileModuleResolver,
+}
+
+impl rhai::ModuleResolver for Sandbox {
+    fn resolve(&self, engine: &Engine, source: Option<&str>, path: &str, position: Position)
+               -> ::std::result::Result<rhai::Shared<rhai::Module>, Box<EvalAltResult>> {
+        if !is_sandboxed(path) {
+            return Err(Box::new(EvalAltResult::ErrorModuleNotFound(path.to_owned(), position)));
+        }
+        self.inner.resolve(engine, source, path, position)
+    }
+}
+
+/// Whether a relative script path stays inside the directory it is
+/// relative to.
+fn is_sandboxed(path: &str) -> bool {
+    Path::new(path).components().all(|component| match component {
+        Component::Normal(_) | Component::CurDir => true,
+        _ => false,
+    })
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+fn has_fn(ast: &AST, name: &str, arity: usize) -> bool {
+    ast.iter_functions().any(|f| f.name == name && f.params.len() == arity)
+}
+
+fn call(engine: &Engine, script: &mut Script, name: &str, arguments: Vec<Dynamic>) -> ::std::result::Result<Dynamic, Box<EvalAltResult>> {
+    let options = CallFnOptions::new().eval_ast(false).rewind_scope(true).bind_this_ptr(&mut script.this);
+    engine.call_fn_with_options(options, &mut script.scope, &script.ast, name, arguments)
+}
+
+type ScriptResult<T> = ::std::result::Result<T, Box<EvalAltResult>>;
+
+fn script_error<T>(message: String) -> ScriptResult<T> {
+    Err(Box::new(EvalAltResult::ErrorRuntime(message.into(), Position::NONE)))
+}
+
+/// Runs `f` on the bound scene.
+fn with_scene<F, T>(context: &Context, f: F) -> ScriptResult<T> where F: FnOnce(&mut Scene, &mut Resources) -> ScriptResult<T> {
+    match context.get() {
+        // The pointers are only set by `Bind`, which borrows both mutably for
+        // as long as they are set, and scripts run on the calling thread.
+        Some((scene, resources)) => unsafe { f(&mut *scene, &mut *resources) },
+        None => script_error("the scene is only available inside script callbacks".to_owned()),
+    }
+}
+
+/// Registers the `Entity` type and the scene functions.
+fn register(engine: &mut Engine, context: &Context, registry: Rc<Registry>) {
+    engine.register_type_with_name::<Entity>("Entity")
+          .register_get("index", |e: &mut Entity| e.index() as rhai::INT)
+          .register_get("generation", |e: &mut Entity| e.generation() as rhai::INT)
+          .register_fn("to_string", |e: &mut Entity| format!("{:?}", e))
+          .register_fn("to_debug", |e: &mut Entity| format!("{:?}", e))
+          .register_fn("==", |a: Entity, b: Entity| a == b)
+          .register_fn("!=", |a: Entity, b: Entity| a != b);
+
+    let ctx = context.clone();
+    engine.register_fn("spawn_entity", move || with_scene(&ctx, |scene, _| Ok(scene.spawn())));
+
+    let ctx = context.clone();
+    engine.register_fn("despawn_entity", move |entity: Entity| with_scene(&ctx, |scene, _| Ok(scene.despawn(entity))));
+
+    let ctx = context.clone();
+    engine.register_fn("is_alive", move |entity: Entity| with_scene(&ctx, |scene, _| Ok(scene.is_alive(entity))));
+
+    let ctx = context.clone();
+    let reg = registry.clone();
+    engine.register_fn("has", move |entity: Entity, key: &str| {
+        with_scene(&ctx, |scene, _| Ok(reg.has_component(scene, entity, key)))
+    });
+
+    // Entities having every component in `keys`.
+    let ctx = context.clone();
+    let reg = registry.clone();
+    engine.register_fn("query", move |keys: Array| {
+        let keys = try!(keys.into_iter()
+                            .map(|key| key.into_string().or_else(|ty| script_error(format!("component keys are strings, not {}", ty))))
+                            .collect::<ScriptResult<Vec<String>>>());
+        with_scene(&ctx, |scene, _| {
+            Ok(scene.entities()
+                    .filter(|&entity| keys.iter().all(|key| reg.has_component(scene, entity, key)))
+                    .map(Dynamic::from)
+                    .collect::<Array>())
+        })
+    });
+
+    // The component as a map, or `()` if the entity has none.
+    let ctx = context.clone();
+    let reg = registry.clone();
+    engine.register_fn("get", move |entity: Entity, key: &str| {
+        with_scene(&ctx, |scene, _| match reg.save_component(scene, entity, key) {
+            Some(Ok(value)) => rhai::serde::to_dynamic(&value),
+            Some(Err(e)) => script_error(e.to_string()),
+            None => Ok(Dynamic::UNIT),
+        })
+    });
+
+    let ctx = context.clone();
+    let reg = registry.clone();
+    engine.register_fn("set", move |entity: Entity, key: &str, value: Dynamic| {
+        if key == Parent::key() {
+            return script_error("use set_parent to change the parent of an entity".to_owned());
+        }
+        let value = try!(rhai::serde::from_dynamic::<::serde_json::Value>(&value));
+        with_scene(&ctx, |scene, resources| {
+            if !scene.is_alive(entity) {
+                return script_error(format!("entity {:?} is dead", entity));
+            }
+            match reg.load_component(scene, entity, key, value, resources) {
+                Ok(true) => Ok(()),
+                Ok(false) => script_error(format!("unknown component '{}'", key)),
+                Err(e) => script_error(e.to_string()),
+            }
+        })
+    });
+
+    let ctx = context.clone();
+    let reg = registry.clone();
+    engine.register_fn("remove", move |entity: Entity, key: &str| {
+        with_scene(&ctx, |scene, _| Ok(reg.remove_component(scene, entity, key)))
+    });
+
+    // The parent of the entity, or `()` for roots.
+    let ctx = context.clone();
+    engine.register_fn("parent", move |entity: Entity| {
+        with_scene(&ctx, |scene, _| Ok(scene.parent(entity).map_or(Dynamic::UNIT, Dynamic::from)))
+    });
+
+    let ctx = context.clone();
+    engine.register_fn("children", move |entity: Entity| {
+        with_scene(&ctx, |scene, _| Ok(scene.children(entity).iter().cloned().map(Dynamic::from).collect::<Array>()))
+    });
+
+    let ctx = context.clone();
+    engine.register_fn("set_parent", move |entity: Entity, parent: Entity| {
+        with_scene(&ctx, |scene, _| Ok(scene.set_parent(entity, Some(parent))))
+    });
+
+    let ctx = context.clone();
+    engine.register_fn("clear_parent", move |entity: Entity| {
+        with_scene(&ctx, |scene, _| Ok(scene.set_parent(entity, None)))
+    });
+
+    // Some entity with the name, or `()`.
+    let ctx = context.clone();
+    engine.register_fn("find_named", move |name: &str| {
+        with_scene(&ctx, |scene, _| Ok(scene.find_named(name).map_or(Dynamic::UNIT, Dynamic::from)))
+    });
+
+    let ctx = context.clone();
+    engine.register_fn("tagged", move |tag: &str| {
+        with_scene(&ctx, |scene, _| Ok(scene.tagged(tag).into_iter().map(Dynamic::from).collect::<Array>()))
+    });
+
+    let ctx = context.clone();
+    engine.register_fn("has_tag", move |entity: Entity, tag: &str| {
+        with_scene(&ctx, |scene, _| Ok(scene.has_tag(entity, tag)))
+    });
+
+    let ctx = context.clone();
+    engine.register_fn("add_tag", move |entity: Entity, tag: &str| {
+        with_scene(&ctx, |scene, _| Ok(scene.add_tag(entity, tag)))
+    });
+
+    let ctx = context.clone();
+    engine.register_fn("remove_tag", move |entity: Entity, tag: &str| {
+        with_scene(&ctx, |scene, _| Ok(scene.remove_tag(entity, tag)))
+    });
+}